@@ -1,3 +1,5 @@
+use crate::intcode::solver::{Constraint, Linear, Solver, Verdict};
+use crate::intcode::symbolic::{End, SymbolicEmulator};
 use crate::intcode::IntCodeEmulator;
 
 const INPUT: &str = include_str!("../input/2019/day2.txt");
@@ -16,19 +18,30 @@ pub fn part1() -> i64 {
 }
 
 pub fn part2() -> i64 {
-    let input = parse_input();
+    // rather than trying every noun and verb, work out what the program computes from them and
+    // solve for the output
+    let mut vm = SymbolicEmulator::new(parse_input());
+    let noun = vm.make_symbolic(1);
+    let verb = vm.make_symbolic(2);
 
-    for noun in 0..=99 {
-        for verb in 0..=99 {
-            let output = run(&input, noun, verb);
+    let path = vm
+        .explore(1, 10_000)
+        .pop()
+        .filter(|path| path.end == End::Halted)
+        .expect("Unable to run IntCode VM");
+    let output = path.cell(0);
+    let output = output.as_linear().expect("Output isn't linear");
 
-            if output == 19_690_720 {
-                return 100 * noun + verb;
-            }
-        }
-    }
+    let mut solver = Solver::new();
+    solver.bound(noun, 0, 99);
+    solver.bound(verb, 0, 99);
+    let target = Constraint::equal(output, &Linear::constant(19_690_720));
+    solver.assert(target.expect("Output overflows"));
 
-    panic!("Correct noun/verb combo not found");
+    match solver.solve() {
+        Verdict::Satisfiable(model) => 100 * model[noun] + model[verb],
+        _ => panic!("Correct noun/verb combo not found"),
+    }
 }
 
 fn run(input: &[i64], noun: i64, verb: i64) -> i64 {
@@ -37,7 +50,7 @@ fn run(input: &[i64], noun: i64, verb: i64) -> i64 {
     program[2] = verb;
 
     let mut vm = IntCodeEmulator::new(program);
-    vm.execute();
+    vm.execute().expect("Unable to run IntCode VM");
 
     vm.ram()[0]
 }
@@ -6,7 +6,7 @@ pub fn part1() -> i64 {
     let mut vm = IntCodeEmulator::from_input(INPUT);
     vm.stdin().push_back(1);
 
-    vm.execute();
+    vm.execute().expect("Unable to run IntCode VM");
 
     let result = vm
         .stdout()
@@ -20,7 +20,7 @@ pub fn part2() -> i64 {
     let mut vm = IntCodeEmulator::from_input(INPUT);
     vm.stdin().push_back(5);
 
-    vm.execute();
+    vm.execute().expect("Unable to run IntCode VM");
 
     let result = vm
         .stdout()
@@ -1,68 +1,26 @@
-use crate::intcode::{IntCodeEmulator, YieldReason};
-use itertools::Itertools;
-use std::collections::VecDeque;
+use crate::intcode::topology::Topology;
+use crate::intcode::IntCodeEmulator;
 
 const INPUT: &str = include_str!("../input/2019/day7.txt");
 
 pub fn part1() -> i64 {
     let program = IntCodeEmulator::parse_input(INPUT);
-    let permutations = (0..5).permutations(5);
 
-    permutations
-        .map(|p| run_in_series(&program, &p))
-        .max()
+    Topology::chain(5)
+        .optimise_permutations(&program, &[0, 1, 2, 3, 4])
         .expect("Unable to run IntCode VMs")
+        .expect("No output produced")
+        .signal
 }
 
 pub fn part2() -> i64 {
     let program = IntCodeEmulator::parse_input(INPUT);
-    let permutations = (5..10).permutations(5);
 
-    permutations
-        .map(|p| run_feedback_loop(&program, &p))
-        .max()
+    Topology::ring(5)
+        .optimise_permutations(&program, &[5, 6, 7, 8, 9])
         .expect("Unable to run IntCode VMs")
-}
-
-/// runs intcode VMs with the given IDs in series and returns the output from the final one
-fn run_in_series(program: &[i64], ids: &[i64]) -> i64 {
-    ids.iter().fold(0, |current, &id| {
-        let mut vm = IntCodeEmulator::new(program.to_vec());
-        vm.stdin().push_back(id);
-        vm.stdin().push_back(current);
-
-        vm.execute();
-
-        vm.stdout().pop_back().expect("No output produced")
-    })
-}
-
-/// runs intcode VMs with the given IDs in a feedback loop until none require any more input
-/// then returns the final result
-fn run_feedback_loop(program: &[i64], ids: &[i64]) -> i64 {
-    let mut waiting: VecDeque<IntCodeEmulator> = ids
-        .iter()
-        .map(|&id| {
-            let mut vm = IntCodeEmulator::new(program.to_vec());
-            vm.stdin().push_back(id);
-            vm
-        })
-        .collect();
-
-    let mut value = 0;
-
-    while let Some(mut vm) = waiting.pop_front() {
-        vm.stdin().push_back(value);
-
-        let result = vm.execute_until_yield();
-        value = vm.stdout().pop_back().expect("No output produced");
-
-        if let YieldReason::InputRequired = result {
-            waiting.push_back(vm);
-        }
-    }
-
-    value
+        .expect("No output produced")
+        .signal
 }
 
 #[cfg(test)]
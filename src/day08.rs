@@ -70,7 +70,7 @@ mod tests {
     #[test]
     fn day08_part2() {
         // ZPZUB
-        let expected = vec![
+        let expected = [
             "#### ###  #### #  # ###  ",
             "   # #  #    # #  # #  # ",
             "  #  #  #   #  #  # ###  ",
@@ -6,7 +6,7 @@ pub fn part1() -> i64 {
     let mut vm = IntCodeEmulator::from_input(INPUT);
     vm.stdin().push_back(1);
 
-    vm.execute();
+    vm.execute().expect("Unable to run IntCode VM");
 
     if *vm.stdout().back().unwrap() == 0 {
         panic!("Failed with output: {:?}", vm.stdout());
@@ -19,7 +19,7 @@ pub fn part2() -> i64 {
     let mut vm = IntCodeEmulator::from_input(INPUT);
     vm.stdin().push_back(2);
 
-    vm.execute();
+    vm.execute().expect("Unable to run IntCode VM");
 
     vm.stdout().pop_back().expect("No output produced")
 }
@@ -1,4 +1,4 @@
-use crate::intcode::{IntCodeEmulator, YieldReason};
+use crate::intcode::{IntCodeEmulator, IoDevice};
 use crate::points::{Bearing, Direction, Point2D};
 use std::collections::HashMap;
 
@@ -40,51 +40,69 @@ pub fn part2() -> String {
     chars.into_iter().collect()
 }
 
-fn run_program(starting_colour: Colour) -> HashMap<Point2D, Colour> {
-    let mut vm = IntCodeEmulator::from_input(INPUT);
-
-    let mut position = Point2D::zero();
-    let mut bearing = Bearing::North;
-
-    let mut colours = HashMap::new();
-    colours.insert(position, starting_colour);
+/// The hull painting robot, which is attached to the VM as its IO device
+struct Robot {
+    position: Point2D,
+    bearing: Bearing,
+    colours: HashMap<Point2D, Colour>,
 
-    loop {
-        let colour = colours.entry(position).or_insert(Colour::Black);
-        match colour {
-            Colour::Black => vm.stdin().push_back(0),
-            Colour::White => vm.stdin().push_back(1),
-        };
+    /// the colour to paint, if it's been received and the robot is waiting for a direction
+    paint: Option<Colour>,
+}
 
-        // tell the VM about the current square
-        let result = vm.execute_until_yield();
-        if result == YieldReason::Halted {
-            break;
+impl IoDevice for Robot {
+    /// tells the VM about the current square
+    fn input(&mut self) -> Option<i64> {
+        match self.colours.get(&self.position) {
+            Some(Colour::White) => Some(1),
+            _ => Some(0),
         }
+    }
 
-        // paint the square
-        let paint = vm.stdout().pop_front().expect("No colour available");
-        let paint = match paint {
-            0 => Colour::Black,
-            1 => Colour::White,
-            _ => panic!("Unexpected colour: {}", paint),
+    /// paints the square, then moves to the next one
+    fn output(&mut self, value: i64) {
+        let paint = match self.paint.take() {
+            Some(paint) => paint,
+            None => {
+                self.paint = Some(match value {
+                    0 => Colour::Black,
+                    1 => Colour::White,
+                    _ => panic!("Unexpected colour: {}", value),
+                });
+                return;
+            }
         };
 
-        colours.insert(position, paint);
+        self.colours.insert(self.position, paint);
 
-        // move to the next square
-        let direction = vm.stdout().pop_front().expect("No direction available");
-        let direction = match direction {
+        let direction = match value {
             0 => Direction::Left,
             1 => Direction::Right,
-            _ => panic!("Unexpected direction: {}", direction),
+            _ => panic!("Unexpected direction: {}", value),
         };
 
-        bearing = bearing.turn(&direction);
-        position = position.move_bearing(&bearing);
+        self.bearing = self.bearing.turn(&direction);
+        self.position = self.position.move_bearing(&self.bearing);
     }
+}
+
+fn run_program(starting_colour: Colour) -> HashMap<Point2D, Colour> {
+    let position = Point2D::zero();
+
+    let mut colours = HashMap::new();
+    colours.insert(position, starting_colour);
+
+    let robot = Robot {
+        position,
+        bearing: Bearing::North,
+        colours,
+        paint: None,
+    };
+
+    let mut vm = IntCodeEmulator::from_input(INPUT).with_device(robot);
+    vm.execute().expect("Unable to run IntCode VM");
 
-    colours
+    vm.into_device().colours
 }
 
 #[cfg(test)]
@@ -98,7 +116,7 @@ mod tests {
 
     #[test]
     fn day11_part2() {
-        let expected = vec![
+        let expected = [
             "  ██  ███  ████ █  █ ████  ██  ████  ██ ",
             " █  █ █  █ █    █ █     █ █  █ █    █  █",
             " █  █ ███  ███  ██     █  █    ███  █   ",
@@ -1,35 +1,111 @@
+pub mod ascii;
+pub mod assembler;
+mod cache;
+pub mod compiler;
+pub mod debugger;
+pub mod decompiler;
+pub mod device;
+pub mod dialect;
+pub mod disassembler;
+mod error;
+pub mod fuzz;
+pub mod graph;
+pub mod history;
+pub mod memory;
+pub mod network;
+pub mod optimiser;
+pub mod profiler;
+mod snapshot;
+pub mod solver;
+pub mod symbolic;
+pub mod terminal;
+pub mod threaded;
+pub mod topology;
+pub mod trace;
+
+pub use self::device::{IoDevice, QueueDevice};
+pub use self::error::{ErrorKind, IntCodeError};
+pub use self::memory::{Cells, Memory, MemoryBackend};
+pub use self::trace::Observer;
+
+use self::cache::DecodeCache;
+use self::dialect::{Decoded, Dialect};
+
 use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// how many instructions to execute between checks of the deadline, since reading the clock costs
+/// more than executing most instructions
+const DEADLINE_INTERVAL: u32 = 1024;
 
-#[derive(Debug)]
-enum StepResult {
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StepResult {
     Continue,
     InputRequired,
     Halted,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum YieldReason {
     InputRequired,
     Halted,
+
+    /// the instruction budget ran out, and execution can continue once it's been topped up
+    BudgetExhausted,
+
+    /// the deadline passed, and execution can continue once it's been extended or removed
+    DeadlineExceeded,
+}
+
+/// How closely a VM sticks to the rules, for checking what a program relies on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Strictness {
+    /// the instruction set from before day 9, so relative mode and `AdjustBase` are rejected
+    Legacy,
+
+    /// reads beyond the end of memory fail rather than returning 0, as does executing any cell
+    /// which wasn't part of the program as it was loaded
+    Strict,
+
+    /// anything goes, which is the default
+    #[default]
+    Permissive,
 }
 
-#[derive(Debug)]
-pub struct IntCodeEmulator {
-    ram: Vec<i64>,
+/// An IntCode VM, which performs its input and output through a device. By default input and
+/// output are buffered in queues, which are accessed with `stdin` and `stdout`.
+#[derive(Debug, Clone)]
+pub struct IntCodeEmulator<D = QueueDevice> {
+    ram: Memory,
     pointer: usize,
     base: i64,
-    stdin: VecDeque<i64>,
-    stdout: VecDeque<i64>,
+    device: D,
+    cache: Option<DecodeCache>,
+    budget: Option<u64>,
+    deadline: Option<Instant>,
+    dialect: Option<Arc<Dialect>>,
+    strictness: Strictness,
+
+    /// the length of the program as it was loaded, beyond which memory only holds data
+    image: usize,
 }
 
 impl IntCodeEmulator {
     pub fn new(program: Vec<i64>) -> IntCodeEmulator {
+        let image = program.len();
+
         IntCodeEmulator {
-            ram: program,
+            ram: Memory::from(program),
             pointer: 0,
             base: 0,
-            stdin: VecDeque::new(),
-            stdout: VecDeque::new(),
+            device: QueueDevice::default(),
+            cache: None,
+            budget: None,
+            deadline: None,
+            dialect: None,
+            strictness: Strictness::Permissive,
+            image,
         }
     }
 
@@ -46,122 +122,436 @@ impl IntCodeEmulator {
             .collect()
     }
 
-    pub fn ram(&self) -> &Vec<i64> {
+    pub fn stdin(&mut self) -> &mut VecDeque<i64> {
+        &mut self.device.input
+    }
+
+    pub fn stdout(&mut self) -> &mut VecDeque<i64> {
+        &mut self.device.output
+    }
+}
+
+impl<D: IoDevice> IntCodeEmulator<D> {
+    /// replaces the device, keeping the rest of the VM's state
+    pub fn with_device<E: IoDevice>(self, device: E) -> IntCodeEmulator<E> {
+        IntCodeEmulator {
+            ram: self.ram,
+            pointer: self.pointer,
+            base: self.base,
+            device,
+            cache: self.cache,
+            budget: self.budget,
+            deadline: self.deadline,
+            dialect: self.dialect,
+            strictness: self.strictness,
+            image: self.image,
+        }
+    }
+
+    pub fn device(&self) -> &D {
+        &self.device
+    }
+
+    pub fn device_mut(&mut self) -> &mut D {
+        &mut self.device
+    }
+
+    pub fn into_device(self) -> D {
+        self.device
+    }
+
+    pub fn ram(&self) -> &Memory {
         &self.ram
     }
 
-    pub fn stdin(&mut self) -> &mut VecDeque<i64> {
-        &mut self.stdin
+    /// moves memory into the given backend. Memory is dense by default, but paged memory is better
+    /// suited to programs which write to very large addresses
+    pub fn set_memory_backend(&mut self, backend: MemoryBackend) {
+        self.ram.set_backend(backend);
+
+        // the decode cache is indexed by address, so would be just as large as dense memory
+        if backend == MemoryBackend::Paged {
+            self.cache = None;
+        }
     }
 
-    pub fn stdout(&mut self) -> &mut VecDeque<i64> {
-        &mut self.stdout
+    /// limits the number of cells memory can allocate, so that a program writing to a huge address
+    /// fails with `ErrorKind::MemoryLimit` rather than exhausting the host's memory
+    pub fn set_memory_limit(&mut self, limit: Option<usize>) {
+        self.ram.set_limit(limit);
+    }
+
+    pub fn pointer(&self) -> usize {
+        self.pointer
+    }
+
+    pub fn set_pointer(&mut self, pointer: usize) {
+        self.pointer = pointer;
+    }
+
+    pub fn base(&self) -> i64 {
+        self.base
+    }
+
+    pub fn set_base(&mut self, base: i64) {
+        self.base = base;
+    }
+
+    /// reads a memory cell, treating anything outside of memory as 0
+    pub fn peek(&self, address: usize) -> i64 {
+        self.ram.read(address)
+    }
+
+    /// writes a memory cell, growing memory if required. The memory limit doesn't apply
+    pub fn poke(&mut self, address: usize, value: i64) {
+        self.ram.set(address, value);
+
+        if let Some(cache) = &mut self.cache {
+            cache.invalidate(address);
+        }
+    }
+
+    /// caches decoded instructions so that code which runs repeatedly is only decoded once.
+    ///
+    /// Cached instructions are invalidated whenever a write touches one of their cells, so
+    /// self-modifying programs still behave correctly. Clones of the VM share a copy of the cache,
+    /// so it's worth calling `predecode` on a template VM which is cloned many times.
+    pub fn enable_decode_cache(&mut self) {
+        if self.cache.is_none() {
+            self.cache = Some(DecodeCache::default());
+        }
+    }
+
+    pub fn disable_decode_cache(&mut self) {
+        self.cache = None;
+    }
+
+    /// decodes every instruction in memory up front, enabling the decode cache if required
+    pub fn predecode(&mut self) {
+        self.cache
+            .get_or_insert_with(DecodeCache::default)
+            .predecode(&self.ram);
+    }
+
+    /// the number of instructions which can still be executed, if there's a limit
+    pub fn instruction_budget(&self) -> Option<u64> {
+        self.budget
+    }
+
+    /// limits how many more instructions can be executed before `execute_until_yield` returns
+    /// `YieldReason::BudgetExhausted`. Every instruction executed counts against the budget, including
+    /// single steps, but only the execute methods stop when it runs out
+    pub fn set_instruction_budget(&mut self, budget: Option<u64>) {
+        self.budget = budget;
+    }
+
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// sets a time after which `execute_until_yield` returns `YieldReason::DeadlineExceeded`. The
+    /// clock is only checked every so often, so execution may overrun the deadline slightly
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+    }
+
+    /// sets the deadline to the given time from now
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.deadline = Some(Instant::now() + timeout);
+    }
+
+    pub fn dialect(&self) -> Option<&Dialect> {
+        self.dialect.as_deref()
+    }
+
+    /// restricts the VM to a dialect's opcodes and modes, or extends it with custom opcodes. Without
+    /// one the VM understands the full day 9 instruction set. Custom instructions are always
+    /// interpreted, even by the compiler
+    pub fn set_dialect(&mut self, dialect: Option<Dialect>) {
+        self.dialect = dialect.map(Arc::new);
+    }
+
+    pub fn strictness(&self) -> Strictness {
+        self.strictness
+    }
+
+    /// sets how closely the VM sticks to the rules. Anything stricter than permissive is always
+    /// interpreted, even by the compiler
+    pub fn set_strictness(&mut self, strictness: Strictness) {
+        self.strictness = strictness;
     }
 
-    pub fn execute(&mut self) {
+    pub fn execute(&mut self) -> Result<(), IntCodeError> {
+        self.execute_with(&mut ())
+    }
+
+    /// executes the program until it halts, notifying the observer of everything it does
+    pub fn execute_with<O>(&mut self, observer: &mut O) -> Result<(), IntCodeError>
+    where
+        O: Observer + ?Sized,
+    {
+        let kind = match self.execute_until_yield_with(observer)? {
+            YieldReason::Halted => return Ok(()),
+            YieldReason::InputRequired => ErrorKind::InputRequired,
+            YieldReason::BudgetExhausted => ErrorKind::BudgetExhausted,
+            YieldReason::DeadlineExceeded => ErrorKind::DeadlineExceeded,
+        };
+
+        Err(self.error(kind))
+    }
+
+    pub fn execute_until_yield(&mut self) -> Result<YieldReason, IntCodeError> {
+        self.execute_until_yield_with(&mut ())
+    }
+
+    /// executes the program until it halts or needs input, notifying the observer of everything it does
+    pub fn execute_until_yield_with<O>(
+        &mut self,
+        observer: &mut O,
+    ) -> Result<YieldReason, IntCodeError>
+    where
+        O: Observer + ?Sized,
+    {
+        let mut executed: u32 = 0;
+
         loop {
-            match self.step() {
+            if let Some(reason) = self.limit_reached(executed) {
+                return Ok(reason);
+            }
+            executed = executed.wrapping_add(1);
+
+            match self.step_with(observer)? {
                 StepResult::Continue => continue,
-                StepResult::InputRequired => panic!("Input required but none received"),
-                StepResult::Halted => break,
+                StepResult::InputRequired => return Ok(YieldReason::InputRequired),
+                StepResult::Halted => return Ok(YieldReason::Halted),
             }
         }
     }
 
-    pub fn execute_until_yield(&mut self) -> YieldReason {
-        loop {
-            match self.step() {
-                StepResult::Continue => continue,
-                StepResult::InputRequired => return YieldReason::InputRequired,
-                StepResult::Halted => return YieldReason::Halted,
+    /// checks whether the budget has run out or the deadline has passed, given the number of
+    /// instructions executed so far by the current call
+    fn limit_reached(&self, executed: u32) -> Option<YieldReason> {
+        if self.budget == Some(0) {
+            return Some(YieldReason::BudgetExhausted);
+        }
+
+        match self.deadline {
+            Some(deadline)
+                if executed.is_multiple_of(DEADLINE_INTERVAL) && Instant::now() >= deadline =>
+            {
+                Some(YieldReason::DeadlineExceeded)
             }
+            _ => None,
+        }
+    }
+
+    /// counts executed instructions against the budget
+    fn spend(&mut self, instructions: u64) {
+        if let Some(budget) = &mut self.budget {
+            *budget = budget.saturating_sub(instructions);
         }
     }
 
-    fn step(&mut self) -> StepResult {
+    pub fn step(&mut self) -> Result<StepResult, IntCodeError> {
+        self.step_with(&mut ())
+    }
+
+    /// executes a single instruction, notifying the observer of everything it does
+    pub fn step_with<O>(&mut self, observer: &mut O) -> Result<StepResult, IntCodeError>
+    where
+        O: Observer + ?Sized,
+    {
+        let result = self.try_step(observer).map_err(|kind| self.error(kind))?;
+
+        if result == StepResult::Continue {
+            self.spend(1);
+        }
+
+        Ok(result)
+    }
+
+    fn try_step<O>(&mut self, observer: &mut O) -> Result<StepResult, ErrorKind>
+    where
+        O: Observer + ?Sized,
+    {
+        let strict = self.strictness == Strictness::Strict;
+        if strict && self.pointer >= self.image {
+            return Err(ErrorKind::ExecutedData {
+                address: self.pointer,
+            });
+        }
+
+        if let Some(dialect) = &self.dialect {
+            let opcode = self
+                .ram
+                .get(self.pointer)
+                .ok_or(ErrorKind::TruncatedInstruction)?;
+
+            if let Decoded::Custom(custom) = dialect.decode(opcode)? {
+                return self.execute_custom(&custom, observer);
+            }
+        }
+
         let program = &mut self.ram;
         let base = &mut self.base;
-        let instruction = Instruction::parse(program, self.pointer);
+        let instruction = match &mut self.cache {
+            Some(cache) => cache.decode(program, self.pointer)?,
+            None => Instruction::parse(program, self.pointer)?,
+        };
+
+        if let Some(dialect) = &self.dialect {
+            dialect.check_standard(program.read(self.pointer))?;
+        }
+
+        match self.strictness {
+            Strictness::Legacy => check_legacy(program.read(self.pointer), &instruction)?,
+            Strictness::Strict if self.pointer + instruction.size() > self.image => {
+                return Err(ErrorKind::ExecutedData {
+                    address: self.image,
+                })
+            }
+            _ => {}
+        }
         let steps = instruction.steps();
 
-        match instruction {
-            Instruction::Add(left, right, dest) => dest.write(
-                program,
-                *base,
-                left.read(program, *base) + right.read(program, *base),
-            ),
+        // an input which has to wait doesn't execute, so the observer only hears about it once
+        // there's a value for it
+        let input = match instruction {
+            Instruction::Input(_) => match self.device.input() {
+                None => return Ok(StepResult::InputRequired),
+                input => input,
+            },
+            _ => None,
+        };
 
-            Instruction::Multiply(left, right, dest) => dest.write(
-                program,
-                *base,
-                left.read(program, *base) * right.read(program, *base),
-            ),
+        observer.instruction(self.pointer, *base, &instruction);
 
-            Instruction::Input(dest) => {
-                let input = match self.stdin.pop_front() {
-                    None => return StepResult::InputRequired,
-                    Some(v) => v,
-                };
+        let written = match instruction {
+            Instruction::Add(left, right, dest) => {
+                let value = left
+                    .read(program, *base, strict, observer)?
+                    .wrapping_add(right.read(program, *base, strict, observer)?);
+                Some(dest.write(program, *base, value, observer)?)
+            }
 
-                dest.write(program, *base, input);
+            Instruction::Multiply(left, right, dest) => {
+                let value = left
+                    .read(program, *base, strict, observer)?
+                    .wrapping_mul(right.read(program, *base, strict, observer)?);
+                Some(dest.write(program, *base, value, observer)?)
+            }
+
+            Instruction::Input(dest) => {
+                let input = input.ok_or(ErrorKind::InputRequired)?;
+                observer.input(input);
+                Some(dest.write(program, *base, input, observer)?)
             }
 
             Instruction::Output(src) => {
-                self.stdout.push_back(src.read(program, *base));
+                let output = src.read(program, *base, strict, observer)?;
+                observer.output(output);
+                self.device.output(output);
+                None
             }
 
             Instruction::JumpTrue(condition, dest) => {
-                if condition.read(program, *base) != 0 {
-                    self.pointer = dest.read(program, *base) as usize;
-                    return StepResult::Continue;
+                if condition.read(program, *base, strict, observer)? != 0 {
+                    self.pointer = address(dest.read(program, *base, strict, observer)?)?;
+                    return Ok(StepResult::Continue);
                 }
+                None
             }
 
             Instruction::JumpFalse(condition, dest) => {
-                if condition.read(program, *base) == 0 {
-                    self.pointer = dest.read(program, *base) as usize;
-                    return StepResult::Continue;
+                if condition.read(program, *base, strict, observer)? == 0 {
+                    self.pointer = address(dest.read(program, *base, strict, observer)?)?;
+                    return Ok(StepResult::Continue);
                 }
+                None
             }
 
             Instruction::LessThan(left, right, dest) => {
-                dest.write(
-                    program,
-                    *base,
-                    if left.read(program, *base) < right.read(program, *base) {
-                        1
-                    } else {
-                        0
-                    },
-                );
+                let value = if left.read(program, *base, strict, observer)?
+                    < right.read(program, *base, strict, observer)?
+                {
+                    1
+                } else {
+                    0
+                };
+                Some(dest.write(program, *base, value, observer)?)
             }
 
             Instruction::Equals(left, right, dest) => {
-                dest.write(
-                    program,
-                    *base,
-                    if left.read(program, *base) == right.read(program, *base) {
-                        1
-                    } else {
-                        0
-                    },
-                );
+                let value = if left.read(program, *base, strict, observer)?
+                    == right.read(program, *base, strict, observer)?
+                {
+                    1
+                } else {
+                    0
+                };
+                Some(dest.write(program, *base, value, observer)?)
             }
 
             Instruction::AdjustBase(offset) => {
-                *base += offset.read(program, *base);
+                *base = base.wrapping_add(offset.read(program, *base, strict, observer)?);
+                None
             }
 
-            Instruction::Halt => return StepResult::Halted,
+            Instruction::Halt => return Ok(StepResult::Halted),
+        };
+
+        if let (Some(cache), Some(address)) = (&mut self.cache, written) {
+            cache.invalidate(address);
         }
 
         self.pointer += steps;
-        StepResult::Continue
+        Ok(StepResult::Continue)
+    }
+
+    /// wraps an error with the current state of the VM
+    fn error(&self, kind: ErrorKind) -> IntCodeError {
+        let opcode = self.ram.get(self.pointer);
+        IntCodeError::new(kind, self.pointer, self.base, opcode)
+    }
+}
+
+/// converts a value into a memory address, failing if it's negative
+fn address(value: i64) -> Result<usize, ErrorKind> {
+    if value < 0 {
+        return Err(ErrorKind::NegativeAddress { address: value });
+    }
+
+    Ok(value as usize)
+}
+
+/// reads a parameter from memory. Anything beyond the end of memory reads as 0, unless strict
+fn load(program: &Memory, position: usize, strict: bool) -> Result<i64, ErrorKind> {
+    match program.get(position) {
+        Some(value) => Ok(value),
+        None if strict => Err(ErrorKind::ReadOutOfBounds { address: position }),
+        None => Ok(0),
     }
 }
 
-#[derive(Debug)]
-enum Instruction {
+/// checks an instruction only uses what was available before day 9
+fn check_legacy(opcode: i64, instruction: &Instruction) -> Result<(), ErrorKind> {
+    if let Instruction::AdjustBase(_) = instruction {
+        return Err(ErrorKind::UnknownOpcode);
+    }
+
+    for parameter in 1..instruction.size() {
+        let mode = (opcode / 10_i64.pow(parameter as u32 + 1)) % 10;
+
+        if mode == 2 {
+            return Err(ErrorKind::InvalidMode { parameter, mode });
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Instruction {
     Add(ReadValue, ReadValue, WriteValue),
     Multiply(ReadValue, ReadValue, WriteValue),
     Input(WriteValue),
@@ -175,51 +565,42 @@ enum Instruction {
 }
 
 impl Instruction {
-    fn parse(program: &[i64], pointer: usize) -> Instruction {
-        let opcode = program[pointer];
-
-        let mode1 = (opcode / 100) % 10;
-        let mode2 = (opcode / 1000) % 10;
-        let mode3 = (opcode / 10000) % 10;
-
-        match opcode % 100 {
-            1 => Instruction::Add(
-                ReadValue::new(program[pointer + 1], mode1),
-                ReadValue::new(program[pointer + 2], mode2),
-                WriteValue::new(program[pointer + 3], mode3),
-            ),
-            2 => Instruction::Multiply(
-                ReadValue::new(program[pointer + 1], mode1),
-                ReadValue::new(program[pointer + 2], mode2),
-                WriteValue::new(program[pointer + 3], mode3),
-            ),
-            3 => Instruction::Input(WriteValue::new(program[pointer + 1], mode1)),
-            4 => Instruction::Output(ReadValue::new(program[pointer + 1], mode1)),
-            5 => Instruction::JumpTrue(
-                ReadValue::new(program[pointer + 1], mode1),
-                ReadValue::new(program[pointer + 2], mode2),
-            ),
-            6 => Instruction::JumpFalse(
-                ReadValue::new(program[pointer + 1], mode1),
-                ReadValue::new(program[pointer + 2], mode2),
-            ),
-            7 => Instruction::LessThan(
-                ReadValue::new(program[pointer + 1], mode1),
-                ReadValue::new(program[pointer + 2], mode2),
-                WriteValue::new(program[pointer + 3], mode3),
-            ),
-            8 => Instruction::Equals(
-                ReadValue::new(program[pointer + 1], mode1),
-                ReadValue::new(program[pointer + 2], mode2),
-                WriteValue::new(program[pointer + 3], mode3),
-            ),
-            9 => Instruction::AdjustBase(ReadValue::new(program[pointer + 1], mode1)),
+    pub fn parse<C>(program: &C, pointer: usize) -> Result<Instruction, ErrorKind>
+    where
+        C: Cells + ?Sized,
+    {
+        let cell = |offset: usize| {
+            program
+                .cell(pointer + offset)
+                .ok_or(ErrorKind::TruncatedInstruction)
+        };
+
+        let opcode = cell(0)?;
+        let mode = |parameter: u32| (opcode / 10_i64.pow(parameter + 1)) % 10;
+
+        let read =
+            |parameter: usize| ReadValue::new(cell(parameter)?, mode(parameter as u32), parameter);
+        let write =
+            |parameter: usize| WriteValue::new(cell(parameter)?, mode(parameter as u32), parameter);
+
+        let instruction = match opcode % 100 {
+            1 => Instruction::Add(read(1)?, read(2)?, write(3)?),
+            2 => Instruction::Multiply(read(1)?, read(2)?, write(3)?),
+            3 => Instruction::Input(write(1)?),
+            4 => Instruction::Output(read(1)?),
+            5 => Instruction::JumpTrue(read(1)?, read(2)?),
+            6 => Instruction::JumpFalse(read(1)?, read(2)?),
+            7 => Instruction::LessThan(read(1)?, read(2)?, write(3)?),
+            8 => Instruction::Equals(read(1)?, read(2)?, write(3)?),
+            9 => Instruction::AdjustBase(read(1)?),
             99 => Instruction::Halt,
-            _ => panic!("Unknown opcode {} at pointer {}", opcode, pointer),
-        }
+            _ => return Err(ErrorKind::UnknownOpcode),
+        };
+
+        Ok(instruction)
     }
 
-    fn steps(&self) -> usize {
+    pub fn steps(&self) -> usize {
         match self {
             Instruction::Add(..) => 4,
             Instruction::Multiply(..) => 4,
@@ -233,62 +614,263 @@ impl Instruction {
             Instruction::Halt => 0,
         }
     }
+
+    /// the number of memory cells the instruction occupies, including the opcode
+    pub fn size(&self) -> usize {
+        match self {
+            Instruction::Halt => 1,
+            _ => self.steps(),
+        }
+    }
 }
 
-#[derive(Debug)]
-enum ReadValue {
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReadValue {
     Position(i64),
     Immediate(i64),
     Relative(i64),
 }
 
 impl ReadValue {
-    fn new(value: i64, mode: i64) -> ReadValue {
+    fn new(value: i64, mode: i64, parameter: usize) -> Result<ReadValue, ErrorKind> {
         match mode {
-            0 => ReadValue::Position(value),
-            1 => ReadValue::Immediate(value),
-            2 => ReadValue::Relative(value),
-            _ => panic!("Unsupported read mode: {:?}", mode),
+            0 => Ok(ReadValue::Position(value)),
+            1 => Ok(ReadValue::Immediate(value)),
+            2 => Ok(ReadValue::Relative(value)),
+            _ => Err(ErrorKind::InvalidMode { parameter, mode }),
         }
     }
 
-    fn read(&self, program: &[i64], base: i64) -> i64 {
+    fn read<O>(
+        &self,
+        program: &Memory,
+        base: i64,
+        strict: bool,
+        observer: &mut O,
+    ) -> Result<i64, ErrorKind>
+    where
+        O: Observer + ?Sized,
+    {
         let position = match *self {
-            ReadValue::Position(position) => position as usize,
-            ReadValue::Relative(position) => (position + base) as usize,
-            ReadValue::Immediate(value) => return value,
+            ReadValue::Position(position) => address(position)?,
+            ReadValue::Relative(position) => address(position.wrapping_add(base))?,
+            ReadValue::Immediate(value) => return Ok(value),
         };
 
-        *program.get(position).unwrap_or(&0)
+        let value = load(program, position, strict)?;
+        observer.read(position, value);
+        Ok(value)
     }
 }
 
-#[derive(Debug)]
-enum WriteValue {
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WriteValue {
     Position(i64),
     Relative(i64),
 }
 
 impl WriteValue {
-    fn new(value: i64, mode: i64) -> WriteValue {
+    fn new(value: i64, mode: i64, parameter: usize) -> Result<WriteValue, ErrorKind> {
         match mode {
-            0 => WriteValue::Position(value),
-            2 => WriteValue::Relative(value),
-            _ => panic!("Unsupported write mode: {:?}", mode),
+            0 => Ok(WriteValue::Position(value)),
+            1 => Err(ErrorKind::ImmediateWrite { parameter }),
+            2 => Ok(WriteValue::Relative(value)),
+            _ => Err(ErrorKind::InvalidMode { parameter, mode }),
         }
     }
 
-    fn write(&self, program: &mut Vec<i64>, base: i64, value: i64) {
-        let position: usize = match *self {
-            WriteValue::Position(position) => position as usize,
-            WriteValue::Relative(position) => (position + base) as usize,
+    fn write<O>(
+        &self,
+        program: &mut Memory,
+        base: i64,
+        value: i64,
+        observer: &mut O,
+    ) -> Result<usize, ErrorKind>
+    where
+        O: Observer + ?Sized,
+    {
+        let position = match *self {
+            WriteValue::Position(position) => address(position)?,
+            WriteValue::Relative(position) => address(position.wrapping_add(base))?,
         };
 
-        if position >= program.len() {
-            // memory can grow dynamically if we try to access a non-existant index
-            program.resize_with(position + 1, Default::default);
-        }
+        // memory can grow dynamically if we try to access a non-existant index, within its limit
+        program.check(position)?;
+
+        observer.write(position, program.read(position), value);
+        program.set(position, value);
+        Ok(position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(program: Vec<i64>) -> Result<(), IntCodeError> {
+        IntCodeEmulator::new(program).execute()
+    }
+
+    #[test]
+    fn unknown_opcode() {
+        let error = run(vec![1101, 1, 2, 0, 42]).unwrap_err();
+
+        assert_eq!(error.kind, ErrorKind::UnknownOpcode);
+        assert_eq!(error.pointer, 4);
+        assert_eq!(error.opcode, Some(42));
+    }
+
+    #[test]
+    fn invalid_mode() {
+        let error = run(vec![10301, 1, 2, 0]).unwrap_err();
+        assert_eq!(
+            error.kind,
+            ErrorKind::InvalidMode {
+                parameter: 1,
+                mode: 3
+            }
+        );
+    }
+
+    #[test]
+    fn negative_relative_address() {
+        let error = run(vec![109, -5, 204, 1, 99]).unwrap_err();
+
+        assert_eq!(error.kind, ErrorKind::NegativeAddress { address: -4 });
+        assert_eq!(error.pointer, 2);
+        assert_eq!(error.base, -5);
+    }
+
+    #[test]
+    fn immediate_write() {
+        let error = run(vec![11101, 1, 2, 0]).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::ImmediateWrite { parameter: 3 });
+    }
+
+    #[test]
+    fn truncated_instruction() {
+        let error = run(vec![1101, 1, 2]).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::TruncatedInstruction);
+
+        let error = run(vec![1106, 0, 10]).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::TruncatedInstruction);
+        assert_eq!(error.pointer, 10);
+        assert_eq!(error.opcode, None);
+    }
+
+    #[test]
+    fn input_required() {
+        let error = run(vec![3, 0, 99]).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::InputRequired);
+    }
+
+    #[test]
+    fn budget_exhausted() {
+        // counts up in cell 7 forever
+        let mut vm = IntCodeEmulator::new(vec![1001, 7, 1, 7, 1105, 1, 0, 0]);
+        vm.set_instruction_budget(Some(5));
+
+        assert_eq!(vm.execute_until_yield(), Ok(YieldReason::BudgetExhausted));
+        assert_eq!(vm.peek(7), 3);
+        assert_eq!(vm.pointer(), 4);
+
+        vm.set_instruction_budget(Some(2));
+        assert_eq!(vm.execute_until_yield(), Ok(YieldReason::BudgetExhausted));
+        assert_eq!(vm.peek(7), 4);
+        assert_eq!(vm.pointer(), 4);
+
+        let error = vm.execute().unwrap_err();
+        assert_eq!(error.kind, ErrorKind::BudgetExhausted);
+    }
+
+    #[test]
+    fn legacy_rejects_relative_mode() {
+        let mut vm = IntCodeEmulator::new(vec![109, 1, 99]);
+        vm.set_strictness(Strictness::Legacy);
+        let error = vm.execute().unwrap_err();
+        assert_eq!(error.kind, ErrorKind::UnknownOpcode);
+        assert_eq!(error.opcode, Some(109));
+
+        let mut vm = IntCodeEmulator::new(vec![204, 0, 99]);
+        vm.set_strictness(Strictness::Legacy);
+        let error = vm.execute().unwrap_err();
+        assert_eq!(
+            error.kind,
+            ErrorKind::InvalidMode {
+                parameter: 1,
+                mode: 2
+            }
+        );
+
+        let mut vm = IntCodeEmulator::from_input(include_str!("../input/2019/day5.txt"));
+        vm.set_strictness(Strictness::Legacy);
+        vm.stdin().push_back(5);
+        vm.execute().unwrap();
+        assert_eq!(vm.stdout().len(), 1);
+    }
+
+    #[test]
+    fn strict_rejects_reads_beyond_memory() {
+        let mut vm = IntCodeEmulator::new(vec![4, 100, 99]);
+        vm.set_strictness(Strictness::Strict);
+        let error = vm.execute().unwrap_err();
+        assert_eq!(error.kind, ErrorKind::ReadOutOfBounds { address: 100 });
+        assert_eq!(error.pointer, 0);
+
+        let mut vm = IntCodeEmulator::new(vec![4, 100, 99]);
+        vm.execute().unwrap();
+        assert_eq!(vm.stdout().pop_front(), Some(0));
+
+        // writing a cell first makes it safe to read
+        let mut vm = IntCodeEmulator::new(vec![1101, 2, 3, 100, 4, 100, 99]);
+        vm.set_strictness(Strictness::Strict);
+        vm.execute().unwrap();
+        assert_eq!(vm.stdout().pop_front(), Some(5));
+    }
+
+    #[test]
+    fn strict_rejects_executing_data() {
+        // jumps to an instruction it wrote beyond the end of the program
+        let mut vm = IntCodeEmulator::new(vec![1101, 99, 0, 10, 1105, 1, 10]);
+        vm.set_strictness(Strictness::Strict);
+        let error = vm.execute().unwrap_err();
+        assert_eq!(error.kind, ErrorKind::ExecutedData { address: 10 });
+        assert_eq!(error.pointer, 10);
+
+        let mut vm = IntCodeEmulator::new(vec![1101, 99, 0, 10, 1105, 1, 10]);
+        vm.execute().unwrap();
+
+        // an instruction whose parameters run off the end of the program
+        let mut vm = IntCodeEmulator::new(vec![1101, 0, 0, 10, 1105, 1, 7, 104]);
+        vm.set_strictness(Strictness::Strict);
+        let error = vm.execute().unwrap_err();
+        assert_eq!(error.kind, ErrorKind::ExecutedData { address: 8 });
+        assert_eq!(error.pointer, 7);
+    }
+
+    #[test]
+    fn strict_runs_puzzles() {
+        let mut vm = IntCodeEmulator::from_input(include_str!("../input/2019/day9.txt"));
+        vm.set_strictness(Strictness::Strict);
+        vm.stdin().push_back(1);
+        vm.execute().unwrap();
+        assert_eq!(vm.stdout().len(), 1);
+
+        let mut vm = IntCodeEmulator::from_input(include_str!("../input/2019/day5.txt"));
+        vm.set_strictness(Strictness::Strict);
+        vm.stdin().push_back(1);
+        vm.execute().unwrap();
+        assert!(vm.stdout().iter().rev().skip(1).all(|&v| v == 0));
+    }
+
+    #[test]
+    fn deadline_exceeded() {
+        let mut vm = IntCodeEmulator::new(vec![1105, 1, 0]);
+        vm.set_timeout(Duration::from_millis(10));
+        assert_eq!(vm.execute_until_yield(), Ok(YieldReason::DeadlineExceeded));
 
-        program[position] = value;
+        vm.set_deadline(None);
+        vm.set_instruction_budget(Some(1));
+        assert_eq!(vm.execute_until_yield(), Ok(YieldReason::BudgetExhausted));
     }
 }
@@ -0,0 +1,162 @@
+use crate::intcode::{IntCodeEmulator, IntCodeError, YieldReason};
+use std::collections::VecDeque;
+
+/// Runs a program which communicates in ASCII text.
+///
+/// Input is pushed as strings rather than individual characters, and output is decoded back into
+/// lines of text as the program runs. Output values outside of the ASCII range, such as a final
+/// answer, are kept separately so they don't corrupt the text.
+#[derive(Debug, Clone)]
+pub struct AsciiEmulator {
+    vm: IntCodeEmulator,
+    lines: VecDeque<String>,
+    partial: String,
+    values: Vec<i64>,
+}
+
+impl AsciiEmulator {
+    pub fn new(vm: IntCodeEmulator) -> AsciiEmulator {
+        AsciiEmulator {
+            vm,
+            lines: VecDeque::new(),
+            partial: String::new(),
+            values: Vec::new(),
+        }
+    }
+
+    pub fn from_input(input: &str) -> AsciiEmulator {
+        AsciiEmulator::new(IntCodeEmulator::from_input(input))
+    }
+
+    pub fn vm(&self) -> &IntCodeEmulator {
+        &self.vm
+    }
+
+    pub fn vm_mut(&mut self) -> &mut IntCodeEmulator {
+        &mut self.vm
+    }
+
+    pub fn into_inner(self) -> IntCodeEmulator {
+        self.vm
+    }
+
+    /// queues text as input, sending each character as its code point
+    pub fn push_str(&mut self, text: &str) {
+        self.vm.stdin().extend(text.chars().map(|c| c as i64));
+    }
+
+    /// queues a line of text as input, followed by a newline
+    pub fn push_line(&mut self, line: &str) {
+        self.push_str(line);
+        self.vm.stdin().push_back(i64::from(b'\n'));
+    }
+
+    pub fn execute(&mut self) -> Result<(), IntCodeError> {
+        let result = self.vm.execute();
+        self.decode();
+        result
+    }
+
+    pub fn execute_until_yield(&mut self) -> Result<YieldReason, IntCodeError> {
+        let result = self.vm.execute_until_yield();
+        self.decode();
+        result
+    }
+
+    /// removes and returns the next complete line of output
+    pub fn next_line(&mut self) -> Option<String> {
+        self.lines.pop_front()
+    }
+
+    /// removes and returns every complete line of output
+    pub fn take_lines(&mut self) -> Vec<String> {
+        self.lines.drain(..).collect()
+    }
+
+    /// output text which hasn't been terminated by a newline yet, such as a prompt
+    pub fn partial(&self) -> &str {
+        &self.partial
+    }
+
+    /// removes and returns all of the output text, including any unterminated line
+    pub fn take_text(&mut self) -> String {
+        let mut text = String::new();
+
+        for line in self.lines.drain(..) {
+            text.push_str(&line);
+            text.push('\n');
+        }
+
+        text.push_str(&self.partial);
+        self.partial.clear();
+        text
+    }
+
+    /// output values which weren't ASCII characters, in the order they were output
+    pub fn values(&self) -> &[i64] {
+        &self.values
+    }
+
+    /// removes and returns the output values which weren't ASCII characters
+    pub fn take_values(&mut self) -> Vec<i64> {
+        std::mem::take(&mut self.values)
+    }
+
+    /// moves everything the VM has output into lines of text and other values
+    fn decode(&mut self) {
+        for value in self.vm.stdout().drain(..) {
+            match value {
+                10 => self.lines.push_back(std::mem::take(&mut self.partial)),
+                0..=127 => self.partial.push(value as u8 as char),
+                _ => self.values.push(value),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::assembler::assemble;
+
+    /// prompts for a line, echoes it back, then outputs its length
+    const ECHO: &str = "
+        prompt: out #62
+                out #32
+                add #0, #0, [length]
+        loop:   in [char]
+                out [char]
+                eq [char], #10, [done]
+                jt [done], #prompt
+                add [length], #1, [length]
+                lt [length], #3, [done]
+                jt [done], #loop
+                out #1000
+                hlt
+        char:   data 0
+        length: data 0
+        done:   data 0
+    ";
+
+    #[test]
+    fn reads_lines_and_prompts() {
+        let mut vm = AsciiEmulator::new(IntCodeEmulator::new(assemble(ECHO).unwrap()));
+        vm.push_line("hi");
+
+        assert_eq!(vm.execute_until_yield(), Ok(YieldReason::InputRequired));
+        assert_eq!(vm.take_lines(), vec!["> hi"]);
+        assert_eq!(vm.partial(), "> ");
+        assert_eq!(vm.take_text(), "> ");
+        assert_eq!(vm.partial(), "");
+    }
+
+    #[test]
+    fn separates_non_ascii_values() {
+        let mut vm = AsciiEmulator::new(IntCodeEmulator::new(assemble(ECHO).unwrap()));
+        vm.push_str("abc");
+
+        assert_eq!(vm.execute(), Ok(()));
+        assert_eq!(vm.take_text(), "> abc");
+        assert_eq!(vm.values(), &[1000]);
+    }
+}
@@ -0,0 +1,464 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+/// Whether an instruction parameter is read from or written to
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Parameter {
+    Read,
+    Write,
+}
+
+const BINARY: &[Parameter] = &[Parameter::Read, Parameter::Read, Parameter::Write];
+const JUMP: &[Parameter] = &[Parameter::Read, Parameter::Read];
+
+/// the opcode and parameters of every supported mnemonic
+const MNEMONICS: &[(&str, i64, &[Parameter])] = &[
+    ("add", 1, BINARY),
+    ("mul", 2, BINARY),
+    ("in", 3, &[Parameter::Write]),
+    ("out", 4, &[Parameter::Read]),
+    ("jt", 5, JUMP),
+    ("jf", 6, JUMP),
+    ("lt", 7, BINARY),
+    ("eq", 8, BINARY),
+    ("arb", 9, &[Parameter::Read]),
+    ("hlt", 99, &[]),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AssembleErrorKind {
+    UnknownMnemonic(String),
+    OperandCount { expected: usize, found: usize },
+    InvalidOperand(String),
+    InvalidLabel(String),
+    DuplicateLabel(String),
+    UnknownLabel(String),
+    OutOfRange { label: String, offset: i64 },
+    ImmediateWrite { parameter: usize },
+}
+
+/// An error in assembly source, along with the line (numbered from 1) it occurred on
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssembleError {
+    pub kind: AssembleErrorKind,
+    pub line: usize,
+}
+
+/// A number, label or label with an offset, e.g. `12`, `loop` or `buffer+3`
+#[derive(Debug, Clone, PartialEq)]
+struct Expression {
+    label: Option<String>,
+    offset: i64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Operand {
+    Position(Expression),
+    Immediate(Expression),
+    Relative(Expression),
+}
+
+#[derive(Debug)]
+enum Statement {
+    Instruction(i64, Vec<Operand>),
+    Data(Vec<Expression>),
+}
+
+/// Assembles IntCode source into a program.
+///
+/// Each line holds any number of `label:` prefixes followed by an optional statement, and anything
+/// after a `;` is a comment. Statements are either one of the mnemonics produced by the
+/// disassembler (`add`, `mul`, `in`, `out`, `jt`, `jf`, `lt`, `eq`, `arb`, `hlt`) with comma-separated
+/// operands, or a `data` directive listing raw cell values.
+///
+/// Operands are written as `[x]` for position mode, `#x` for immediate mode and `[rb+x]` for
+/// relative mode, where `x` is a number, a label or a label with an offset such as `buffer+2`.
+pub fn assemble(source: &str) -> Result<Vec<i64>, AssembleError> {
+    let mut labels = HashMap::new();
+    let mut statements = Vec::new();
+    let mut address = 0;
+
+    // first pass - parse each line and work out the address of every label
+    for (index, line) in source.lines().enumerate() {
+        let line_number = index + 1;
+        let error = |kind| AssembleError::new(kind, line_number);
+
+        let mut rest = line.split(';').next().unwrap().trim();
+
+        while let Some(colon) = rest.find(':') {
+            let label = rest[..colon].trim();
+
+            if !is_label(label) {
+                return Err(error(AssembleErrorKind::InvalidLabel(label.to_string())));
+            }
+
+            if labels.insert(label.to_string(), address).is_some() {
+                return Err(error(AssembleErrorKind::DuplicateLabel(label.to_string())));
+            }
+
+            rest = rest[colon + 1..].trim();
+        }
+
+        if rest.is_empty() {
+            continue;
+        }
+
+        let statement = parse_statement(rest).map_err(error)?;
+        address += match &statement {
+            Statement::Instruction(_, operands) => operands.len() + 1,
+            Statement::Data(values) => values.len(),
+        };
+
+        statements.push((line_number, statement));
+    }
+
+    // second pass - resolve labels and encode each statement
+    let mut program = Vec::with_capacity(address);
+
+    for (line_number, statement) in statements {
+        let resolve = |expression: &Expression| {
+            expression
+                .resolve(&labels)
+                .map_err(|kind| AssembleError::new(kind, line_number))
+        };
+
+        match statement {
+            Statement::Instruction(opcode, operands) => {
+                let mut instruction = opcode;
+                let mut parameters = Vec::with_capacity(operands.len());
+
+                for (index, operand) in operands.iter().enumerate() {
+                    let (mode, expression) = match operand {
+                        Operand::Position(e) => (0, e),
+                        Operand::Immediate(e) => (1, e),
+                        Operand::Relative(e) => (2, e),
+                    };
+
+                    instruction += mode * 10_i64.pow(index as u32 + 2);
+                    parameters.push(resolve(expression)?);
+                }
+
+                program.push(instruction);
+                program.extend(parameters);
+            }
+            Statement::Data(values) => {
+                for value in values.iter() {
+                    program.push(resolve(value)?);
+                }
+            }
+        }
+    }
+
+    Ok(program)
+}
+
+fn parse_statement(statement: &str) -> Result<Statement, AssembleErrorKind> {
+    let (mnemonic, operands) = match statement.find(char::is_whitespace) {
+        Some(space) => (&statement[..space], statement[space..].trim()),
+        None => (statement, ""),
+    };
+
+    let operands: Vec<&str> = if operands.is_empty() {
+        Vec::new()
+    } else {
+        operands.split(',').map(|o| o.trim()).collect()
+    };
+
+    if mnemonic == "data" {
+        return operands
+            .iter()
+            .map(|o| Expression::parse(o))
+            .collect::<Result<Vec<_>, _>>()
+            .map(Statement::Data);
+    }
+
+    let &(_, opcode, parameters) = MNEMONICS
+        .iter()
+        .find(|(name, _, _)| *name == mnemonic)
+        .ok_or_else(|| AssembleErrorKind::UnknownMnemonic(mnemonic.to_string()))?;
+
+    if operands.len() != parameters.len() {
+        return Err(AssembleErrorKind::OperandCount {
+            expected: parameters.len(),
+            found: operands.len(),
+        });
+    }
+
+    let operands = operands
+        .iter()
+        .zip(parameters.iter())
+        .enumerate()
+        .map(|(index, (operand, &parameter))| {
+            let operand = Operand::parse(operand)?;
+
+            match (parameter, &operand) {
+                (Parameter::Write, Operand::Immediate(_)) => {
+                    Err(AssembleErrorKind::ImmediateWrite {
+                        parameter: index + 1,
+                    })
+                }
+                _ => Ok(operand),
+            }
+        })
+        .collect::<Result<Vec<Operand>, AssembleErrorKind>>()?;
+
+    Ok(Statement::Instruction(opcode, operands))
+}
+
+fn is_label(label: &str) -> bool {
+    let mut chars = label.chars();
+
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {}
+        _ => return false,
+    }
+
+    label != "rb" && chars.all(is_label_char)
+}
+
+fn is_label_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+impl Operand {
+    fn parse(operand: &str) -> Result<Operand, AssembleErrorKind> {
+        let invalid = || AssembleErrorKind::InvalidOperand(operand.to_string());
+
+        if let Some(immediate) = operand.strip_prefix('#') {
+            return Expression::parse(immediate).map(Operand::Immediate);
+        }
+
+        if !operand.starts_with('[') || !operand.ends_with(']') {
+            return Err(invalid());
+        }
+
+        let inner = operand[1..operand.len() - 1].trim();
+        let offset = match inner.get(2..) {
+            Some(offset) if inner.starts_with("rb") && !offset.starts_with(is_label_char) => {
+                offset.trim()
+            }
+            _ => return Expression::parse(inner).map(Operand::Position),
+        };
+
+        if offset.is_empty() {
+            Ok(Operand::Relative(Expression {
+                label: None,
+                offset: 0,
+            }))
+        } else if offset.starts_with('+') || offset.starts_with('-') {
+            // a relative offset can itself be negative, e.g. [rb-2]
+            Expression::parse(offset.trim_start_matches('+')).map(Operand::Relative)
+        } else {
+            Err(invalid())
+        }
+    }
+}
+
+impl Expression {
+    fn parse(expression: &str) -> Result<Expression, AssembleErrorKind> {
+        let expression = expression.trim();
+        let invalid = || AssembleErrorKind::InvalidOperand(expression.to_string());
+
+        // a minus sign can be spaced from its number, but a number can't contain spaces
+        let number = match expression.strip_prefix('-') {
+            Some(digits) => format!("-{}", digits.trim_start()),
+            None => expression.to_string(),
+        };
+
+        if let Ok(value) = number.parse() {
+            return Ok(Expression {
+                label: None,
+                offset: value,
+            });
+        }
+
+        let (label, offset) = match expression.rfind(['+', '-']) {
+            Some(sign) => {
+                let offset: i64 = expression[sign + 1..]
+                    .trim()
+                    .parse()
+                    .map_err(|_| invalid())?;
+
+                let offset = if &expression[sign..=sign] == "-" {
+                    -offset
+                } else {
+                    offset
+                };
+
+                (expression[..sign].trim(), offset)
+            }
+            None => (expression, 0),
+        };
+
+        if !is_label(label) {
+            return Err(invalid());
+        }
+
+        Ok(Expression {
+            label: Some(label.to_string()),
+            offset,
+        })
+    }
+
+    fn resolve(&self, labels: &HashMap<String, usize>) -> Result<i64, AssembleErrorKind> {
+        match &self.label {
+            None => Ok(self.offset),
+            Some(label) => {
+                let address = labels
+                    .get(label)
+                    .ok_or_else(|| AssembleErrorKind::UnknownLabel(label.clone()))?;
+
+                (*address as i64).checked_add(self.offset).ok_or_else(|| {
+                    AssembleErrorKind::OutOfRange {
+                        label: label.clone(),
+                        offset: self.offset,
+                    }
+                })
+            }
+        }
+    }
+}
+
+impl AssembleError {
+    fn new(kind: AssembleErrorKind, line: usize) -> AssembleError {
+        AssembleError { kind, line }
+    }
+}
+
+impl fmt::Display for AssembleErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AssembleErrorKind::UnknownMnemonic(m) => write!(f, "unknown mnemonic '{}'", m),
+            AssembleErrorKind::OperandCount { expected, found } => {
+                write!(f, "expected {} operands but found {}", expected, found)
+            }
+            AssembleErrorKind::InvalidOperand(o) => write!(f, "invalid operand '{}'", o),
+            AssembleErrorKind::InvalidLabel(l) => write!(f, "invalid label '{}'", l),
+            AssembleErrorKind::DuplicateLabel(l) => write!(f, "duplicate label '{}'", l),
+            AssembleErrorKind::UnknownLabel(l) => write!(f, "unknown label '{}'", l),
+            AssembleErrorKind::OutOfRange { label, offset } => {
+                write!(f, "'{}{:+}' is out of range", label, offset)
+            }
+            AssembleErrorKind::ImmediateWrite { parameter } => {
+                write!(
+                    f,
+                    "parameter {} is written to so can't be immediate",
+                    parameter
+                )
+            }
+        }
+    }
+}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.kind)
+    }
+}
+
+impl Error for AssembleError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::disassembler::{disassemble, Content};
+    use crate::intcode::IntCodeEmulator;
+
+    #[test]
+    fn encodes_modes() {
+        let program = assemble("mul [4], #3, [rb-2]\nhlt").unwrap();
+        assert_eq!(program, vec![21002, 4, 3, -2, 99]);
+
+        let program = assemble("mul [4], #- 3, [rb - 2]\nhlt").unwrap();
+        assert_eq!(program, vec![21002, 4, -3, -2, 99]);
+    }
+
+    #[test]
+    fn resolves_labels_and_data() {
+        let source = "
+            ; count down from the input to zero
+                    in [counter]
+            loop:   out [counter]
+                    add [counter], #-1, [counter]
+                    jt [counter], #loop
+                    hlt
+            counter: data 0
+        ";
+
+        let program = assemble(source).unwrap();
+        assert_eq!(
+            program,
+            vec![3, 12, 4, 12, 1001, 12, -1, 12, 1005, 12, 2, 99, 0]
+        );
+
+        let mut vm = IntCodeEmulator::new(program);
+        vm.stdin().push_back(3);
+        vm.execute().unwrap();
+
+        assert_eq!(
+            vm.stdout().iter().copied().collect::<Vec<i64>>(),
+            vec![3, 2, 1]
+        );
+    }
+
+    #[test]
+    fn round_trips_disassembly() {
+        let original = IntCodeEmulator::parse_input(include_str!("../../input/2019/day9.txt"));
+
+        // only the leading code section is guaranteed to decode as written
+        let source = disassemble(&original[..63])
+            .iter()
+            .map(|line| match line.content {
+                Content::Instruction(instruction) => instruction.to_string(),
+                Content::Data => panic!("Unexpected data at {}", line.address),
+            })
+            .collect::<Vec<String>>()
+            .join("\n");
+
+        assert_eq!(assemble(&source).unwrap(), &original[..63]);
+    }
+
+    #[test]
+    fn reports_errors_with_line_numbers() {
+        let error = assemble("hlt\nadd #1, #2, #3").unwrap_err();
+        assert_eq!(error.line, 2);
+        assert_eq!(
+            error.kind,
+            AssembleErrorKind::ImmediateWrite { parameter: 3 }
+        );
+
+        let error = assemble("data 1 2").unwrap_err();
+        assert_eq!(
+            error.kind,
+            AssembleErrorKind::InvalidOperand("1 2".to_string())
+        );
+
+        let error = assemble("out [a b]\na: data 0\nb: data 0").unwrap_err();
+        assert_eq!(error.line, 1);
+        assert_eq!(
+            error.kind,
+            AssembleErrorKind::InvalidOperand("a b".to_string())
+        );
+
+        let error = assemble("hlt\nout [end+9223372036854775807]\nend: hlt").unwrap_err();
+        assert_eq!(error.line, 2);
+        assert_eq!(
+            error.kind,
+            AssembleErrorKind::OutOfRange {
+                label: "end".to_string(),
+                offset: i64::MAX
+            }
+        );
+        assert_eq!(
+            error.to_string(),
+            "line 2: 'end+9223372036854775807' is out of range"
+        );
+
+        let error = assemble("jt #1, #missing").unwrap_err();
+        assert_eq!(
+            error.kind,
+            AssembleErrorKind::UnknownLabel("missing".to_string())
+        );
+    }
+}
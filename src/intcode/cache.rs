@@ -0,0 +1,272 @@
+use crate::intcode::{ErrorKind, Instruction, Memory};
+use std::sync::Arc;
+
+/// the largest number of cells any instruction occupies
+const MAX_INSTRUCTION_SIZE: usize = 4;
+
+/// Holds instructions which have already been decoded, indexed by their address.
+///
+/// The decoded instructions are shared between clones of a VM, so a template VM can be decoded once
+/// and then cloned cheaply. When a clone modifies a shared instruction it's marked as stale for that
+/// clone only, and from then on is decoded every time it's executed.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct DecodeCache {
+    entries: Arc<Vec<Option<Instruction>>>,
+
+    /// bitset of shared entries which this VM has invalidated, allocated on first use
+    stale: Vec<u64>,
+}
+
+impl DecodeCache {
+    /// returns the cached instruction at the pointer, decoding and caching it if required
+    pub(crate) fn decode(
+        &mut self,
+        program: &Memory,
+        pointer: usize,
+    ) -> Result<Instruction, ErrorKind> {
+        if let Some(Some(instruction)) = self.entries.get(pointer) {
+            if !self.is_stale(pointer) {
+                return Ok(*instruction);
+            }
+        }
+
+        let instruction = Instruction::parse(program, pointer)?;
+
+        // only cache the instruction if no other clone is sharing the entries
+        if let Some(entries) = Arc::get_mut(&mut self.entries) {
+            if pointer >= entries.len() {
+                entries.resize(pointer + 1, None);
+            }
+
+            entries[pointer] = Some(instruction);
+            self.set_stale(pointer, false);
+        }
+
+        Ok(instruction)
+    }
+
+    /// decodes every instruction found by a linear sweep of the program. Anything else, such as the
+    /// target of a jump into the middle of an instruction, is still decoded on demand
+    pub(crate) fn predecode(&mut self, program: &Memory) {
+        let mut entries = vec![None; program.len()];
+        let mut pointer = 0;
+
+        while pointer < program.len() {
+            match Instruction::parse(program, pointer) {
+                Ok(instruction) => {
+                    entries[pointer] = Some(instruction);
+                    pointer += instruction.size();
+                }
+                Err(_) => pointer += 1,
+            }
+        }
+
+        self.entries = Arc::new(entries);
+        self.stale.clear();
+    }
+
+    /// removes any cached instruction which includes the given address
+    pub(crate) fn invalidate(&mut self, address: usize) {
+        // nothing cached can reach that far
+        if self.entries.is_empty() || address >= self.entries.len() + MAX_INSTRUCTION_SIZE - 1 {
+            return;
+        }
+
+        let start = address.saturating_sub(MAX_INSTRUCTION_SIZE - 1);
+        let end = address.min(self.entries.len().saturating_sub(1));
+
+        for pointer in start..=end {
+            let covered = match self.entries[pointer] {
+                Some(instruction) => pointer + instruction.size() > address,
+                None => false,
+            };
+
+            if !covered {
+                continue;
+            }
+
+            match Arc::get_mut(&mut self.entries) {
+                Some(entries) => entries[pointer] = None,
+                None => self.set_stale(pointer, true),
+            }
+        }
+    }
+
+    fn is_stale(&self, pointer: usize) -> bool {
+        match self.stale.get(pointer / 64) {
+            Some(bits) => bits & (1 << (pointer % 64)) != 0,
+            None => false,
+        }
+    }
+
+    fn set_stale(&mut self, pointer: usize, stale: bool) {
+        if !stale && self.stale.is_empty() {
+            return;
+        }
+
+        // entries can grow whilst they're unshared, so the bitset might not reach this far yet
+        if stale && self.stale.len() <= pointer / 64 {
+            self.stale
+                .resize(self.entries.len().max(pointer + 1) / 64 + 1, 0);
+        }
+
+        if let Some(bits) = self.stale.get_mut(pointer / 64) {
+            if stale {
+                *bits |= 1 << (pointer % 64);
+            } else {
+                *bits &= !(1 << (pointer % 64));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::intcode::assembler::assemble;
+    use crate::intcode::IntCodeEmulator;
+    use std::time::Instant;
+
+    const DAY02: &str = include_str!("../../input/2019/day2.txt");
+    const DAY09: &str = include_str!("../../input/2019/day9.txt");
+
+    #[test]
+    fn matches_interpreter() {
+        for input in [1, 2].iter() {
+            let mut plain = IntCodeEmulator::from_input(DAY09);
+            let mut cached = plain.clone();
+            cached.enable_decode_cache();
+
+            for vm in [&mut plain, &mut cached].iter_mut() {
+                vm.stdin().push_back(*input);
+                vm.execute().unwrap();
+            }
+
+            assert_eq!(cached.stdout(), plain.stdout());
+            assert_eq!(cached.ram(), plain.ram());
+        }
+    }
+
+    #[test]
+    fn invalidates_self_modified_code() {
+        // the first instruction rewrites the opcode of the second from multiply to add
+        let program = assemble(
+            "
+                    add #0, #1101, [patch]
+            patch:  mul #3, #4, [result]
+                    out [result]
+                    hlt
+            result: data 0
+            ",
+        )
+        .unwrap();
+
+        let mut vm = IntCodeEmulator::new(program);
+        vm.predecode();
+        vm.execute().unwrap();
+
+        assert_eq!(vm.stdout().pop_back(), Some(7));
+    }
+
+    #[test]
+    fn invalidates_poked_operands() {
+        let mut template = IntCodeEmulator::new(IntCodeEmulator::parse_input(DAY02));
+        template.predecode();
+
+        let mut vm = template.clone();
+        vm.poke(1, 12);
+        vm.poke(2, 2);
+        vm.execute().unwrap();
+
+        assert_eq!(vm.ram()[0], 6_627_023);
+    }
+
+    #[test]
+    fn invalidates_empty_cache() {
+        let mut vm = IntCodeEmulator::new(vec![1101, 1, 1, 0, 99]);
+        vm.enable_decode_cache();
+        vm.poke(1, 2);
+
+        // the template's empty cache is shared with the clone, which writes before decoding much
+        let mut clone = vm.clone();
+        clone.execute().unwrap();
+        assert_eq!(clone.ram()[0], 3);
+
+        vm.poke(100, 1);
+        vm.execute().unwrap();
+        assert_eq!(vm.ram()[0], 3);
+    }
+
+    #[test]
+    fn invalidates_code_in_grown_memory() {
+        let mut vm = IntCodeEmulator::new(vec![1105, 1, 100]);
+        vm.predecode();
+
+        // marking an entry as stale whilst the cache is shared sizes the bitset to the program
+        let clone = vm.clone();
+        vm.poke(0, 1105);
+        drop(clone);
+
+        // out #1, in [200], jmp 100
+        for (offset, &value) in [104, 1, 3, 200, 1105, 1, 100].iter().enumerate() {
+            vm.poke(100 + offset, value);
+        }
+        assert!(vm.execute_until_yield().is_ok());
+
+        let clone = vm.clone();
+        vm.poke(101, 2);
+        vm.stdin().push_back(0);
+        assert!(vm.execute_until_yield().is_ok());
+        drop(clone);
+
+        assert_eq!(vm.stdout(), &[1, 2]);
+    }
+
+    /// run with `cargo test --release -- --ignored --nocapture` to compare the engines.
+    ///
+    /// Day 2 writes its results into the operands of the instructions which follow, so each clone
+    /// marks much of the shared cache as stale and only gains around 10%. Loop-heavy programs like
+    /// day 9 re-execute the same instructions and run nearly twice as fast.
+    #[test]
+    #[ignore]
+    fn benchmark_decode_cache() {
+        let program = IntCodeEmulator::parse_input(DAY02);
+
+        let brute_force = |template: &IntCodeEmulator| {
+            let start = Instant::now();
+
+            for noun in 0..=99 {
+                for verb in 0..=99 {
+                    let mut vm = template.clone();
+                    vm.poke(1, noun);
+                    vm.poke(2, verb);
+                    vm.execute().unwrap();
+                }
+            }
+
+            start.elapsed()
+        };
+
+        let plain = IntCodeEmulator::new(program.clone());
+        let mut cached = IntCodeEmulator::new(program);
+        cached.predecode();
+
+        println!("day02 interpreter:  {:?}", brute_force(&plain));
+        println!("day02 decode cache: {:?}", brute_force(&cached));
+
+        let boost = |cache: bool| {
+            let start = Instant::now();
+            let mut vm = IntCodeEmulator::from_input(DAY09);
+
+            if cache {
+                vm.enable_decode_cache();
+            }
+
+            vm.stdin().push_back(2);
+            vm.execute().unwrap();
+            start.elapsed()
+        };
+
+        println!("day09 interpreter:  {:?}", boost(false));
+        println!("day09 decode cache: {:?}", boost(true));
+    }
+}
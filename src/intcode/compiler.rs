@@ -0,0 +1,637 @@
+use crate::intcode::{
+    address, ErrorKind, Instruction, IntCodeEmulator, IntCodeError, Memory, Observer, ReadValue,
+    StepResult, Strictness, WriteValue, YieldReason,
+};
+use std::collections::HashSet;
+use std::fmt;
+
+/// the most instructions compiled into a single block
+const MAX_BLOCK_SIZE: usize = 64;
+
+/// a compiled instruction, returning the address it wrote to (if any)
+type Op = Box<dyn Fn(&mut IntCodeEmulator) -> Result<Option<usize>, ErrorKind> + Send + Sync>;
+
+/// a compiled conditional jump, returning the address to continue from
+type Jump = Box<dyn Fn(&IntCodeEmulator) -> Result<usize, ErrorKind> + Send + Sync>;
+
+/// A parameter which is read from, with any position already validated
+#[derive(Debug, Clone, Copy)]
+enum Source {
+    Immediate(i64),
+    Position(usize),
+    Relative(i64),
+}
+
+/// A parameter which is written to, with any position already validated
+#[derive(Debug, Clone, Copy)]
+enum Target {
+    Position(usize),
+    Relative(i64),
+}
+
+/// A straight-line run of instructions compiled into a chain of closures, optionally ending in a
+/// conditional jump
+struct Block {
+    /// the address of each compiled instruction, followed by the address after the last one
+    addresses: Vec<usize>,
+    ops: Vec<Op>,
+    jump: Option<Jump>,
+}
+
+impl Block {
+    /// the number of instructions in the block, including any jump
+    fn instructions(&self) -> u64 {
+        (self.ops.len() + self.jump.is_some() as usize) as u64
+    }
+}
+
+enum Entry {
+    NotCompiled,
+    Compiled(Block),
+    Interpreted,
+}
+
+/// Runs an IntCode program by compiling it into Rust closures.
+///
+/// Code is compiled a basic block at a time as execution first reaches it. Instructions that have to
+/// yield (input and halt), or which are patched by a position mode write anywhere in the program,
+/// are never compiled and fall back to `IntCodeEmulator::step` instead. Any other write which lands
+/// on compiled code discards the affected blocks so that self-modifying programs behave exactly as
+/// they would under the interpreter.
+///
+/// This doesn't reach the order of magnitude speedup it was meant to: Day 9 runs only about three
+/// times as fast as under the interpreter, because each instruction is still a boxed closure which
+/// checks memory bounds, and blocks end at every jump.
+///
+/// A VM with a dialect or any strictness other than `Permissive` isn't compiled at all, and runs no
+/// faster than the interpreter; `is_fallback` reports when that's the case. Observers aren't
+/// supported because compiled code doesn't report individual reads and writes.
+pub struct CompiledEmulator {
+    vm: IntCodeEmulator,
+    entries: Vec<Entry>,
+
+    /// cells which belong to a compiled block, so writes to them must discard it
+    code: Vec<bool>,
+
+    /// cells which are targets of position mode writes, so are never compiled
+    patched: HashSet<usize>,
+}
+
+impl CompiledEmulator {
+    pub fn new(vm: IntCodeEmulator) -> CompiledEmulator {
+        let patched = patched_cells(&vm.ram);
+
+        CompiledEmulator {
+            vm,
+            entries: Vec::new(),
+            code: Vec::new(),
+            patched,
+        }
+    }
+
+    pub fn vm(&self) -> &IntCodeEmulator {
+        &self.vm
+    }
+
+    pub fn into_inner(self) -> IntCodeEmulator {
+        self.vm
+    }
+
+    pub fn stdin(&mut self) -> &mut std::collections::VecDeque<i64> {
+        self.vm.stdin()
+    }
+
+    pub fn stdout(&mut self) -> &mut std::collections::VecDeque<i64> {
+        self.vm.stdout()
+    }
+
+    /// returns true if the VM's settings leave every instruction to the interpreter, so running it
+    /// here is no faster than running it directly
+    pub fn is_fallback(&self) -> bool {
+        self.vm.dialect.is_some() || self.vm.strictness != Strictness::Permissive
+    }
+
+    /// writes a memory cell, discarding any compiled code which includes it
+    pub fn poke(&mut self, address: usize, value: i64) {
+        self.vm.poke(address, value);
+        self.invalidate(address);
+    }
+
+    pub fn execute(&mut self) -> Result<(), IntCodeError> {
+        let kind = match self.execute_until_yield()? {
+            YieldReason::Halted => return Ok(()),
+            YieldReason::InputRequired => ErrorKind::InputRequired,
+            YieldReason::BudgetExhausted => ErrorKind::BudgetExhausted,
+            YieldReason::DeadlineExceeded => ErrorKind::DeadlineExceeded,
+        };
+
+        Err(self.vm.error(kind))
+    }
+
+    /// executes until the VM halts, needs input, or runs out of budget or time. Compiled blocks are
+    /// only run whilst the budget can cover every instruction in them, so it's spent exactly as it
+    /// would be by the interpreter
+    pub fn execute_until_yield(&mut self) -> Result<YieldReason, IntCodeError> {
+        let mut executed: u32 = 0;
+
+        loop {
+            if let Some(reason) = self.vm.limit_reached(executed) {
+                return Ok(reason);
+            }
+            executed = executed.wrapping_add(1);
+
+            let pointer = self.vm.pointer;
+
+            // nothing beyond the end of memory can be decoded, so leave the interpreter to report
+            // it rather than growing the entries to match a wild jump
+            if pointer < self.vm.ram.len() {
+                if pointer >= self.entries.len() {
+                    self.entries.resize_with(pointer + 1, || Entry::NotCompiled);
+                }
+
+                if let Entry::NotCompiled = self.entries[pointer] {
+                    self.entries[pointer] = self.compile(pointer);
+                }
+            }
+
+            let budget = self.vm.budget;
+            let block = match self.entries.get(pointer) {
+                Some(Entry::Compiled(block))
+                    if budget.is_none_or(|b| b >= block.instructions()) =>
+                {
+                    block
+                }
+                _ => {
+                    let mut tracker = WriteTracker(None);
+                    let result = self.vm.step_with(&mut tracker)?;
+
+                    if let Some(written) = tracker.0 {
+                        self.invalidate(written);
+                    }
+
+                    match result {
+                        StepResult::Continue => continue,
+                        StepResult::InputRequired => return Ok(YieldReason::InputRequired),
+                        StepResult::Halted => return Ok(YieldReason::Halted),
+                    }
+                }
+            };
+
+            let vm = &mut self.vm;
+            let mut modified = None;
+
+            for (index, op) in block.ops.iter().enumerate() {
+                let written = match op(vm) {
+                    Ok(written) => written,
+                    Err(kind) => {
+                        // only the instructions before the failing one count against the budget
+                        vm.pointer = block.addresses[index];
+                        vm.spend(index as u64);
+                        return Err(vm.error(kind));
+                    }
+                };
+
+                if let Some(written) = written {
+                    if self.code.get(written) == Some(&true) {
+                        // the block has modified compiled code, so resume in the interpreter
+                        vm.pointer = block.addresses[index + 1];
+                        vm.spend(index as u64 + 1);
+                        modified = Some(written);
+                        break;
+                    }
+                }
+            }
+
+            if let Some(written) = modified {
+                self.invalidate(written);
+                continue;
+            }
+
+            vm.pointer = block.addresses[block.ops.len()];
+            vm.spend(block.ops.len() as u64);
+
+            if let Some(jump) = &block.jump {
+                vm.pointer = jump(vm).map_err(|kind| vm.error(kind))?;
+                vm.spend(1);
+            }
+        }
+    }
+
+    /// compiles the block starting at the pointer, if there are any instructions to compile
+    fn compile(&mut self, pointer: usize) -> Entry {
+        // dialects can change what any opcode means and strictness adds checks compiled code doesn't
+        // make, so leave everything to the interpreter
+        if self.is_fallback() {
+            return Entry::Interpreted;
+        }
+
+        let mut addresses = vec![pointer];
+        let mut ops = Vec::new();
+        let mut jump = None;
+        let mut current = pointer;
+
+        while ops.len() < MAX_BLOCK_SIZE {
+            let instruction = match Instruction::parse(&self.vm.ram, current) {
+                Ok(instruction) => instruction,
+                Err(_) => break,
+            };
+
+            let end = current + instruction.size();
+            if (current..end).any(|cell| self.patched.contains(&cell)) {
+                break;
+            }
+
+            match compile_jump(&instruction) {
+                Some(Some(compiled)) => {
+                    jump = Some(compiled);
+                    current = end;
+                    break;
+                }
+                Some(None) => break,
+                None => {}
+            }
+
+            match compile_op(&instruction) {
+                Some(op) => ops.push(op),
+                None => break,
+            }
+
+            current = end;
+            addresses.push(current);
+        }
+
+        if ops.is_empty() && jump.is_none() {
+            return Entry::Interpreted;
+        }
+
+        if current > self.code.len() {
+            self.code.resize(current, false);
+        }
+
+        for cell in &mut self.code[pointer..current] {
+            *cell = true;
+        }
+
+        Entry::Compiled(Block {
+            addresses,
+            ops,
+            jump,
+        })
+    }
+
+    /// discards every compiled block which includes the given address
+    fn invalidate(&mut self, address: usize) {
+        if self.code.get(address) != Some(&true) {
+            return;
+        }
+
+        for pointer in 0..self.entries.len() {
+            let covers = match &self.entries[pointer] {
+                Entry::Compiled(block) => {
+                    let end = match block.jump {
+                        // jumps are always three cells long
+                        Some(_) => block.addresses[block.ops.len()] + 3,
+                        None => block.addresses[block.ops.len()],
+                    };
+                    pointer <= address && address < end
+                }
+                _ => false,
+            };
+
+            if covers {
+                self.entries[pointer] = Entry::NotCompiled;
+            }
+        }
+
+        // other blocks may still cover the cell, but an extra check on a later write is harmless
+        self.code[address] = false;
+    }
+}
+
+/// remembers the address written by an interpreted instruction
+struct WriteTracker(Option<usize>);
+
+impl Observer for WriteTracker {
+    fn write(&mut self, address: usize, _old: i64, _new: i64) {
+        self.0 = Some(address);
+    }
+}
+
+impl fmt::Debug for CompiledEmulator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let blocks = self
+            .entries
+            .iter()
+            .filter(|e| matches!(e, Entry::Compiled(_)))
+            .count();
+
+        f.debug_struct("CompiledEmulator")
+            .field("vm", &self.vm)
+            .field("blocks", &blocks)
+            .finish()
+    }
+}
+
+/// finds every cell which is written by a position mode parameter, using a linear sweep
+fn patched_cells(program: &Memory) -> HashSet<usize> {
+    (0..program.len())
+        .filter_map(|pointer| Instruction::parse(program, pointer).ok())
+        .filter_map(|instruction| match instruction {
+            Instruction::Add(_, _, WriteValue::Position(p))
+            | Instruction::Multiply(_, _, WriteValue::Position(p))
+            | Instruction::Input(WriteValue::Position(p))
+            | Instruction::LessThan(_, _, WriteValue::Position(p))
+            | Instruction::Equals(_, _, WriteValue::Position(p)) => Some(p),
+            _ => None,
+        })
+        .filter(|&p| p >= 0)
+        .map(|p| p as usize)
+        .collect()
+}
+
+/// compiles a straight-line instruction, or returns None if it has to be interpreted
+fn compile_op(instruction: &Instruction) -> Option<Op> {
+    let op: Op = match *instruction {
+        Instruction::Add(left, right, dest) => {
+            let (left, right, dest) = (source(left)?, source(right)?, target(dest)?);
+            Box::new(move |vm| {
+                let value = left.get(vm)?.wrapping_add(right.get(vm)?);
+                dest.put(vm, value).map(Some)
+            })
+        }
+        Instruction::Multiply(left, right, dest) => {
+            let (left, right, dest) = (source(left)?, source(right)?, target(dest)?);
+            Box::new(move |vm| {
+                let value = left.get(vm)?.wrapping_mul(right.get(vm)?);
+                dest.put(vm, value).map(Some)
+            })
+        }
+        Instruction::LessThan(left, right, dest) => {
+            let (left, right, dest) = (source(left)?, source(right)?, target(dest)?);
+            Box::new(move |vm| {
+                let value = (left.get(vm)? < right.get(vm)?) as i64;
+                dest.put(vm, value).map(Some)
+            })
+        }
+        Instruction::Equals(left, right, dest) => {
+            let (left, right, dest) = (source(left)?, source(right)?, target(dest)?);
+            Box::new(move |vm| {
+                let value = (left.get(vm)? == right.get(vm)?) as i64;
+                dest.put(vm, value).map(Some)
+            })
+        }
+        Instruction::Output(src) => {
+            let src = source(src)?;
+            Box::new(move |vm| {
+                let value = src.get(vm)?;
+                vm.device.output.push_back(value);
+                Ok(None)
+            })
+        }
+        Instruction::AdjustBase(offset) => {
+            let offset = source(offset)?;
+            Box::new(move |vm| {
+                vm.base = vm.base.wrapping_add(offset.get(vm)?);
+                Ok(None)
+            })
+        }
+        _ => return None,
+    };
+
+    Some(op)
+}
+
+/// compiles a conditional jump. Returns None if the instruction isn't a jump at all, and Some(None)
+/// if it's a jump which has to be interpreted
+fn compile_jump(instruction: &Instruction) -> Option<Option<Jump>> {
+    let (condition, dest, when) = match *instruction {
+        Instruction::JumpTrue(condition, dest) => (condition, dest, true),
+        Instruction::JumpFalse(condition, dest) => (condition, dest, false),
+        _ => return None,
+    };
+
+    let (condition, dest) = match (source(condition), source(dest)) {
+        (Some(condition), Some(dest)) => (condition, dest),
+        _ => return Some(None),
+    };
+
+    Some(Some(Box::new(move |vm| {
+        if (condition.get(vm)? != 0) == when {
+            address(dest.get(vm)?)
+        } else {
+            Ok(vm.pointer + 3)
+        }
+    })))
+}
+
+fn source(value: ReadValue) -> Option<Source> {
+    match value {
+        ReadValue::Immediate(value) => Some(Source::Immediate(value)),
+        ReadValue::Position(position) => address(position).ok().map(Source::Position),
+        ReadValue::Relative(offset) => Some(Source::Relative(offset)),
+    }
+}
+
+fn target(value: WriteValue) -> Option<Target> {
+    match value {
+        WriteValue::Position(position) => address(position).ok().map(Target::Position),
+        WriteValue::Relative(offset) => Some(Target::Relative(offset)),
+    }
+}
+
+impl Source {
+    #[inline]
+    fn get(self, vm: &IntCodeEmulator) -> Result<i64, ErrorKind> {
+        let position = match self {
+            Source::Immediate(value) => return Ok(value),
+            Source::Position(position) => position,
+            Source::Relative(offset) => address(vm.base.wrapping_add(offset))?,
+        };
+
+        Ok(vm.peek(position))
+    }
+}
+
+impl Target {
+    #[inline]
+    fn put(self, vm: &mut IntCodeEmulator, value: i64) -> Result<usize, ErrorKind> {
+        let position = match self {
+            Target::Position(position) => position,
+            Target::Relative(offset) => address(vm.base.wrapping_add(offset))?,
+        };
+
+        vm.ram.check(position)?;
+        vm.poke(position, value);
+        Ok(position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::assembler::assemble;
+    use std::time::Instant;
+
+    /// runs a program on both the interpreter and the compiler, checking they behave identically
+    fn differential(program: Vec<i64>, inputs: &[i64]) -> Vec<i64> {
+        let mut interpreted = IntCodeEmulator::new(program);
+        let mut compiled = CompiledEmulator::new(interpreted.clone());
+        assert!(!compiled.is_fallback());
+
+        interpreted.stdin().extend(inputs);
+        compiled.stdin().extend(inputs);
+
+        let expected = interpreted.execute_until_yield();
+        let actual = compiled.execute_until_yield();
+
+        assert_eq!(actual, expected);
+        assert_eq!(compiled.vm().ram(), interpreted.ram());
+        assert_eq!(compiled.vm().pointer(), interpreted.pointer());
+        assert_eq!(compiled.vm().base(), interpreted.base());
+        assert_eq!(compiled.stdout(), interpreted.stdout());
+
+        interpreted.stdout().iter().copied().collect()
+    }
+
+    #[test]
+    fn matches_interpreter_on_puzzles() {
+        let day05 = IntCodeEmulator::parse_input(include_str!("../../input/2019/day5.txt"));
+        let day09 = IntCodeEmulator::parse_input(include_str!("../../input/2019/day9.txt"));
+
+        assert_eq!(differential(day05.clone(), &[1]).last(), Some(&7_988_899));
+        assert_eq!(differential(day05, &[5]), vec![13_758_663]);
+        assert_eq!(differential(day09.clone(), &[1]), vec![4_006_117_640]);
+        assert_eq!(differential(day09, &[2]), vec![88231]);
+    }
+
+    #[test]
+    fn matches_interpreter_on_self_modifying_code() {
+        // the loop patches the immediate operand of its own output instruction via a relative
+        // write, so the compiled block must be discarded each time round
+        let program = assemble(
+            "
+                    arb #loop+1
+            loop:   out #0
+                    add [rb+0], #1, [rb+0]
+                    add [count], #-1, [count]
+                    jt [count], #loop
+                    hlt
+            count:  data 3
+            ",
+        )
+        .unwrap();
+
+        assert_eq!(differential(program, &[]), vec![0, 1, 2]);
+
+        // an interpreted input instruction patches the operand of a compiled output instruction
+        let program = assemble(
+            "
+            start:  out #5
+                    jf [done], #read
+                    hlt
+            read:   arb #start+1
+                    in [rb+0]
+                    add #1, #0, [done]
+                    jt #1, #start
+            done:   data 0
+            ",
+        )
+        .unwrap();
+
+        assert_eq!(differential(program, &[9]), vec![5, 9]);
+    }
+
+    #[test]
+    fn matches_interpreter_on_errors() {
+        let program = assemble("arb #-5\nout [rb+1]\nhlt").unwrap();
+        differential(program, &[]);
+    }
+
+    #[test]
+    fn matches_interpreter_on_wild_jumps() {
+        let program = assemble("jt #1, #1000000000000").unwrap();
+        differential(program, &[]);
+    }
+
+    #[test]
+    fn keeps_strictness_checks() {
+        for strictness in [Strictness::Legacy, Strictness::Strict].iter() {
+            let mut vm = IntCodeEmulator::new(vec![1101, 2, 3, 100, 4, 100, 204, 0, 99]);
+            vm.set_strictness(*strictness);
+
+            let mut compiled = CompiledEmulator::new(vm.clone());
+            assert!(compiled.is_fallback());
+            assert_eq!(compiled.execute_until_yield(), vm.execute_until_yield());
+            assert_eq!(compiled.stdout(), vm.stdout());
+        }
+    }
+
+    #[test]
+    fn spends_budget_like_interpreter() {
+        let program = IntCodeEmulator::parse_input(include_str!("../../input/2019/day9.txt"));
+
+        for budget in [1, 10, 100, 1000].iter() {
+            let mut interpreted = IntCodeEmulator::new(program.clone());
+            interpreted.stdin().push_back(2);
+            interpreted.set_instruction_budget(Some(*budget));
+
+            let mut compiled = CompiledEmulator::new(interpreted.clone());
+
+            assert_eq!(
+                compiled.execute_until_yield(),
+                interpreted.execute_until_yield()
+            );
+            assert_eq!(compiled.vm().pointer(), interpreted.pointer());
+            assert_eq!(compiled.vm().ram(), interpreted.ram());
+            assert_eq!(
+                compiled.vm().instruction_budget(),
+                interpreted.instruction_budget()
+            );
+        }
+    }
+
+    #[test]
+    fn spends_budget_like_interpreter_on_errors() {
+        let programs = [
+            // fails part way through a block
+            "add #1, #2, [100]\nadd #3, #4, [101]\narb #-5\nout [rb+1]\nhlt",
+            // fails on the jump at the end of a block
+            "add #1, #2, [100]\njt #1, #-1",
+        ];
+
+        for source in programs.iter() {
+            let mut interpreted = IntCodeEmulator::new(assemble(source).unwrap());
+            interpreted.set_instruction_budget(Some(100));
+
+            let mut compiled = CompiledEmulator::new(interpreted.clone());
+
+            assert_eq!(
+                compiled.execute_until_yield(),
+                interpreted.execute_until_yield()
+            );
+            assert_eq!(
+                compiled.vm().instruction_budget(),
+                interpreted.instruction_budget()
+            );
+        }
+    }
+
+    /// run with `cargo test --release -- --ignored --nocapture` to compare the engines
+    #[test]
+    #[ignore]
+    fn benchmark_compiler() {
+        let program = IntCodeEmulator::parse_input(include_str!("../../input/2019/day9.txt"));
+
+        let start = Instant::now();
+        let mut vm = IntCodeEmulator::new(program.clone());
+        vm.stdin().push_back(2);
+        vm.execute().unwrap();
+        println!("interpreter: {:?}", start.elapsed());
+
+        let start = Instant::now();
+        let mut vm = CompiledEmulator::new(IntCodeEmulator::new(program));
+        vm.stdin().push_back(2);
+        vm.execute().unwrap();
+        println!("compiler:    {:?}", start.elapsed());
+    }
+}
@@ -0,0 +1,482 @@
+use crate::intcode::history::History;
+use crate::intcode::{Instruction, IntCodeEmulator, IntCodeError, StepResult, YieldReason};
+use std::collections::BTreeSet;
+use std::io::{self, BufRead, Write};
+
+/// Why the debugger handed control back to the caller
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stop {
+    /// a single instruction was executed
+    Stepped,
+
+    /// the pointer reached an instruction with a breakpoint
+    Breakpoint(usize),
+
+    /// a watched memory cell changed value
+    Watchpoint { address: usize, old: i64, new: i64 },
+
+    /// the relative base dropped below where it was when `finish` was called
+    Returned,
+
+    /// the VM needs input or has halted
+    Yielded(YieldReason),
+}
+
+/// Wraps an emulator with breakpoints and watchpoints so it can be stepped through.
+///
+/// Every step is recorded in a history, so execution can also be stepped backwards, either an
+/// instruction at a time or back to the last write of a memory cell.
+#[derive(Debug)]
+pub struct Debugger {
+    vm: IntCodeEmulator,
+    breakpoints: BTreeSet<usize>,
+    watchpoints: BTreeSet<usize>,
+    history: History,
+}
+
+impl Debugger {
+    pub fn new(vm: IntCodeEmulator) -> Debugger {
+        Debugger {
+            vm,
+            breakpoints: BTreeSet::new(),
+            watchpoints: BTreeSet::new(),
+            history: History::new(),
+        }
+    }
+
+    pub fn vm(&self) -> &IntCodeEmulator {
+        &self.vm
+    }
+
+    pub fn vm_mut(&mut self) -> &mut IntCodeEmulator {
+        &mut self.vm
+    }
+
+    pub fn into_inner(self) -> IntCodeEmulator {
+        self.vm
+    }
+
+    pub fn breakpoints(&self) -> &BTreeSet<usize> {
+        &self.breakpoints
+    }
+
+    pub fn add_breakpoint(&mut self, address: usize) -> bool {
+        self.breakpoints.insert(address)
+    }
+
+    pub fn remove_breakpoint(&mut self, address: usize) -> bool {
+        self.breakpoints.remove(&address)
+    }
+
+    pub fn watchpoints(&self) -> &BTreeSet<usize> {
+        &self.watchpoints
+    }
+
+    pub fn add_watchpoint(&mut self, address: usize) -> bool {
+        self.watchpoints.insert(address)
+    }
+
+    pub fn remove_watchpoint(&mut self, address: usize) -> bool {
+        self.watchpoints.remove(&address)
+    }
+
+    pub fn history(&self) -> &History {
+        &self.history
+    }
+
+    pub fn history_mut(&mut self) -> &mut History {
+        &mut self.history
+    }
+
+    /// executes a single instruction, ignoring breakpoints
+    pub fn step(&mut self) -> Result<Stop, IntCodeError> {
+        let watched: Vec<(usize, i64)> = self
+            .watchpoints
+            .iter()
+            .map(|&address| (address, self.vm.peek(address)))
+            .collect();
+
+        match self.history.step(&mut self.vm)? {
+            StepResult::Continue => {}
+            StepResult::InputRequired => return Ok(Stop::Yielded(YieldReason::InputRequired)),
+            StepResult::Halted => return Ok(Stop::Yielded(YieldReason::Halted)),
+        }
+
+        let changed = watched
+            .into_iter()
+            .map(|(address, old)| (address, old, self.vm.peek(address)))
+            .find(|&(_, old, new)| old != new);
+
+        match changed {
+            Some((address, old, new)) => Ok(Stop::Watchpoint { address, old, new }),
+            None => Ok(Stop::Stepped),
+        }
+    }
+
+    /// undoes up to `count` instructions, returning the number undone
+    pub fn step_back(&mut self, count: usize) -> usize {
+        (0..count)
+            .take_while(|_| self.history.undo(&mut self.vm).is_some())
+            .count()
+    }
+
+    /// undoes instructions until the one which last wrote to a cell is about to execute again,
+    /// returning false and leaving the VM alone if the write isn't in the history
+    pub fn rewind_to_write(&mut self, address: usize) -> bool {
+        match self.history.last_write(address) {
+            Some(steps) => {
+                self.step_back(steps);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// runs until a breakpoint or watchpoint is hit, or the VM yields
+    pub fn resume(&mut self) -> Result<Stop, IntCodeError> {
+        self.run_until(|_| false)
+    }
+
+    /// runs until the current stack frame is released, i.e. the relative base drops below its
+    /// current value, stopping early for breakpoints, watchpoints and yields
+    pub fn finish(&mut self) -> Result<Stop, IntCodeError> {
+        let base = self.vm.base();
+        self.run_until(|vm| vm.base() < base)
+    }
+
+    fn run_until<F>(&mut self, returned: F) -> Result<Stop, IntCodeError>
+    where
+        F: Fn(&IntCodeEmulator) -> bool,
+    {
+        loop {
+            // always make progress so that resuming from a breakpoint doesn't stop straight away
+            match self.step()? {
+                Stop::Stepped => {}
+                stop => return Ok(stop),
+            }
+
+            if returned(&self.vm) {
+                return Ok(Stop::Returned);
+            }
+
+            if self.breakpoints.contains(&self.vm.pointer()) {
+                return Ok(Stop::Breakpoint(self.vm.pointer()));
+            }
+        }
+    }
+
+    /// describes the instruction at the given address
+    pub fn describe(&self, address: usize) -> String {
+        match Instruction::parse(self.vm.ram(), address) {
+            Ok(instruction) => format!("{:04}  {}", address, instruction),
+            Err(kind) => format!("{:04}  <{}>", address, kind),
+        }
+    }
+
+    /// runs an interactive debugging session, reading commands from `input` until it's exhausted
+    /// or the user quits
+    pub fn repl<R: BufRead, W: Write>(&mut self, input: R, mut output: W) -> io::Result<()> {
+        writeln!(output, "{}", self.describe(self.vm.pointer()))?;
+
+        for line in input.lines() {
+            let line = line?;
+            let words: Vec<&str> = line.split_whitespace().collect();
+
+            if words.is_empty() {
+                continue;
+            }
+
+            match self.command(&words, &mut output) {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(message) => writeln!(output, "error: {}", message)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// executes a single REPL command, returning false if the session should end
+    fn command<W: Write>(&mut self, words: &[&str], output: &mut W) -> Result<bool, String> {
+        let number = |index: usize| -> Result<i64, String> {
+            let word = words
+                .get(index)
+                .ok_or_else(|| format!("'{}' needs more arguments", words[0]))?;
+            word.parse()
+                .map_err(|_| format!("'{}' is not a number", word))
+        };
+        let address = |index: usize| -> Result<usize, String> {
+            let value = number(index)?;
+            if value < 0 {
+                return Err(format!("{} is not a valid address", value));
+            }
+            Ok(value as usize)
+        };
+        let write =
+            |output: &mut W, text: String| writeln!(output, "{}", text).map_err(|e| e.to_string());
+
+        match words[0] {
+            "s" | "step" => {
+                let count = if words.len() > 1 { address(1)? } else { 1 };
+                let mut stop = Stop::Stepped;
+
+                for _ in 0..count {
+                    stop = self.step().map_err(|e| e.to_string())?;
+                    if stop != Stop::Stepped {
+                        break;
+                    }
+                }
+
+                self.report(stop, output)?;
+            }
+            "bs" | "back" => {
+                let count = if words.len() > 1 { address(1)? } else { 1 };
+                if self.step_back(count) == 0 {
+                    return Err("no history to step back through".to_string());
+                }
+                write(output, self.describe(self.vm.pointer()))?;
+            }
+            "rw" | "rewind" => {
+                let address = address(1)?;
+                if !self.rewind_to_write(address) {
+                    return Err(format!("no write to {} in history", address));
+                }
+                write(output, self.describe(self.vm.pointer()))?;
+            }
+            "c" | "continue" => {
+                let stop = self.resume().map_err(|e| e.to_string())?;
+                self.report(stop, output)?;
+            }
+            "f" | "finish" => {
+                let stop = self.finish().map_err(|e| e.to_string())?;
+                self.report(stop, output)?;
+            }
+            "b" | "break" => {
+                self.add_breakpoint(address(1)?);
+            }
+            "d" | "delete" => {
+                self.remove_breakpoint(address(1)?);
+            }
+            "w" | "watch" => {
+                self.add_watchpoint(address(1)?);
+            }
+            "unwatch" => {
+                self.remove_watchpoint(address(1)?);
+            }
+            "i" | "info" => {
+                write(
+                    output,
+                    format!("pointer: {}\nbase: {}", self.vm.pointer(), self.vm.base()),
+                )?;
+                write(output, format!("stdin: {:?}", self.vm.stdin()))?;
+                write(output, format!("stdout: {:?}", self.vm.stdout()))?;
+                write(output, format!("breakpoints: {:?}", self.breakpoints))?;
+                write(output, format!("watchpoints: {:?}", self.watchpoints))?;
+            }
+            "x" | "examine" => {
+                let start = address(1)?;
+                let count = if words.len() > 2 { address(2)? } else { 1 };
+                let cells: Vec<String> = (0..count)
+                    .map_while(|offset| start.checked_add(offset))
+                    .map(|a| self.vm.peek(a).to_string())
+                    .collect();
+                write(output, format!("{:04}  {}", start, cells.join(" ")))?;
+            }
+            "set" => {
+                let (address, value) = (address(1)?, number(2)?);
+                self.vm.ram.check(address).map_err(|e| e.to_string())?;
+                self.vm.poke(address, value);
+            }
+            "l" | "list" => {
+                let mut pointer = if words.len() > 1 {
+                    address(1)?
+                } else {
+                    self.vm.pointer()
+                };
+                let count = if words.len() > 2 { address(2)? } else { 10 };
+
+                for _ in 0..count {
+                    write(output, self.describe(pointer))?;
+                    let size = Instruction::parse(self.vm.ram(), pointer).map_or(1, |i| i.size());
+                    pointer = pointer.saturating_add(size);
+                }
+            }
+            "input" => {
+                for index in 1..words.len() {
+                    let value = number(index)?;
+                    self.vm.stdin().push_back(value);
+                }
+            }
+            "output" => {
+                let values: Vec<i64> = self.vm.stdout().drain(..).collect();
+                write(output, format!("{:?}", values))?;
+            }
+            "q" | "quit" => return Ok(false),
+            "h" | "help" => write(output, HELP.to_string())?,
+            other => return Err(format!("unknown command '{}', try 'help'", other)),
+        }
+
+        Ok(true)
+    }
+
+    fn report<W: Write>(&self, stop: Stop, output: &mut W) -> Result<(), String> {
+        let message = match stop {
+            Stop::Stepped => None,
+            Stop::Breakpoint(address) => Some(format!("breakpoint at {}", address)),
+            Stop::Watchpoint { address, old, new } => Some(format!(
+                "watchpoint {} changed from {} to {}",
+                address, old, new
+            )),
+            Stop::Returned => Some("returned from frame".to_string()),
+            Stop::Yielded(YieldReason::InputRequired) => Some("input required".to_string()),
+            Stop::Yielded(YieldReason::Halted) => Some("halted".to_string()),
+            Stop::Yielded(YieldReason::BudgetExhausted) => {
+                Some("instruction budget exhausted".to_string())
+            }
+            Stop::Yielded(YieldReason::DeadlineExceeded) => Some("deadline exceeded".to_string()),
+        };
+
+        if let Some(message) = message {
+            writeln!(output, "{}", message).map_err(|e| e.to_string())?;
+        }
+
+        writeln!(output, "{}", self.describe(self.vm.pointer())).map_err(|e| e.to_string())
+    }
+}
+
+const HELP: &str = "\
+step|s [n]            execute n instructions (default 1)
+back|bs [n]           undo n instructions (default 1)
+rewind|rw <addr>      undo instructions back to the last write of a memory cell
+continue|c            run until a breakpoint, watchpoint, input request or halt
+finish|f              run until the current relative base frame is released
+break|b <addr>        add a breakpoint
+delete|d <addr>       remove a breakpoint
+watch|w <addr>        stop when a memory cell changes
+unwatch <addr>        remove a watchpoint
+info|i                show pointer, base, stdin, stdout, breakpoints and watchpoints
+examine|x <addr> [n]  show n memory cells (default 1)
+set <addr> <value>    write a memory cell
+list|l [addr] [n]     disassemble n instructions (default from the pointer)
+input <values...>     queue values on stdin
+output                show and clear stdout
+quit|q                end the session";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::assembler::assemble;
+
+    fn debugger(source: &str) -> Debugger {
+        Debugger::new(IntCodeEmulator::new(assemble(source).unwrap()))
+    }
+
+    const COUNTDOWN: &str = "
+                in [counter]
+        loop:   add [counter], #-1, [counter]
+                jt [counter], #loop
+                out #42
+                hlt
+        counter: data 0
+    ";
+
+    #[test]
+    fn stops_at_breakpoints() {
+        let mut debugger = debugger(COUNTDOWN);
+        debugger.vm_mut().stdin().push_back(3);
+        debugger.add_breakpoint(2);
+
+        assert_eq!(debugger.resume().unwrap(), Stop::Breakpoint(2));
+        assert_eq!(debugger.resume().unwrap(), Stop::Breakpoint(2));
+        assert_eq!(debugger.resume().unwrap(), Stop::Breakpoint(2));
+        assert_eq!(
+            debugger.resume().unwrap(),
+            Stop::Yielded(YieldReason::Halted)
+        );
+    }
+
+    #[test]
+    fn stops_at_watchpoints() {
+        let mut debugger = debugger(COUNTDOWN);
+        debugger.vm_mut().stdin().push_back(2);
+        debugger.add_watchpoint(12);
+
+        let stop = debugger.resume().unwrap();
+        assert_eq!(
+            stop,
+            Stop::Watchpoint {
+                address: 12,
+                old: 0,
+                new: 2
+            }
+        );
+        assert_eq!(debugger.vm().pointer(), 2);
+    }
+
+    #[test]
+    fn finishes_frames() {
+        let mut debugger = debugger(
+            "
+                arb #10
+                add #1, #2, [rb+0]
+                arb #-10
+                hlt
+            ",
+        );
+
+        debugger.step().unwrap();
+        assert_eq!(debugger.finish().unwrap(), Stop::Returned);
+        assert_eq!(debugger.vm().pointer(), 8);
+    }
+
+    #[test]
+    fn steps_backwards() {
+        let mut debugger = debugger(COUNTDOWN);
+        debugger.vm_mut().stdin().push_back(3);
+        debugger.add_breakpoint(11);
+
+        assert_eq!(debugger.resume().unwrap(), Stop::Breakpoint(11));
+        assert_eq!(debugger.vm_mut().stdout(), &[42]);
+
+        // back over the output to the final decrement of the counter
+        assert_eq!(debugger.step_back(1), 1);
+        assert!(debugger.vm_mut().stdout().is_empty());
+        assert!(debugger.rewind_to_write(12));
+        assert_eq!((debugger.vm().pointer(), debugger.vm().peek(12)), (2, 1));
+
+        assert_eq!(debugger.step_back(100), 5);
+        assert_eq!((debugger.vm().pointer(), debugger.vm().peek(12)), (0, 0));
+        assert_eq!(debugger.vm_mut().stdin(), &[3]);
+        assert!(!debugger.rewind_to_write(12));
+    }
+
+    #[test]
+    fn runs_repl_commands() {
+        let mut debugger = debugger(COUNTDOWN);
+        let commands = "input 1\nb 11\nc\nset 12 7\nx 12\ns\ni\nq\nstep";
+        let mut output = Vec::new();
+
+        debugger.repl(commands.as_bytes(), &mut output).unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("breakpoint at 11\n0011  hlt"));
+        assert!(output.contains("0012  7"));
+        assert!(output.contains("halted"));
+        assert!(output.contains("stdout: [42]"));
+    }
+
+    #[test]
+    fn rejects_out_of_range_repl_commands() {
+        let mut debugger = debugger(COUNTDOWN);
+        debugger.vm_mut().set_memory_limit(Some(1 << 20));
+        let commands = "x 9223372036854775807 2\nl 9223372036854775807 2\nset 1000000000000 1\nq";
+        let mut output = Vec::new();
+
+        debugger.repl(commands.as_bytes(), &mut output).unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("9223372036854775807  0 0\n"));
+        assert!(output.contains("error: write to 1000000000000 exceeds the memory limit"));
+        assert_eq!(debugger.vm().peek(1_000_000_000_000), 0);
+    }
+}
@@ -0,0 +1,927 @@
+use crate::intcode::graph::{self, Block, Exit, Graph};
+use crate::intcode::{Instruction, ReadValue, WriteValue};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::Write;
+
+/// stands in for the exit of a function when working out post-dominators
+const EXIT: usize = usize::MAX;
+
+/// A condition tested by a branch, as `left op right`
+#[derive(Debug, Clone, PartialEq)]
+struct Cond {
+    left: String,
+    op: &'static str,
+    right: String,
+}
+
+impl Cond {
+    fn negate(&self) -> Cond {
+        let op = match self.op {
+            "<" => ">=",
+            ">=" => "<",
+            "==" => "!=",
+            _ => "==",
+        };
+
+        Cond {
+            left: self.left.clone(),
+            op,
+            right: self.right.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Stmt {
+    /// the start of a block, which is only shown if something jumps to it with a goto
+    Label(usize),
+    Line(String),
+    If {
+        cond: Cond,
+        then: Vec<Stmt>,
+        otherwise: Vec<Stmt>,
+    },
+    Loop(Vec<Stmt>),
+    While {
+        cond: Cond,
+        body: Vec<Stmt>,
+    },
+    Goto(usize),
+    Break,
+    Continue,
+}
+
+/// What a relative base slot in a function's frame is used for
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Slot {
+    /// read before it's written, so it was passed in by the caller
+    Argument,
+    Local,
+}
+
+/// The loop currently being structured
+struct LoopContext {
+    header: usize,
+    exit: Option<usize>,
+}
+
+/// A function found from the calls in a program, with everything needed to structure it
+struct Function<'a> {
+    entry: usize,
+    graph: &'a Graph,
+    blocks: BTreeSet<usize>,
+
+    /// the size of the stack frame allocated by an `arb` at the entry point
+    frame: i64,
+
+    /// how far the base has moved since entry at the start of each block, if it's known
+    deltas: HashMap<usize, Option<i64>>,
+    slots: BTreeMap<i64, Slot>,
+    post_dominators: HashMap<usize, BTreeSet<usize>>,
+    emitted: BTreeSet<usize>,
+}
+
+/// the successors of a block within its function, so calls continue where they return to
+fn successors(block: &Block) -> Vec<usize> {
+    match block.exit {
+        Exit::Call { returns, .. } => vec![returns],
+        exit => exit.successors(),
+    }
+}
+
+/// the name of a function in the pseudocode
+fn function_name(entry: usize) -> String {
+    match entry {
+        0 => "main".to_string(),
+        _ => format!("f{}", entry),
+    }
+}
+
+impl<'a> Function<'a> {
+    fn new(graph: &'a Graph, entry: usize) -> Function<'a> {
+        let mut blocks = BTreeSet::new();
+        let mut pending = vec![entry];
+
+        while let Some(start) = pending.pop() {
+            if graph.blocks.contains_key(&start) && blocks.insert(start) {
+                pending.extend(successors(&graph.blocks[&start]));
+            }
+        }
+
+        let frame = match graph
+            .blocks
+            .get(&entry)
+            .and_then(|b| b.instructions.first())
+        {
+            Some((_, Instruction::AdjustBase(ReadValue::Immediate(size))))
+                if entry != 0 && *size > 0 =>
+            {
+                *size
+            }
+            _ => 0,
+        };
+
+        let mut function = Function {
+            entry,
+            graph,
+            blocks,
+            frame,
+            deltas: HashMap::new(),
+            slots: BTreeMap::new(),
+            post_dominators: HashMap::new(),
+            emitted: BTreeSet::new(),
+        };
+
+        function.track_base();
+        function.classify_slots();
+        function.find_post_dominators();
+        function
+    }
+
+    fn block(&self, start: usize) -> &'a Block {
+        &self.graph.blocks[&start]
+    }
+
+    /// works out how far the base has moved from its value on entry at the start of each block,
+    /// following only adjustments by constants. Calls are assumed to restore the base
+    fn track_base(&mut self) {
+        let mut pending = vec![(self.entry, Some(0))];
+
+        while let Some((start, delta)) = pending.pop() {
+            match self.deltas.get(&start) {
+                Some(&known) if known == delta || known.is_none() => continue,
+                Some(_) => {
+                    // reached with different adjustments, so the base isn't known here
+                    self.deltas.insert(start, None);
+                }
+                None => {
+                    self.deltas.insert(start, delta);
+                }
+            }
+
+            let block = self.block(start);
+            let delta = self.deltas[&start];
+            let end = block
+                .instructions
+                .iter()
+                .fold(delta, |delta, (_, instruction)| adjust(delta, instruction));
+
+            for next in successors(block) {
+                if self.blocks.contains(&next) {
+                    pending.push((next, end));
+                }
+            }
+        }
+    }
+
+    /// decides whether each slot in the frame is an argument or a local, from how it's first used
+    fn classify_slots(&mut self) {
+        for &start in &self.blocks {
+            let mut delta = self.deltas.get(&start).copied().flatten();
+
+            for (_, instruction) in &self.block(start).instructions {
+                if let Some(delta) = delta {
+                    let (reads, writes) = relative_operands(instruction);
+
+                    for slot in reads.into_iter().filter_map(|o| delta.checked_add(o)) {
+                        self.slots.entry(slot).or_insert(Slot::Argument);
+                    }
+                    for slot in writes.into_iter().filter_map(|o| delta.checked_add(o)) {
+                        self.slots.entry(slot).or_insert(Slot::Local);
+                    }
+                }
+
+                delta = adjust(delta, instruction);
+            }
+        }
+    }
+
+    /// works out which blocks every path from each block to the end of the function must pass
+    /// through
+    fn find_post_dominators(&mut self) {
+        let everything: BTreeSet<usize> = self.blocks.iter().copied().chain(Some(EXIT)).collect();
+
+        for &start in &self.blocks {
+            self.post_dominators.insert(start, everything.clone());
+        }
+        self.post_dominators
+            .insert(EXIT, Some(EXIT).into_iter().collect());
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+
+            for &start in self.blocks.iter().rev() {
+                let mut next = successors(self.block(start));
+                next.retain(|n| self.blocks.contains(n));
+                if next.is_empty() {
+                    next.push(EXIT);
+                }
+
+                let mut dominators = next
+                    .iter()
+                    .map(|n| self.post_dominators[n].clone())
+                    .fold(None, |acc: Option<BTreeSet<usize>>, set| match acc {
+                        Some(acc) => Some(acc.intersection(&set).copied().collect()),
+                        None => Some(set),
+                    })
+                    .unwrap_or_default();
+                dominators.insert(start);
+
+                if dominators != self.post_dominators[&start] {
+                    self.post_dominators.insert(start, dominators);
+                    changed = true;
+                }
+            }
+        }
+    }
+
+    /// the nearest block that every path from a block passes through, where its branches join
+    fn join(&self, start: usize) -> Option<usize> {
+        self.post_dominators[&start]
+            .iter()
+            .filter(|&&d| d != start)
+            .max_by_key(|d| self.post_dominators[d].len())
+            .copied()
+            .filter(|&d| d != EXIT)
+    }
+
+    /// the blocks in the loop headed by a block, if any jump back to it
+    fn loop_body(&self, header: usize) -> Option<BTreeSet<usize>> {
+        let latches: Vec<usize> = self
+            .blocks
+            .range(header..)
+            .copied()
+            .filter(|&b| successors(self.block(b)).contains(&header))
+            .collect();
+
+        if latches.is_empty() {
+            return None;
+        }
+
+        let mut body: BTreeSet<usize> = Some(header).into_iter().collect();
+        let mut pending = latches;
+
+        while let Some(start) = pending.pop() {
+            if body.insert(start) {
+                pending.extend(
+                    self.blocks
+                        .iter()
+                        .copied()
+                        .filter(|&p| successors(self.block(p)).contains(&start)),
+                );
+            }
+        }
+
+        Some(body)
+    }
+
+    /// names a slot relative to the base, given how far the base has moved since entry
+    fn relative(&self, delta: Option<i64>, offset: i64) -> String {
+        let slot = match delta.and_then(|delta| delta.checked_add(offset)) {
+            Some(slot) => slot,
+            None => return format!("rb[{}]", offset),
+        };
+
+        // the base starts at zero, so outside a function it's just an address
+        if self.entry == 0 {
+            return format!("mem[{}]", slot);
+        }
+
+        match (slot, self.slots.get(&slot)) {
+            (slot, _) if slot < 0 => format!("caller{}", slot.unsigned_abs()),
+            (0, _) if self.frame > 0 => "return_address".to_string(),
+            (slot, _) if self.frame > 0 && slot >= self.frame => {
+                format!("out{}", slot - self.frame)
+            }
+            (slot, Some(Slot::Argument)) => format!("arg{}", slot),
+            (slot, _) => format!("local{}", slot),
+        }
+    }
+
+    fn read(&self, delta: Option<i64>, value: &ReadValue) -> String {
+        match *value {
+            ReadValue::Immediate(value) => value.to_string(),
+            ReadValue::Position(address) => format!("mem[{}]", address),
+            ReadValue::Relative(offset) => self.relative(delta, offset),
+        }
+    }
+
+    fn write(&self, delta: Option<i64>, value: &WriteValue) -> String {
+        match *value {
+            WriteValue::Position(address) => format!("mem[{}]", address),
+            WriteValue::Relative(offset) => self.relative(delta, offset),
+        }
+    }
+
+    /// the statements for a block's instructions, leaving out the jump that ends it, along with
+    /// the branch condition if it ends in one
+    fn statements(&self, start: usize) -> (Vec<Stmt>, Option<Cond>) {
+        let block = self.block(start);
+        let mut delta = self.deltas.get(&start).copied().flatten();
+        let mut lines = Vec::new();
+        let mut cond = None;
+        let mut call_arguments = BTreeMap::new();
+
+        let last = block.instructions.len().saturating_sub(1);
+
+        for (index, (_, instruction)) in block.instructions.iter().enumerate() {
+            let read = |value: &ReadValue| self.read(delta, value);
+            let write = |value: &WriteValue| self.write(delta, value);
+
+            // frames are implied by functions, so their setup and tear down isn't shown
+            let prologue = index == 0 && start == self.entry && self.frame > 0;
+            let epilogue = index + 1 == last && block.exit == Exit::Return;
+
+            let line = match instruction {
+                Instruction::AdjustBase(_) if prologue || epilogue => None,
+                Instruction::Add(l, r, d) => {
+                    Some(format!("{} = {}", write(d), sum(read(l), read(r))))
+                }
+                Instruction::Multiply(l, r, d) => {
+                    Some(format!("{} = {}", write(d), product(read(l), read(r))))
+                }
+                Instruction::LessThan(l, r, d) => {
+                    Some(format!("{} = {} < {}", write(d), read(l), read(r)))
+                }
+                Instruction::Equals(l, r, d) => {
+                    Some(format!("{} = {} == {}", write(d), read(l), read(r)))
+                }
+                Instruction::Input(d) => Some(format!("{} = input()", write(d))),
+                Instruction::Output(v) => Some(format!("output({})", read(v))),
+                Instruction::AdjustBase(v) => Some(format!("rb += {}", read(v))),
+                Instruction::Halt => Some("halt".to_string()),
+                Instruction::JumpTrue(c, _) | Instruction::JumpFalse(c, _) if index == last => {
+                    if let Exit::Branch { .. } = block.exit {
+                        let jump_if = matches!(instruction, Instruction::JumpTrue(..));
+                        cond = Some(self.condition(&mut lines, delta, c, jump_if));
+                    }
+                    None
+                }
+                // jumps which are never taken do nothing
+                Instruction::JumpTrue(..) | Instruction::JumpFalse(..) => None,
+            };
+
+            // values stored just above the base before a call are its arguments
+            if let (Exit::Call { returns, .. }, Some(WriteValue::Relative(offset))) =
+                (block.exit, written(instruction))
+            {
+                let value = match instruction {
+                    Instruction::Add(l, r, _) => sum(read(l), read(r)),
+                    Instruction::Multiply(l, r, _) => product(read(l), read(r)),
+                    _ => String::new(),
+                };
+
+                if offset == 0 && value == returns.to_string() {
+                    continue;
+                }
+                if offset > 0 && !value.is_empty() {
+                    call_arguments.insert(offset, (lines.len(), value));
+                }
+            }
+
+            if let Some(line) = line {
+                lines.push(Stmt::Line(line));
+            }
+
+            delta = adjust(delta, instruction);
+        }
+
+        if let Exit::Call { target, .. } = block.exit {
+            // only values stored after the last other use of the slots are arguments
+            let arguments: Vec<String> = call_arguments
+                .values()
+                .map(|(_, value)| value.clone())
+                .collect();
+            let positions: BTreeSet<usize> =
+                call_arguments.values().map(|&(index, _)| index).collect();
+
+            lines = lines
+                .into_iter()
+                .enumerate()
+                .filter(|(index, _)| !positions.contains(index))
+                .map(|(_, line)| line)
+                .collect();
+            lines.push(Stmt::Line(format!(
+                "{}({})",
+                function_name(target),
+                arguments.join(", ")
+            )));
+        }
+
+        (lines, cond)
+    }
+
+    /// the condition under which a branch is taken, folding in a comparison made just before it
+    fn condition(
+        &self,
+        lines: &mut Vec<Stmt>,
+        delta: Option<i64>,
+        tested: &ReadValue,
+        jump_if: bool,
+    ) -> Cond {
+        let tested = self.read(delta, tested);
+
+        let folded = match lines.last() {
+            Some(Stmt::Line(line)) => {
+                line.strip_prefix(&format!("{} = ", tested))
+                    .and_then(|comparison| {
+                        [" < ", " == "].iter().find_map(|op| {
+                            let mut sides = comparison.splitn(2, op);
+                            Some((
+                                sides.next()?.to_string(),
+                                op.trim(),
+                                sides.next()?.to_string(),
+                            ))
+                        })
+                    })
+            }
+            _ => None,
+        };
+
+        let cond = match folded {
+            Some((left, op, right)) => {
+                lines.pop();
+                Cond {
+                    left,
+                    op: if op == "<" { "<" } else { "==" },
+                    right,
+                }
+            }
+            None => Cond {
+                left: tested,
+                op: "!=",
+                right: "0".to_string(),
+            },
+        };
+
+        if jump_if {
+            cond
+        } else {
+            cond.negate()
+        }
+    }
+
+    /// structures the code from a block up to (but not including) `until`
+    fn region(
+        &mut self,
+        first: usize,
+        until: Option<usize>,
+        context: Option<&LoopContext>,
+        entering: bool,
+    ) -> Vec<Stmt> {
+        let mut stmts = Vec::new();
+        let mut next = Some(first);
+        let mut entering = entering;
+
+        while let Some(start) = next {
+            if Some(start) == until {
+                break;
+            }
+
+            if let Some(context) = context {
+                if start == context.header && !entering {
+                    stmts.push(Stmt::Continue);
+                    break;
+                }
+                if Some(start) == context.exit {
+                    stmts.push(Stmt::Break);
+                    break;
+                }
+            }
+
+            if self.emitted.contains(&start) || !self.blocks.contains(&start) {
+                stmts.push(Stmt::Goto(start));
+                break;
+            }
+
+            if !entering || context.is_none_or(|c| c.header != start) {
+                if let Some(body) = self.loop_body(start) {
+                    let exit = body
+                        .iter()
+                        .flat_map(|&b| successors(self.block(b)))
+                        .filter(|s| !body.contains(s))
+                        .min();
+
+                    let inner = LoopContext {
+                        header: start,
+                        exit,
+                    };
+                    let mut body = self.region(start, None, Some(&inner), true);
+
+                    // reaching the header again at the end of the body is just the next iteration
+                    if body.last() == Some(&Stmt::Continue) {
+                        body.pop();
+                    }
+
+                    stmts.push(make_loop(body));
+                    next = exit;
+                    entering = false;
+                    continue;
+                }
+            }
+            entering = false;
+
+            self.emitted.insert(start);
+            stmts.push(Stmt::Label(start));
+
+            let block = self.block(start);
+            let (lines, cond) = self.statements(start);
+            stmts.extend(lines);
+
+            next = match block.exit {
+                Exit::FallThrough(n) | Exit::Jump(n) | Exit::Call { returns: n, .. } => Some(n),
+                Exit::Branch { taken, not_taken } => {
+                    let cond = cond.expect("branch has a condition");
+                    let join = self.join(start);
+
+                    let then = self.region(taken, join, context, false);
+                    let otherwise = self.region(not_taken, join, context, false);
+                    stmts.push(make_if(cond, then, otherwise));
+
+                    match join {
+                        Some(join) => Some(join),
+                        None => break,
+                    }
+                }
+                Exit::Return => {
+                    stmts.push(Stmt::Line("return".to_string()));
+                    None
+                }
+                Exit::Halt => None,
+                Exit::Indirect { not_taken } => {
+                    stmts.push(Stmt::Line("goto *(unknown)".to_string()));
+                    not_taken
+                }
+                Exit::Invalid => {
+                    stmts.push(Stmt::Line("invalid instruction".to_string()));
+                    None
+                }
+            };
+        }
+
+        stmts
+    }
+
+    /// the argument names, in order
+    fn arguments(&self) -> Vec<String> {
+        if self.entry == 0 {
+            return Vec::new();
+        }
+
+        self.slots
+            .iter()
+            .filter(|&(&slot, &kind)| {
+                kind == Slot::Argument && slot > 0 && (self.frame == 0 || slot < self.frame)
+            })
+            .map(|(&slot, _)| self.relative(Some(0), slot))
+            .collect()
+    }
+}
+
+/// the base after an instruction, if it's known before it and doesn't overflow
+fn adjust(delta: Option<i64>, instruction: &Instruction) -> Option<i64> {
+    match instruction {
+        Instruction::AdjustBase(ReadValue::Immediate(offset)) => {
+            delta.and_then(|d| d.checked_add(*offset))
+        }
+        Instruction::AdjustBase(_) => None,
+        _ => delta,
+    }
+}
+
+/// the relative offsets an instruction reads and writes
+fn relative_operands(instruction: &Instruction) -> (Vec<i64>, Vec<i64>) {
+    let read = |value: &ReadValue| match *value {
+        ReadValue::Relative(offset) => Some(offset),
+        _ => None,
+    };
+
+    let reads = match instruction {
+        Instruction::Add(l, r, _)
+        | Instruction::Multiply(l, r, _)
+        | Instruction::LessThan(l, r, _)
+        | Instruction::Equals(l, r, _)
+        | Instruction::JumpTrue(l, r)
+        | Instruction::JumpFalse(l, r) => vec![read(l), read(r)],
+        Instruction::Output(v) | Instruction::AdjustBase(v) => vec![read(v)],
+        _ => Vec::new(),
+    };
+
+    let writes = match written(instruction) {
+        Some(WriteValue::Relative(offset)) => vec![offset],
+        _ => Vec::new(),
+    };
+
+    (reads.into_iter().flatten().collect(), writes)
+}
+
+fn written(instruction: &Instruction) -> Option<WriteValue> {
+    match *instruction {
+        Instruction::Add(_, _, d)
+        | Instruction::Multiply(_, _, d)
+        | Instruction::LessThan(_, _, d)
+        | Instruction::Equals(_, _, d)
+        | Instruction::Input(d) => Some(d),
+        _ => None,
+    }
+}
+
+fn sum(left: String, right: String) -> String {
+    match (left.as_str(), right.as_str()) {
+        ("0", _) => right,
+        (_, "0") => left,
+        (_, r) if r.starts_with('-') => format!("{} - {}", left, &r[1..]),
+        _ => format!("{} + {}", left, right),
+    }
+}
+
+fn product(left: String, right: String) -> String {
+    match (left.as_str(), right.as_str()) {
+        ("1", _) => right,
+        (_, "1") => left,
+        _ => format!("{} * {}", left, right),
+    }
+}
+
+fn make_if(cond: Cond, then: Vec<Stmt>, otherwise: Vec<Stmt>) -> Stmt {
+    let shown = |stmts: &[Stmt]| stmts.iter().any(|s| !matches!(s, Stmt::Label(_)));
+
+    if !shown(&then) && shown(&otherwise) {
+        return Stmt::If {
+            cond: cond.negate(),
+            then: otherwise,
+            otherwise: then,
+        };
+    }
+
+    Stmt::If {
+        cond,
+        then,
+        otherwise,
+    }
+}
+
+/// turns a loop which starts by testing whether to leave into a while loop
+fn make_loop(body: Vec<Stmt>) -> Stmt {
+    let first = body.iter().position(|s| !matches!(s, Stmt::Label(_)));
+
+    if let Some(index) = first {
+        if let Stmt::If {
+            cond,
+            then,
+            otherwise,
+        } = &body[index]
+        {
+            // `loop { if c { break } ... }`
+            if then.as_slice() == [Stmt::Break] && otherwise.is_empty() {
+                let mut rest = body.clone();
+                let cond = cond.negate();
+                rest.remove(index);
+
+                return Stmt::While { cond, body: rest };
+            }
+
+            // `loop { if c { ...; continue } break }`
+            if then.last() == Some(&Stmt::Continue)
+                && otherwise.is_empty()
+                && body[index + 1..] == [Stmt::Break]
+            {
+                let mut rest: Vec<Stmt> = body[..index].to_vec();
+                rest.extend_from_slice(&then[..then.len() - 1]);
+
+                return Stmt::While {
+                    cond: cond.clone(),
+                    body: rest,
+                };
+            }
+        }
+    }
+
+    Stmt::Loop(body)
+}
+
+fn gotos(stmts: &[Stmt], targets: &mut BTreeSet<usize>) {
+    for stmt in stmts {
+        match stmt {
+            Stmt::Goto(target) => {
+                targets.insert(*target);
+            }
+            Stmt::If {
+                then, otherwise, ..
+            } => {
+                gotos(then, targets);
+                gotos(otherwise, targets);
+            }
+            Stmt::Loop(body) | Stmt::While { body, .. } => gotos(body, targets),
+            _ => {}
+        }
+    }
+}
+
+fn print(output: &mut String, stmts: &[Stmt], targets: &BTreeSet<usize>, depth: usize) {
+    let indent = "    ".repeat(depth);
+
+    for stmt in stmts {
+        let _ = match stmt {
+            Stmt::Label(address) if targets.contains(address) => {
+                writeln!(
+                    output,
+                    "{}L{}:",
+                    "    ".repeat(depth.saturating_sub(1)),
+                    address
+                )
+            }
+            Stmt::Label(_) => Ok(()),
+            Stmt::Line(line) => writeln!(output, "{}{}", indent, line),
+            Stmt::Goto(address) => writeln!(output, "{}goto L{}", indent, address),
+            Stmt::Break => writeln!(output, "{}break", indent),
+            Stmt::Continue => writeln!(output, "{}continue", indent),
+            Stmt::If {
+                cond,
+                then,
+                otherwise,
+            } => {
+                let _ = writeln!(
+                    output,
+                    "{}if {} {} {} {{",
+                    indent, cond.left, cond.op, cond.right
+                );
+                print(output, then, targets, depth + 1);
+
+                if otherwise
+                    .iter()
+                    .any(|s| !matches!(s, Stmt::Label(a) if !targets.contains(a)))
+                {
+                    let _ = writeln!(output, "{}}} else {{", indent);
+                    print(output, otherwise, targets, depth + 1);
+                }
+
+                writeln!(output, "{}}}", indent)
+            }
+            Stmt::Loop(body) => {
+                let _ = writeln!(output, "{}loop {{", indent);
+                print(output, body, targets, depth + 1);
+                writeln!(output, "{}}}", indent)
+            }
+            Stmt::While { cond, body } => {
+                let _ = writeln!(
+                    output,
+                    "{}while {} {} {} {{",
+                    indent, cond.left, cond.op, cond.right
+                );
+                print(output, body, targets, depth + 1);
+                writeln!(output, "{}}}", indent)
+            }
+        };
+    }
+}
+
+/// Decompiles a program into structured pseudocode.
+///
+/// Code is found by following the control-flow graph from address 0, so it's only as complete as
+/// `graph::analyse`. Each call target becomes a function, whose stack frame comes from the `arb`
+/// at its entry point: slots read before they're written are named as arguments, others as
+/// locals, and values stored above the frame before a call become the call's arguments.
+/// Branches become if/else, code which jumps back to an earlier block becomes a loop, and
+/// anything which can't be structured falls back to labels and gotos. Position mode cells are
+/// shown as `mem[address]`.
+pub fn decompile(program: &[i64]) -> String {
+    let graph = graph::analyse(program);
+    let entries: Vec<usize> = Some(0)
+        .into_iter()
+        .chain(graph.functions.iter().copied())
+        .collect();
+
+    let mut output = String::new();
+
+    for (index, &entry) in entries.iter().enumerate() {
+        if index > 0 {
+            output.push('\n');
+        }
+
+        let mut function = Function::new(&graph, entry);
+        let body = function.region(entry, None, None, false);
+
+        let mut targets = BTreeSet::new();
+        gotos(&body, &mut targets);
+
+        let _ = writeln!(
+            output,
+            "fn {}({}) {{",
+            function_name(entry),
+            function.arguments().join(", ")
+        );
+        print(&mut output, &body, &targets, 1);
+        output.push_str("}\n");
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::assembler::assemble;
+    use crate::intcode::IntCodeEmulator;
+
+    #[test]
+    fn structures_branches_and_loops() {
+        let program = assemble(
+            "
+                in [n]
+                lt [n], #0, [t]
+                jf [t], #count
+                out #-1
+                jt #1, #done
+        count:  eq [n], #0, [t]
+                jt [t], #done
+                out [n]
+                add [n], #-1, [n]
+                jt #1, #count
+        done:   hlt
+        n:      data 0
+        t:      data 0
+            ",
+        )
+        .unwrap();
+
+        assert_eq!(
+            decompile(&program),
+            "\
+fn main() {
+    mem[31] = input()
+    if mem[31] >= 0 {
+        while mem[31] != 0 {
+            output(mem[31])
+            mem[31] = mem[31] - 1
+        }
+    } else {
+        output(-1)
+    }
+    halt
+}
+"
+        );
+    }
+
+    #[test]
+    fn recognises_functions_and_frames() {
+        let program = assemble(
+            "
+                arb #100
+                in [x]
+                add [x], #0, [rb+1]
+                add #back, #0, [rb+0]
+                jt #1, #square
+        back:   out [rb+1]
+                hlt
+        square: arb #3
+                mul [rb-2], [rb-2], [rb-1]
+                add [rb-1], #0, [rb-2]
+                arb #-3
+                jf #0, [rb+0]
+        x:      data 0
+            ",
+        )
+        .unwrap();
+
+        assert_eq!(
+            decompile(&program),
+            "\
+fn main() {
+    rb += 100
+    mem[33] = input()
+    f18(mem[33])
+    output(mem[101])
+    halt
+}
+
+fn f18(arg1) {
+    local2 = arg1 * arg1
+    arg1 = local2
+    return
+}
+"
+        );
+    }
+
+    #[test]
+    fn forgets_the_base_when_it_overflows() {
+        let pseudocode = decompile(&[109, i64::MAX, 109, 1, 99]);
+        assert!(pseudocode.contains("rb += 9223372036854775807\n    rb += 1\n    halt\n"));
+
+        let pseudocode = decompile(&[109, i64::MAX, 109, 1, 204, 0, 99]);
+        assert!(pseudocode.contains("output(rb[0])"));
+    }
+
+    #[test]
+    fn decompiles_puzzles() {
+        let program = IntCodeEmulator::parse_input(include_str!("../../input/2019/day9.txt"));
+        let pseudocode = decompile(&program);
+
+        assert!(pseudocode.starts_with("fn main() {\n"));
+        assert!(pseudocode.contains("\nfn f922(arg1) {\n"));
+        assert!(pseudocode.contains("f922("));
+    }
+}
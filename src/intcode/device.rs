@@ -0,0 +1,216 @@
+use std::collections::VecDeque;
+use std::io::{BufRead, Write};
+use std::sync::mpsc::{Receiver, Sender};
+
+/// A peripheral attached to an emulator which supplies its input and receives its output
+pub trait IoDevice {
+    /// returns the next input value, or None if the VM should yield until input is available
+    fn input(&mut self) -> Option<i64>;
+
+    /// receives a value the VM has output
+    fn output(&mut self, value: i64);
+}
+
+/// Supplies input values to a `Ports` device
+pub trait Source {
+    fn next_value(&mut self) -> Option<i64>;
+}
+
+/// Receives output values from a `Ports` device
+pub trait Sink {
+    fn push_value(&mut self, value: i64);
+}
+
+/// A device made up of a separate input source and output sink
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Ports<I, O> {
+    pub input: I,
+    pub output: O,
+}
+
+/// The default device, which buffers input and output in queues
+pub type QueueDevice = Ports<VecDeque<i64>, VecDeque<i64>>;
+
+impl<I, O> Ports<I, O> {
+    pub fn new(input: I, output: O) -> Ports<I, O> {
+        Ports { input, output }
+    }
+}
+
+impl<I: Source, O: Sink> IoDevice for Ports<I, O> {
+    fn input(&mut self) -> Option<i64> {
+        self.input.next_value()
+    }
+
+    fn output(&mut self, value: i64) {
+        self.output.push_value(value);
+    }
+}
+
+impl Source for VecDeque<i64> {
+    fn next_value(&mut self) -> Option<i64> {
+        self.pop_front()
+    }
+}
+
+impl Sink for VecDeque<i64> {
+    fn push_value(&mut self, value: i64) {
+        self.push_back(value);
+    }
+}
+
+impl Sink for Vec<i64> {
+    fn push_value(&mut self, value: i64) {
+        self.push(value);
+    }
+}
+
+/// blocks until a value is received, or returns None once every sender has gone away
+impl Source for Receiver<i64> {
+    fn next_value(&mut self) -> Option<i64> {
+        self.recv().ok()
+    }
+}
+
+/// values are dropped once the receiver has gone away
+impl Sink for Sender<i64> {
+    fn push_value(&mut self, value: i64) {
+        let _ = self.send(value);
+    }
+}
+
+/// Pulls input lazily from a callback
+#[derive(Debug, Clone)]
+pub struct FnSource<F>(pub F);
+
+impl<F: FnMut() -> Option<i64>> Source for FnSource<F> {
+    fn next_value(&mut self) -> Option<i64> {
+        (self.0)()
+    }
+}
+
+/// Pushes output to a callback
+#[derive(Debug, Clone)]
+pub struct FnSink<F>(pub F);
+
+impl<F: FnMut(i64)> Sink for FnSink<F> {
+    fn push_value(&mut self, value: i64) {
+        (self.0)(value)
+    }
+}
+
+/// Pulls input lazily from an iterator, such as a generator of values
+#[derive(Debug, Clone)]
+pub struct IterSource<I>(pub I);
+
+impl<I: Iterator<Item = i64>> Source for IterSource<I> {
+    fn next_value(&mut self) -> Option<i64> {
+        self.0.next()
+    }
+}
+
+/// Reads input from text such as a file, where values are separated by commas or whitespace.
+/// Anything which isn't a number ends the input.
+#[derive(Debug)]
+pub struct ReaderSource<R> {
+    reader: R,
+    pending: VecDeque<i64>,
+}
+
+impl<R: BufRead> ReaderSource<R> {
+    pub fn new(reader: R) -> ReaderSource<R> {
+        ReaderSource {
+            reader,
+            pending: VecDeque::new(),
+        }
+    }
+}
+
+impl<R: BufRead> Source for ReaderSource<R> {
+    fn next_value(&mut self) -> Option<i64> {
+        while self.pending.is_empty() {
+            let mut line = String::new();
+
+            if self.reader.read_line(&mut line).ok()? == 0 {
+                return None;
+            }
+
+            for value in line.split(|c: char| c == ',' || c.is_whitespace()) {
+                if !value.is_empty() {
+                    self.pending.push_back(value.parse().ok()?);
+                }
+            }
+        }
+
+        self.pending.pop_front()
+    }
+}
+
+/// Writes each output value on its own line, e.g. to a file. Write errors are ignored.
+#[derive(Debug)]
+pub struct WriterSink<W>(pub W);
+
+impl<W: Write> Sink for WriterSink<W> {
+    fn push_value(&mut self, value: i64) {
+        let _ = writeln!(self.0, "{}", value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::assembler::assemble;
+    use crate::intcode::IntCodeEmulator;
+    use std::cell::RefCell;
+
+    /// adds pairs of inputs together until it receives a zero
+    const ADDER: &str = "
+        loop:   in [a]
+                jf [a], #done
+                in [b]
+                add [a], [b], [a]
+                out [a]
+                jt #1, #loop
+        done:   hlt
+        a:      data 0
+        b:      data 0
+    ";
+
+    #[test]
+    fn pulls_from_generators_and_pushes_to_callbacks() {
+        let outputs = RefCell::new(Vec::new());
+        let device = Ports::new(
+            IterSource((1..=4).chain(Some(0))),
+            FnSink(|v| outputs.borrow_mut().push(v)),
+        );
+
+        let mut vm = IntCodeEmulator::new(assemble(ADDER).unwrap()).with_device(device);
+        vm.execute().unwrap();
+
+        assert_eq!(*outputs.borrow(), vec![3, 7]);
+    }
+
+    #[test]
+    fn reads_from_text_and_writes_lines() {
+        let device = Ports::new(
+            ReaderSource::new("10, 20\n30 40\n0".as_bytes()),
+            WriterSink(Vec::new()),
+        );
+
+        let mut vm = IntCodeEmulator::new(assemble(ADDER).unwrap()).with_device(device);
+        vm.execute().unwrap();
+
+        assert_eq!(vm.device().output.0, b"30\n70\n");
+    }
+
+    #[test]
+    fn yields_when_source_is_empty() {
+        let mut vm = IntCodeEmulator::new(assemble(ADDER).unwrap());
+        vm.stdin().push_back(5);
+
+        assert_eq!(
+            vm.execute_until_yield().unwrap(),
+            crate::intcode::YieldReason::InputRequired
+        );
+    }
+}
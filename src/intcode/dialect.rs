@@ -0,0 +1,672 @@
+use crate::intcode::{
+    address, load, ErrorKind, IntCodeEmulator, IoDevice, Memory, Observer, StepResult, Strictness,
+};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::sync::Arc;
+
+/// the opcodes understood by the standard instruction set, along with how many parameters they take
+const STANDARD: [(i64, usize); 10] = [
+    (1, 3),
+    (2, 3),
+    (3, 1),
+    (4, 1),
+    (5, 2),
+    (6, 2),
+    (7, 3),
+    (8, 3),
+    (9, 1),
+    (99, 0),
+];
+
+/// How a parameter is interpreted, given by a digit of its instruction's opcode
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Mode {
+    Position,
+    Immediate,
+    Relative,
+}
+
+impl Mode {
+    pub const ALL: [Mode; 3] = [Mode::Position, Mode::Immediate, Mode::Relative];
+
+    fn from_digit(digit: i64) -> Option<Mode> {
+        match digit {
+            0 => Some(Mode::Position),
+            1 => Some(Mode::Immediate),
+            2 => Some(Mode::Relative),
+            _ => None,
+        }
+    }
+}
+
+/// Whether a custom opcode's parameter is read from or written to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Parameter {
+    Read,
+    Write,
+}
+
+/// What happens after a custom instruction has executed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Effect {
+    /// carry on with the instruction after it
+    Continue,
+
+    /// carry on from the given address
+    Jump(usize),
+
+    /// stop, leaving the pointer on the instruction as `Halt` does
+    Halt,
+
+    /// yield until input is available, then execute the instruction again from the start. It
+    /// shouldn't have changed anything before asking
+    InputRequired,
+}
+
+type Execute = dyn Fn(&mut Operation) -> Result<Effect, ErrorKind> + Send + Sync;
+
+/// An instruction added to a dialect, which runs a callback to execute
+#[derive(Clone)]
+pub struct Opcode {
+    mnemonic: String,
+    parameters: Vec<Parameter>,
+    execute: Arc<Execute>,
+}
+
+impl Opcode {
+    /// creates an opcode which takes the given parameters, and executes by calling `execute`
+    pub fn new<F>(mnemonic: &str, parameters: &[Parameter], execute: F) -> Opcode
+    where
+        F: Fn(&mut Operation) -> Result<Effect, ErrorKind> + Send + Sync + 'static,
+    {
+        Opcode {
+            mnemonic: mnemonic.to_string(),
+            parameters: parameters.to_vec(),
+            execute: Arc::new(execute),
+        }
+    }
+
+    pub fn mnemonic(&self) -> &str {
+        &self.mnemonic
+    }
+
+    pub fn parameters(&self) -> &[Parameter] {
+        &self.parameters
+    }
+}
+
+impl fmt::Debug for Opcode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Opcode")
+            .field("mnemonic", &self.mnemonic)
+            .field("parameters", &self.parameters)
+            .finish()
+    }
+}
+
+/// A custom instruction as it executes, with its parameters already resolved
+pub struct Operation<'a> {
+    pointer: usize,
+    arguments: Vec<Argument>,
+    ram: &'a mut Memory,
+    base: &'a mut i64,
+    device: &'a mut dyn IoDevice,
+    observer: &'a mut dyn Observer,
+    written: Vec<usize>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Argument {
+    Value(i64),
+    Address(usize),
+}
+
+impl Operation<'_> {
+    /// the address of the instruction
+    pub fn pointer(&self) -> usize {
+        self.pointer
+    }
+
+    /// the value of a read parameter, numbered from 1. Panics if it's not a read parameter
+    pub fn read(&self, parameter: usize) -> i64 {
+        match self.arguments.get(parameter.wrapping_sub(1)) {
+            Some(Argument::Value(value)) => *value,
+            _ => panic!("parameter {} isn't read", parameter),
+        }
+    }
+
+    /// writes the value to a write parameter, numbered from 1. Panics if it's not a write parameter
+    pub fn write(&mut self, parameter: usize, value: i64) -> Result<(), ErrorKind> {
+        let address = match self.arguments.get(parameter.wrapping_sub(1)) {
+            Some(Argument::Address(address)) => *address,
+            _ => panic!("parameter {} isn't written", parameter),
+        };
+
+        self.poke(address, value)
+    }
+
+    /// reads any memory cell, treating anything outside of memory as 0
+    pub fn peek(&mut self, address: usize) -> i64 {
+        let value = self.ram.read(address);
+        self.observer.read(address, value);
+        value
+    }
+
+    /// writes any memory cell, within the memory limit
+    pub fn poke(&mut self, address: usize, value: i64) -> Result<(), ErrorKind> {
+        self.ram.check(address)?;
+
+        self.observer.write(address, self.ram.read(address), value);
+        self.ram.set(address, value);
+        self.written.push(address);
+        Ok(())
+    }
+
+    pub fn base(&self) -> i64 {
+        *self.base
+    }
+
+    pub fn set_base(&mut self, base: i64) {
+        *self.base = base;
+    }
+
+    /// takes the next input value, or None if the instruction should return `Effect::InputRequired`
+    pub fn input(&mut self) -> Option<i64> {
+        let input = self.device.input()?;
+        self.observer.input(input);
+        Some(input)
+    }
+
+    pub fn output(&mut self, value: i64) {
+        self.observer.output(value);
+        self.device.output(value);
+    }
+}
+
+/// The set of opcodes and parameter modes an emulator understands.
+///
+/// Dialects are built up from one of the presets, adding custom opcodes which can replace standard
+/// ones. Anything outside the dialect fails with `ErrorKind::UnknownOpcode` or
+/// `ErrorKind::InvalidMode`.
+#[derive(Clone)]
+pub struct Dialect {
+    standard: BTreeSet<i64>,
+    modes: BTreeSet<Mode>,
+    custom: BTreeMap<i64, Opcode>,
+}
+
+/// The interpretation of an opcode in a dialect
+pub(crate) enum Decoded {
+    Standard,
+    Custom(Opcode),
+}
+
+impl Dialect {
+    /// the instruction set from day 2: add, multiply and halt, with only position mode parameters
+    pub fn day02() -> Dialect {
+        Dialect {
+            standard: [1, 2, 99].iter().copied().collect(),
+            modes: Some(Mode::Position).into_iter().collect(),
+            custom: BTreeMap::new(),
+        }
+    }
+
+    /// the complete instruction set from day 9, which the emulator understands by default
+    pub fn day09() -> Dialect {
+        Dialect {
+            standard: STANDARD.iter().map(|&(opcode, _)| opcode).collect(),
+            modes: Mode::ALL.iter().copied().collect(),
+            custom: BTreeMap::new(),
+        }
+    }
+
+    /// adds a custom opcode, replacing any opcode with the same number. Panics unless the number is
+    /// between 1 and 99, since the hundreds and above hold the modes
+    pub fn opcode(mut self, number: i64, opcode: Opcode) -> Dialect {
+        assert!(
+            (1..100).contains(&number),
+            "opcode {} isn't between 1 and 99",
+            number
+        );
+
+        self.standard.remove(&number);
+        self.custom.insert(number, opcode);
+        self
+    }
+
+    /// sets which parameter modes are allowed, for both standard and custom opcodes
+    pub fn modes(mut self, modes: &[Mode]) -> Dialect {
+        self.modes = modes.iter().copied().collect();
+        self
+    }
+
+    /// the custom opcode with the given number, if there is one
+    pub fn custom(&self, number: i64) -> Option<&Opcode> {
+        self.custom.get(&number)
+    }
+
+    pub fn is_standard(&self, number: i64) -> bool {
+        self.standard.contains(&number)
+    }
+
+    pub fn allows(&self, mode: Mode) -> bool {
+        self.modes.contains(&mode)
+    }
+
+    /// works out what an opcode means in the dialect, checking its modes are allowed. Standard
+    /// opcodes are checked after they've been decoded, so their other errors take priority
+    pub(crate) fn decode(&self, opcode: i64) -> Result<Decoded, ErrorKind> {
+        let number = opcode % 100;
+
+        if let Some(custom) = self.custom.get(&number) {
+            self.check_modes(opcode, custom.parameters.len())?;
+
+            for (index, parameter) in custom.parameters.iter().enumerate() {
+                if *parameter == Parameter::Write
+                    && mode(opcode, index + 1) == Some(Mode::Immediate)
+                {
+                    return Err(ErrorKind::ImmediateWrite {
+                        parameter: index + 1,
+                    });
+                }
+            }
+
+            return Ok(Decoded::Custom(custom.clone()));
+        }
+
+        match self.standard.contains(&number) {
+            true => Ok(Decoded::Standard),
+            false => Err(ErrorKind::UnknownOpcode),
+        }
+    }
+
+    /// checks that every parameter of a standard opcode uses an allowed mode
+    pub(crate) fn check_standard(&self, opcode: i64) -> Result<(), ErrorKind> {
+        let number = opcode % 100;
+        let parameters = STANDARD
+            .iter()
+            .find(|&&(standard, _)| standard == number)
+            .map_or(0, |&(_, parameters)| parameters);
+
+        self.check_modes(opcode, parameters)
+    }
+
+    fn check_modes(&self, opcode: i64, parameters: usize) -> Result<(), ErrorKind> {
+        for parameter in 1..=parameters {
+            match mode(opcode, parameter) {
+                Some(mode) if self.modes.contains(&mode) => {}
+                _ => {
+                    return Err(ErrorKind::InvalidMode {
+                        parameter,
+                        mode: digit(opcode, parameter),
+                    })
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for Dialect {
+    fn default() -> Dialect {
+        Dialect::day09()
+    }
+}
+
+impl fmt::Debug for Dialect {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Dialect")
+            .field("standard", &self.standard)
+            .field("modes", &self.modes)
+            .field("custom", &self.custom)
+            .finish()
+    }
+}
+
+/// the mode digit of a parameter, numbered from 1
+fn digit(opcode: i64, parameter: usize) -> i64 {
+    (opcode / 10_i64.pow(parameter as u32 + 1)) % 10
+}
+
+fn mode(opcode: i64, parameter: usize) -> Option<Mode> {
+    Mode::from_digit(digit(opcode, parameter))
+}
+
+/// Something a custom instruction did, which is reported to the observer once it's known that the
+/// instruction actually executed
+#[derive(Debug, Clone, Copy)]
+enum Notification {
+    Read(usize, i64),
+    Write(usize, i64, i64),
+    Input(i64),
+    Output(i64),
+}
+
+impl Observer for Vec<Notification> {
+    fn read(&mut self, address: usize, value: i64) {
+        self.push(Notification::Read(address, value));
+    }
+
+    fn write(&mut self, address: usize, old: i64, new: i64) {
+        self.push(Notification::Write(address, old, new));
+    }
+
+    fn input(&mut self, value: i64) {
+        self.push(Notification::Input(value));
+    }
+
+    fn output(&mut self, value: i64) {
+        self.push(Notification::Output(value));
+    }
+}
+
+impl<D: IoDevice> IntCodeEmulator<D> {
+    /// executes a custom instruction at the pointer. An instruction which has to wait for input
+    /// doesn't execute, so observers are only told about it once it can run
+    pub(crate) fn execute_custom<O>(
+        &mut self,
+        opcode: &Opcode,
+        observer: &mut O,
+    ) -> Result<StepResult, ErrorKind>
+    where
+        O: Observer + ?Sized,
+    {
+        let code = self.ram.read(self.pointer);
+        let end = self.pointer + 1 + opcode.parameters.len();
+        let strict = self.strictness == Strictness::Strict;
+
+        if end > self.ram.len() {
+            return Err(ErrorKind::TruncatedInstruction);
+        }
+        if strict && end > self.image {
+            return Err(ErrorKind::ExecutedData {
+                address: self.image,
+            });
+        }
+
+        let (pointer, base) = (self.pointer, self.base);
+        let mut notifications = Vec::new();
+        let mut arguments = Vec::with_capacity(opcode.parameters.len());
+
+        for (index, parameter) in opcode.parameters.iter().enumerate() {
+            let number = index + 1;
+            let value = self.ram.read(self.pointer + number);
+
+            let resolved = match mode(code, number) {
+                Some(Mode::Immediate) => {
+                    arguments.push(Argument::Value(value));
+                    continue;
+                }
+                Some(Mode::Relative) if self.strictness == Strictness::Legacy => {
+                    return Err(ErrorKind::InvalidMode {
+                        parameter: number,
+                        mode: 2,
+                    })
+                }
+                Some(Mode::Relative) => address(value.wrapping_add(self.base))?,
+                _ => address(value)?,
+            };
+
+            arguments.push(match parameter {
+                Parameter::Read => {
+                    let value = load(&self.ram, resolved, strict)?;
+                    notifications.read(resolved, value);
+                    Argument::Value(value)
+                }
+                Parameter::Write => Argument::Address(resolved),
+            });
+        }
+
+        let mut operation = Operation {
+            pointer,
+            arguments,
+            ram: &mut self.ram,
+            base: &mut self.base,
+            device: &mut self.device,
+            observer: &mut notifications,
+            written: Vec::new(),
+        };
+
+        let effect = (opcode.execute)(&mut operation);
+
+        if let Some(cache) = &mut self.cache {
+            for &address in &operation.written {
+                cache.invalidate(address);
+            }
+        }
+
+        if effect != Ok(Effect::InputRequired) {
+            observer.custom(pointer, base, opcode);
+
+            for notification in notifications {
+                match notification {
+                    Notification::Read(address, value) => observer.read(address, value),
+                    Notification::Write(address, old, new) => observer.write(address, old, new),
+                    Notification::Input(value) => observer.input(value),
+                    Notification::Output(value) => observer.output(value),
+                }
+            }
+        }
+
+        match effect? {
+            Effect::Continue => self.pointer += 1 + opcode.parameters.len(),
+            Effect::Jump(target) => self.pointer = target,
+            Effect::Halt => return Ok(StepResult::Halted),
+            Effect::InputRequired => return Ok(StepResult::InputRequired),
+        }
+
+        Ok(StepResult::Continue)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::compiler::CompiledEmulator;
+    use crate::intcode::profiler::Profiler;
+    use crate::intcode::trace::Recorder;
+    use crate::intcode::{IntCodeError, YieldReason};
+
+    /// integer division and remainder as opcodes 10 and 11, and a jump if negative as 12
+    fn arithmetic() -> Dialect {
+        let divide = |operation: &mut Operation, remainder: bool| {
+            let (left, right) = (operation.read(1), operation.read(2));
+            if right == 0 {
+                return Err(ErrorKind::Custom("division by zero".to_string()));
+            }
+
+            let value = match remainder {
+                true => left.wrapping_rem(right),
+                false => left.wrapping_div(right),
+            };
+            operation.write(3, value)?;
+            Ok(Effect::Continue)
+        };
+
+        let binary = [Parameter::Read, Parameter::Read, Parameter::Write];
+
+        Dialect::day09()
+            .opcode(10, Opcode::new("div", &binary, move |op| divide(op, false)))
+            .opcode(11, Opcode::new("mod", &binary, move |op| divide(op, true)))
+            .opcode(
+                12,
+                Opcode::new("jn", &[Parameter::Read, Parameter::Read], |op| {
+                    match (op.read(1) < 0, address(op.read(2))) {
+                        (true, target) => Ok(Effect::Jump(target?)),
+                        (false, _) => Ok(Effect::Continue),
+                    }
+                }),
+            )
+    }
+
+    /// reads numbers until one is negative, outputting the quotient and remainder of each by 7
+    const DIVIDER: [i64; 21] = [
+        3, 100, 1012, 100, 20, 1010, 100, 7, 101, 1011, 100, 7, 102, 4, 101, 4, 102, 1105, 1, 0, 99,
+    ];
+
+    fn run(vm: &mut IntCodeEmulator, inputs: &[i64]) -> Result<Vec<i64>, IntCodeError> {
+        vm.stdin().extend(inputs.iter().copied());
+        vm.execute()?;
+        Ok(vm.stdout().drain(..).collect())
+    }
+
+    #[test]
+    fn day02_preset() {
+        let mut program = IntCodeEmulator::parse_input(include_str!("../../input/2019/day2.txt"));
+        program[1] = 12;
+        program[2] = 2;
+
+        let mut vm = IntCodeEmulator::new(program);
+        vm.set_dialect(Some(Dialect::day02()));
+        vm.execute().unwrap();
+        assert_eq!(vm.peek(0), 6_627_023);
+
+        let mut vm = IntCodeEmulator::new(vec![1101, 1, 2, 0, 99]);
+        vm.set_dialect(Some(Dialect::day02()));
+        assert_eq!(
+            vm.execute().unwrap_err().kind,
+            ErrorKind::InvalidMode {
+                parameter: 1,
+                mode: 1
+            }
+        );
+
+        let mut vm = IntCodeEmulator::new(vec![3, 0, 99]);
+        vm.set_dialect(Some(Dialect::day02()));
+        assert_eq!(vm.execute().unwrap_err().kind, ErrorKind::UnknownOpcode);
+    }
+
+    #[test]
+    fn day09_preset() {
+        let program = IntCodeEmulator::parse_input(include_str!("../../input/2019/day9.txt"));
+
+        let mut standard = IntCodeEmulator::new(program.clone());
+        let mut preset = IntCodeEmulator::new(program);
+        preset.set_dialect(Some(Dialect::day09()));
+
+        assert_eq!(run(&mut preset, &[1]), run(&mut standard, &[1]));
+    }
+
+    #[test]
+    fn custom_opcodes() {
+        let mut vm = IntCodeEmulator::new(DIVIDER.to_vec());
+        vm.set_dialect(Some(arithmetic()));
+
+        let inputs = [23, 7, 0, -1];
+        assert_eq!(run(&mut vm, &inputs).unwrap(), vec![3, 2, 1, 0, 0, 0]);
+
+        // the compiler and decode cache have to leave custom instructions to the dialect
+        let mut vm = IntCodeEmulator::new(DIVIDER.to_vec());
+        vm.set_dialect(Some(arithmetic()));
+        vm.enable_decode_cache();
+        vm.stdin().extend(inputs.iter().copied());
+
+        let mut compiled = CompiledEmulator::new(vm);
+        assert!(compiled.is_fallback());
+        compiled.execute().unwrap();
+        let output: Vec<i64> = compiled.stdout().iter().copied().collect();
+        assert_eq!(output, vec![3, 2, 1, 0, 0, 0]);
+
+        // without the dialect, the opcodes aren't understood
+        let mut vm = IntCodeEmulator::new(DIVIDER.to_vec());
+        assert_eq!(
+            run(&mut vm, &inputs).unwrap_err().kind,
+            ErrorKind::UnknownOpcode
+        );
+    }
+
+    #[test]
+    fn custom_errors_and_input() {
+        let mut vm = IntCodeEmulator::new(vec![1010, 5, 0, 7, 99, 10, 0]);
+        vm.set_dialect(Some(arithmetic()));
+
+        let error = vm.execute().unwrap_err();
+        assert_eq!(
+            error.kind,
+            ErrorKind::Custom("division by zero".to_string())
+        );
+        assert_eq!(error.pointer, 0);
+
+        // immediate writes are rejected before the callback runs
+        let mut vm = IntCodeEmulator::new(vec![11010, 5, 2, 7, 99]);
+        vm.set_dialect(Some(arithmetic()));
+        assert_eq!(
+            vm.execute().unwrap_err().kind,
+            ErrorKind::ImmediateWrite { parameter: 3 }
+        );
+
+        // a syscall which outputs the square of its input, yielding until there is some
+        let dialect = Dialect::day09().opcode(
+            20,
+            Opcode::new("sqr", &[], |op| match op.input() {
+                Some(value) => {
+                    op.output(value * value);
+                    Ok(Effect::Continue)
+                }
+                None => Ok(Effect::InputRequired),
+            }),
+        );
+
+        let mut vm = IntCodeEmulator::new(vec![20, 99]);
+        vm.set_dialect(Some(dialect));
+
+        assert_eq!(
+            vm.execute_until_yield().unwrap(),
+            YieldReason::InputRequired
+        );
+        assert_eq!(vm.pointer(), 0);
+
+        vm.stdin().push_back(5);
+        vm.execute().unwrap();
+        assert_eq!(vm.stdout().pop_front(), Some(25));
+    }
+    #[test]
+    fn custom_opcodes_are_observed() {
+        let mut vm = IntCodeEmulator::new(DIVIDER.to_vec());
+        vm.set_dialect(Some(arithmetic()));
+        vm.stdin().extend(vec![23, -1]);
+
+        let mut profiler = Profiler::new();
+        vm.execute_with(&mut profiler).unwrap();
+
+        assert_eq!(profiler.opcode_count("div"), 1);
+        assert_eq!(profiler.opcode_count("jn"), 2);
+        assert_eq!(profiler.count(5), 1);
+        assert_eq!(profiler.instructions(), 10);
+
+        // a custom instruction waiting for input is only reported once it runs
+        let dialect = Dialect::day09().opcode(
+            20,
+            Opcode::new("in2", &[Parameter::Write], |op| match op.input() {
+                Some(value) => {
+                    op.write(1, value * 2)?;
+                    Ok(Effect::Continue)
+                }
+                None => Ok(Effect::InputRequired),
+            }),
+        );
+
+        let mut vm = IntCodeEmulator::new(vec![20, 3, 99, 0]);
+        vm.set_dialect(Some(dialect));
+
+        let mut recorder = Recorder::default();
+        vm.execute_until_yield_with(&mut recorder).unwrap();
+        assert!(recorder.events.is_empty());
+
+        vm.stdin().push_back(4);
+        vm.execute_with(&mut recorder).unwrap();
+
+        let events: Vec<String> = recorder.events.iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            events,
+            vec![
+                "0000 rb=0 in2",
+                "  input 4",
+                "  write [3] = 8 (was 0)",
+                "0002 rb=0 hlt",
+            ]
+        );
+    }
+}
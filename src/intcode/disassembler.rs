@@ -0,0 +1,252 @@
+use crate::intcode::{Instruction, ReadValue, WriteValue};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// maximum number of consecutive data cells shown on a single line
+const DATA_PER_LINE: usize = 8;
+
+/// A single line of a disassembly listing
+#[derive(Debug, PartialEq)]
+pub struct Line {
+    pub address: usize,
+    pub cells: Vec<i64>,
+    pub content: Content,
+
+    /// addresses of instructions which jump directly to this line
+    pub jumped_from: Vec<usize>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Content {
+    Instruction(Instruction),
+    Data,
+}
+
+/// Disassembles a program with a linear sweep, treating any cells which don't decode as data
+pub fn disassemble(program: &[i64]) -> Vec<Line> {
+    let mut lines = Vec::new();
+    let mut pointer = 0;
+
+    while pointer < program.len() {
+        match Instruction::parse(program, pointer) {
+            Ok(instruction) => {
+                let size = instruction.size();
+                lines.push(Line::new(
+                    pointer,
+                    &program[pointer..pointer + size],
+                    Content::Instruction(instruction),
+                ));
+                pointer += size;
+            }
+            Err(_) => {
+                // extend the previous data line if there's room, otherwise start a new one
+                match lines.last_mut() {
+                    Some(line)
+                        if line.content == Content::Data && line.cells.len() < DATA_PER_LINE =>
+                    {
+                        line.cells.push(program[pointer])
+                    }
+                    _ => lines.push(Line::new(
+                        pointer,
+                        &program[pointer..=pointer],
+                        Content::Data,
+                    )),
+                }
+                pointer += 1;
+            }
+        }
+    }
+
+    annotate_jumps(&mut lines);
+    lines
+}
+
+/// Disassembles a program into a printable listing
+pub fn listing(program: &[i64]) -> String {
+    disassemble(program)
+        .iter()
+        .map(|line| line.to_string())
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// records the source of every jump with an immediate target against the line it lands on
+fn annotate_jumps(lines: &mut [Line]) {
+    let mut targets: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+
+    for line in lines.iter() {
+        if let Content::Instruction(
+            Instruction::JumpTrue(_, ReadValue::Immediate(target))
+            | Instruction::JumpFalse(_, ReadValue::Immediate(target)),
+        ) = line.content
+        {
+            if target >= 0 {
+                targets
+                    .entry(target as usize)
+                    .or_default()
+                    .push(line.address);
+            }
+        }
+    }
+
+    for line in lines.iter_mut() {
+        if let Some(sources) = targets.remove(&line.address) {
+            line.jumped_from = sources;
+        }
+    }
+}
+
+impl Line {
+    fn new(address: usize, cells: &[i64], content: Content) -> Line {
+        Line {
+            address,
+            cells: cells.to_vec(),
+            content,
+            jumped_from: Vec::new(),
+        }
+    }
+}
+
+impl Instruction {
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Instruction::Add(..) => "add",
+            Instruction::Multiply(..) => "mul",
+            Instruction::Input(..) => "in",
+            Instruction::Output(..) => "out",
+            Instruction::JumpTrue(..) => "jt",
+            Instruction::JumpFalse(..) => "jf",
+            Instruction::LessThan(..) => "lt",
+            Instruction::Equals(..) => "eq",
+            Instruction::AdjustBase(..) => "arb",
+            Instruction::Halt => "hlt",
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mnemonic = self.mnemonic();
+
+        match self {
+            Instruction::Add(left, right, dest)
+            | Instruction::Multiply(left, right, dest)
+            | Instruction::LessThan(left, right, dest)
+            | Instruction::Equals(left, right, dest) => {
+                write!(f, "{} {}, {}, {}", mnemonic, left, right, dest)
+            }
+            Instruction::Input(dest) => write!(f, "{} {}", mnemonic, dest),
+            Instruction::Output(src) | Instruction::AdjustBase(src) => {
+                write!(f, "{} {}", mnemonic, src)
+            }
+            Instruction::JumpTrue(condition, dest) | Instruction::JumpFalse(condition, dest) => {
+                write!(f, "{} {}, {}", mnemonic, condition, dest)
+            }
+            Instruction::Halt => write!(f, "{}", mnemonic),
+        }
+    }
+}
+
+impl fmt::Display for ReadValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ReadValue::Position(position) => write!(f, "[{}]", position),
+            ReadValue::Immediate(value) => write!(f, "#{}", value),
+            ReadValue::Relative(offset) => write_relative(f, offset),
+        }
+    }
+}
+
+impl fmt::Display for WriteValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            WriteValue::Position(position) => write!(f, "[{}]", position),
+            WriteValue::Relative(offset) => write_relative(f, offset),
+        }
+    }
+}
+
+fn write_relative(f: &mut fmt::Formatter, offset: i64) -> fmt::Result {
+    if offset < 0 {
+        write!(f, "[rb-{}]", offset.unsigned_abs())
+    } else {
+        write!(f, "[rb+{}]", offset)
+    }
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let cells = self
+            .cells
+            .iter()
+            .map(|c| c.to_string())
+            .collect::<Vec<String>>()
+            .join(" ");
+
+        let content = match self.content {
+            Content::Instruction(instruction) => instruction.to_string(),
+            Content::Data => "data".to_string(),
+        };
+
+        write!(f, "{:04}  {:<32}  {}", self.address, cells, content)?;
+
+        if !self.jumped_from.is_empty() {
+            let sources = self
+                .jumped_from
+                .iter()
+                .map(|s| format!("{:04}", s))
+                .collect::<Vec<String>>()
+                .join(", ");
+            write!(f, "  ; <- {}", sources)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_parameter_modes() {
+        let lines = disassemble(&[21101, 3, -4, 7, 204, -2, 99]);
+        let rendered: Vec<String> = lines
+            .iter()
+            .map(|line| match line.content {
+                Content::Instruction(instruction) => instruction.to_string(),
+                Content::Data => "data".to_string(),
+            })
+            .collect();
+
+        assert_eq!(rendered, vec!["add #3, #-4, [rb+7]", "out [rb-2]", "hlt"]);
+    }
+
+    #[test]
+    fn renders_extreme_offsets() {
+        let listing = listing(&[204, i64::MIN, 99]);
+        assert!(listing.contains("out [rb-9223372036854775808]"));
+    }
+
+    #[test]
+    fn groups_data_cells() {
+        let program = vec![99, 0, 0, 55, 1106];
+        let lines = disassemble(&program);
+
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1].address, 1);
+        assert_eq!(lines[1].content, Content::Data);
+        assert_eq!(lines[1].cells, vec![0, 0, 55, 1106]);
+    }
+
+    #[test]
+    fn annotates_jump_targets() {
+        let program = vec![1105, 1, 4, 99, 4, 0, 99];
+        let listing = listing(&program);
+
+        assert_eq!(
+            listing.lines().nth(2).unwrap().trim_end(),
+            "0004  4 0                               out [0]  ; <- 0000"
+        );
+    }
+}
@@ -0,0 +1,114 @@
+use std::error::Error;
+use std::fmt;
+
+/// The specific reason an IntCode program could not continue
+#[derive(Debug, Clone, PartialEq)]
+pub enum ErrorKind {
+    /// the opcode at the pointer isn't one the emulator understands
+    UnknownOpcode,
+
+    /// a parameter (numbered from 1) has a mode other than position, immediate or relative
+    InvalidMode { parameter: usize, mode: i64 },
+
+    /// a position or relative parameter, or a jump target, resolved to a negative address
+    NegativeAddress { address: i64 },
+
+    /// a parameter (numbered from 1) which is written to was given in immediate mode
+    ImmediateWrite { parameter: usize },
+
+    /// the instruction at the pointer runs off the end of memory
+    TruncatedInstruction,
+
+    /// the program tried to read input but none was available
+    InputRequired,
+
+    /// the instruction budget ran out before the program halted
+    BudgetExhausted,
+
+    /// the deadline passed before the program halted
+    DeadlineExceeded,
+
+    /// writing to the address would take memory over its limit, as a number of cells
+    MemoryLimit { address: usize, limit: usize },
+
+    /// a read from beyond the end of memory, which isn't allowed in strict mode
+    ReadOutOfBounds { address: usize },
+
+    /// strict mode only allows the program as it was loaded to be executed, and the instruction at
+    /// the pointer reaches this address beyond it
+    ExecutedData { address: usize },
+
+    /// a custom opcode failed, for the reason given
+    Custom(String),
+}
+
+/// An error raised whilst executing an IntCode program, along with the state of the VM at the time
+#[derive(Debug, Clone, PartialEq)]
+pub struct IntCodeError {
+    pub kind: ErrorKind,
+    pub pointer: usize,
+    pub base: i64,
+
+    /// the raw opcode at the pointer, or None if the pointer is outside of memory
+    pub opcode: Option<i64>,
+}
+
+impl IntCodeError {
+    pub fn new(kind: ErrorKind, pointer: usize, base: i64, opcode: Option<i64>) -> IntCodeError {
+        IntCodeError {
+            kind,
+            pointer,
+            base,
+            opcode,
+        }
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ErrorKind::UnknownOpcode => write!(f, "unknown opcode"),
+            ErrorKind::InvalidMode { parameter, mode } => {
+                write!(f, "unsupported mode {} for parameter {}", mode, parameter)
+            }
+            ErrorKind::NegativeAddress { address } => write!(f, "negative address {}", address),
+            ErrorKind::ImmediateWrite { parameter } => {
+                write!(f, "write to parameter {} in immediate mode", parameter)
+            }
+            ErrorKind::TruncatedInstruction => write!(f, "truncated instruction"),
+            ErrorKind::InputRequired => write!(f, "input required but none received"),
+            ErrorKind::BudgetExhausted => write!(f, "instruction budget exhausted"),
+            ErrorKind::DeadlineExceeded => write!(f, "deadline exceeded"),
+            ErrorKind::MemoryLimit { address, limit } => write!(
+                f,
+                "write to {} exceeds the memory limit of {} cells",
+                address, limit
+            ),
+            ErrorKind::ReadOutOfBounds { address } => {
+                write!(f, "read from {} beyond the end of memory", address)
+            }
+            ErrorKind::ExecutedData { address } => {
+                write!(f, "executed data at {} beyond the program", address)
+            }
+            ErrorKind::Custom(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl fmt::Display for IntCodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} at pointer {} (base {}",
+            self.kind, self.pointer, self.base
+        )?;
+
+        if let Some(opcode) = self.opcode {
+            write!(f, ", opcode {}", opcode)?;
+        }
+
+        write!(f, ")")
+    }
+}
+
+impl Error for IntCodeError {}
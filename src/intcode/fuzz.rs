@@ -0,0 +1,461 @@
+use crate::intcode::compiler::CompiledEmulator;
+use crate::intcode::{ErrorKind, IntCodeEmulator, IntCodeError, MemoryBackend, YieldReason};
+use std::fmt;
+
+/// the most cells a program can use, since random values are easily large enough to exhaust memory
+/// when used as addresses
+pub const MEMORY_LIMIT: usize = 1 << 16;
+
+/// A small xorshift random number generator, so fuzzing is repeatable from a seed
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        // xorshift gets stuck at zero, and similar seeds give similar early values without mixing
+        Rng(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// a value in `0..n`
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    /// a value in `low..=high`
+    pub fn range(&mut self, low: i64, high: i64) -> i64 {
+        low + (self.next_u64() % (high - low + 1) as u64) as i64
+    }
+
+    /// true with the given percentage chance
+    pub fn chance(&mut self, percent: usize) -> bool {
+        self.below(100) < percent
+    }
+}
+
+/// A program and the input to give it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Case {
+    pub program: Vec<i64>,
+    pub inputs: Vec<i64>,
+}
+
+/// the number of parameters each opcode takes, and which of them is written to
+fn shape(opcode: i64) -> (usize, Option<usize>) {
+    match opcode {
+        1 | 2 | 7 | 8 => (3, Some(3)),
+        3 => (1, Some(1)),
+        4 | 9 => (1, None),
+        5 | 6 => (2, None),
+        _ => (0, None),
+    }
+}
+
+/// Generates a random program which is mostly well formed.
+///
+/// Every opcode is used with every valid mode, and parameters are chosen so that reads and writes
+/// often land on the program itself, so self-modifying code is common. Jumps usually target the
+/// start of an instruction. A few instructions have invalid opcodes or modes so that errors are
+/// covered too.
+pub fn generate(rng: &mut Rng) -> Case {
+    let instructions = instructions(rng);
+    let mut starts = Vec::with_capacity(instructions.len());
+    let mut len = 0;
+
+    for (_, modes) in &instructions {
+        starts.push(len);
+        len += 1 + modes.len();
+    }
+
+    // the code is followed by some data which writes and reads can share
+    let data = rng.below(8);
+    let size = (len + data) as i64;
+    let mut program = Vec::with_capacity(len + data);
+
+    for (opcode, modes) in instructions {
+        let jump = opcode == 5 || opcode == 6;
+        let encoded = modes
+            .iter()
+            .enumerate()
+            .fold(opcode, |encoded, (index, mode)| {
+                encoded + mode * 10_i64.pow(index as u32 + 2)
+            });
+        program.push(encoded);
+
+        for (index, &mode) in modes.iter().enumerate() {
+            let value = match mode {
+                1 if jump && index == 1 && !rng.chance(10) => {
+                    starts[rng.below(starts.len())] as i64
+                }
+                // negative bases and addresses are errors, so only produce them occasionally
+                1 if opcode == 9 && rng.chance(10) => rng.range(-4, -1),
+                1 if opcode == 9 => rng.range(0, 8),
+                1 => rng.range(-10, 100),
+                2 if rng.chance(5) => rng.range(-6, -1),
+                2 => rng.range(0, 12),
+                _ if rng.chance(1) => rng.range(-3, -1),
+                // writes to the code are often fatal, so favour the data after it
+                _ if rng.chance(70) => rng.range(len as i64, size + 4),
+                _ => rng.range(0, size + 4),
+            };
+            program.push(value);
+        }
+    }
+
+    for _ in 0..data {
+        program.push(rng.range(0, 30));
+    }
+
+    let inputs = (0..rng.below(6)).map(|_| rng.range(-5, 30)).collect();
+    Case { program, inputs }
+}
+
+/// chooses the opcode and parameter modes of each instruction in the code, which always ends with a
+/// halt
+fn instructions(rng: &mut Rng) -> Vec<(i64, Vec<i64>)> {
+    let count = 1 + rng.below(24);
+    let mut instructions = Vec::with_capacity(count + 1);
+
+    for _ in 0..count {
+        let opcode = match rng.below(100) {
+            0..=3 => 99,
+            4 if rng.chance(50) => [0, 10, 42, -1][rng.below(4)],
+            _ => 1 + rng.below(9) as i64,
+        };
+        let (parameters, written) = shape(opcode);
+
+        let mut modes = Vec::with_capacity(parameters);
+        for parameter in 1..=parameters {
+            let mode = match rng.below(100) {
+                0 if rng.chance(50) => 3 + rng.below(7) as i64,
+                _ if Some(parameter) == written && !rng.chance(1) => [0, 2][rng.below(2)],
+                _ => rng.below(3) as i64,
+            };
+            modes.push(mode);
+        }
+
+        instructions.push((opcode, modes));
+    }
+
+    instructions.push((99, Vec::new()));
+    instructions
+}
+
+/// How a run finished
+#[derive(Debug, Clone, PartialEq)]
+pub enum End {
+    Yielded(YieldReason),
+    Failed(ErrorKind),
+}
+
+/// Everything observable about a finished run
+#[derive(Debug, Clone, PartialEq)]
+pub struct Outcome {
+    pub end: End,
+    pub pointer: usize,
+    pub base: i64,
+    pub ram: Vec<i64>,
+    pub output: Vec<i64>,
+}
+
+/// Runs a program with a deliberately simple interpreter, written straight from the puzzle
+/// descriptions, to check the real emulators against. It stops after `budget` instructions, or
+/// when a write would go beyond `MEMORY_LIMIT`.
+pub fn reference(case: &Case, budget: u64) -> Outcome {
+    let mut ram = case.program.clone();
+    let mut inputs = case.inputs.iter().copied();
+    let mut output = Vec::new();
+    let (mut pointer, mut base) = (0, 0);
+
+    let end = (|| {
+        for _ in 0..budget {
+            match reference_step(&mut ram, &mut pointer, &mut base, &mut inputs, &mut output)? {
+                Some(reason) => return Ok(reason),
+                None => continue,
+            }
+        }
+
+        Ok(YieldReason::BudgetExhausted)
+    })();
+
+    Outcome {
+        end: match end {
+            Ok(reason) => End::Yielded(reason),
+            Err(kind) => End::Failed(kind),
+        },
+        pointer,
+        base,
+        ram,
+        output,
+    }
+}
+
+/// executes one instruction, returning the reason to stop if it halts or needs input
+fn reference_step<I: Iterator<Item = i64>>(
+    ram: &mut Vec<i64>,
+    pointer: &mut usize,
+    base: &mut i64,
+    inputs: &mut I,
+    output: &mut Vec<i64>,
+) -> Result<Option<YieldReason>, ErrorKind> {
+    let cell = |address: usize| {
+        ram.get(address)
+            .copied()
+            .ok_or(ErrorKind::TruncatedInstruction)
+    };
+    let to_address = |value: i64| {
+        if value < 0 {
+            return Err(ErrorKind::NegativeAddress { address: value });
+        }
+        Ok(value as usize)
+    };
+
+    let opcode = cell(*pointer)?;
+    let (parameters, written) = match opcode % 100 {
+        99 => return Ok(Some(YieldReason::Halted)),
+        1..=9 => shape(opcode % 100),
+        _ => return Err(ErrorKind::UnknownOpcode),
+    };
+
+    // decode every parameter to an address, or a value for immediate mode, before doing anything
+    let mut decoded = Vec::with_capacity(parameters);
+    for parameter in 1..=parameters {
+        let raw = cell(*pointer + parameter)?;
+        let mode = (opcode / 10_i64.pow(parameter as u32 + 1)) % 10;
+
+        decoded.push(match mode {
+            0 => (raw, false),
+            1 if Some(parameter) == written => return Err(ErrorKind::ImmediateWrite { parameter }),
+            1 => (raw, true),
+            2 => (raw.wrapping_add(*base), false),
+            _ => return Err(ErrorKind::InvalidMode { parameter, mode }),
+        });
+    }
+
+    let read = |ram: &Vec<i64>, parameter: usize| -> Result<i64, ErrorKind> {
+        let (value, immediate) = decoded[parameter - 1];
+        if immediate {
+            return Ok(value);
+        }
+        Ok(ram.get(to_address(value)?).copied().unwrap_or(0))
+    };
+    let write = |ram: &mut Vec<i64>, parameter: usize, value: i64| -> Result<(), ErrorKind> {
+        let address = to_address(decoded[parameter - 1].0)?;
+        if address >= MEMORY_LIMIT {
+            return Err(ErrorKind::MemoryLimit {
+                address,
+                limit: MEMORY_LIMIT,
+            });
+        }
+        if address >= ram.len() {
+            ram.resize(address + 1, 0);
+        }
+        ram[address] = value;
+        Ok(())
+    };
+
+    match opcode % 100 {
+        1 => {
+            let value = read(ram, 1)?.wrapping_add(read(ram, 2)?);
+            write(ram, 3, value)?;
+        }
+        2 => {
+            let value = read(ram, 1)?.wrapping_mul(read(ram, 2)?);
+            write(ram, 3, value)?;
+        }
+        3 => match inputs.next() {
+            Some(value) => write(ram, 1, value)?,
+            None => return Ok(Some(YieldReason::InputRequired)),
+        },
+        4 => output.push(read(ram, 1)?),
+        5 | 6 => {
+            let condition = read(ram, 1)?;
+            if (condition != 0) == (opcode % 100 == 5) {
+                *pointer = to_address(read(ram, 2)?)?;
+                return Ok(None);
+            }
+        }
+        7 => {
+            let value = (read(ram, 1)? < read(ram, 2)?) as i64;
+            write(ram, 3, value)?;
+        }
+        8 => {
+            let value = (read(ram, 1)? == read(ram, 2)?) as i64;
+            write(ram, 3, value)?;
+        }
+        _ => *base = base.wrapping_add(read(ram, 1)?),
+    }
+
+    *pointer += 1 + parameters;
+    Ok(None)
+}
+
+/// A difference between an emulator and the reference interpreter
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mismatch {
+    pub engine: &'static str,
+    pub case: Case,
+    pub expected: Outcome,
+    pub actual: Outcome,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let program: Vec<String> = self.case.program.iter().map(|v| v.to_string()).collect();
+
+        writeln!(f, "{} differs from the reference", self.engine)?;
+        writeln!(f, "program: {}", program.join(","))?;
+        writeln!(f, "inputs: {:?}", self.case.inputs)?;
+        writeln!(f, "expected: {:?}", self.expected)?;
+        write!(f, "actual: {:?}", self.actual)
+    }
+}
+
+fn outcome(vm: &mut IntCodeEmulator, result: Result<YieldReason, IntCodeError>) -> Outcome {
+    Outcome {
+        end: match result {
+            Ok(reason) => End::Yielded(reason),
+            Err(e) => End::Failed(e.kind),
+        },
+        pointer: vm.pointer(),
+        base: vm.base(),
+        ram: vm.ram().to_vec(),
+        output: vm.stdout().iter().copied().collect(),
+    }
+}
+
+/// runs a case on every engine, returning the first which doesn't match the reference
+pub fn check(case: &Case, budget: u64) -> Result<(), Box<Mismatch>> {
+    let expected = reference(case, budget);
+
+    let mut template = IntCodeEmulator::new(case.program.clone());
+    template.stdin().extend(case.inputs.iter().copied());
+    template.set_instruction_budget(Some(budget));
+    template.set_memory_limit(Some(MEMORY_LIMIT));
+
+    let interpreted = |configure: fn(&mut IntCodeEmulator)| {
+        let mut vm = template.clone();
+        configure(&mut vm);
+        let result = vm.execute_until_yield();
+        outcome(&mut vm, result)
+    };
+
+    let mut compiled = CompiledEmulator::new(template.clone());
+    let result = compiled.execute_until_yield();
+    let mut vm = compiled.into_inner();
+
+    let mut engines = vec![
+        ("interpreter", interpreted(|_| {})),
+        ("decode cache", interpreted(|vm| vm.enable_decode_cache())),
+        ("compiler", outcome(&mut vm, result)),
+    ];
+
+    // paged memory counts its limit in whole pages, so only stops at the same point if the
+    // program stays within the limit
+    if !matches!(expected.end, End::Failed(ErrorKind::MemoryLimit { .. })) {
+        engines.push((
+            "paged memory",
+            interpreted(|vm| {
+                vm.set_memory_backend(MemoryBackend::Paged);
+                vm.set_memory_limit(None);
+            }),
+        ));
+    }
+
+    for (engine, actual) in engines {
+        if actual != expected {
+            return Err(Box::new(Mismatch {
+                engine,
+                case: case.clone(),
+                expected,
+                actual,
+            }));
+        }
+    }
+
+    Ok(())
+}
+
+/// generates and checks `cases` random programs, stopping at the first mismatch
+pub fn fuzz(seed: u64, cases: usize, budget: u64) -> Result<(), Box<Mismatch>> {
+    let mut rng = Rng::new(seed);
+
+    for _ in 0..cases {
+        check(&generate(&mut rng), budget)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn reference_solves_puzzles() {
+        let mut program = IntCodeEmulator::parse_input(include_str!("../../input/2019/day2.txt"));
+        program[1] = 12;
+        program[2] = 2;
+        let case = Case {
+            program,
+            inputs: Vec::new(),
+        };
+        assert_eq!(reference(&case, 1000).ram[0], 6_627_023);
+
+        let case = Case {
+            program: IntCodeEmulator::parse_input(include_str!("../../input/2019/day9.txt")),
+            inputs: vec![1],
+        };
+        let outcome = reference(&case, 1_000_000);
+        assert_eq!(outcome.end, End::Yielded(YieldReason::Halted));
+        assert_eq!(outcome.output, vec![4_006_117_640]);
+    }
+
+    #[test]
+    fn covers_every_opcode_and_mode() {
+        let mut rng = Rng::new(1);
+        let mut seen = HashSet::new();
+
+        for _ in 0..500 {
+            for (opcode, modes) in instructions(&mut rng) {
+                for (index, mode) in modes.into_iter().enumerate() {
+                    seen.insert((opcode, index + 1, mode));
+                }
+            }
+        }
+
+        for opcode in 1..=9 {
+            let (parameters, written) = shape(opcode);
+
+            for parameter in 1..=parameters {
+                for mode in 0..=2 {
+                    if Some(parameter) == written && mode == 1 {
+                        continue;
+                    }
+
+                    assert!(
+                        seen.contains(&(opcode, parameter, mode)),
+                        "opcode {} parameter {} mode {}",
+                        opcode,
+                        parameter,
+                        mode
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn engines_match_reference() {
+        if let Err(mismatch) = fuzz(2019, 2000, 2000) {
+            panic!("{}", mismatch);
+        }
+    }
+}
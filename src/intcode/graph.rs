@@ -0,0 +1,384 @@
+use crate::intcode::{Instruction, ReadValue, WriteValue};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+/// How control leaves a basic block
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Exit {
+    Halt,
+
+    /// execution runs on into the next block, which starts at a jump target
+    FallThrough(usize),
+
+    /// an unconditional jump to a known address
+    Jump(usize),
+
+    /// a conditional jump to a known address
+    Branch {
+        taken: usize,
+        not_taken: usize,
+    },
+
+    /// an unconditional jump to a function, after storing the address it returns to
+    Call {
+        target: usize,
+        returns: usize,
+    },
+
+    /// an unconditional jump to an address read relative to the base, i.e. a return from a function
+    Return,
+
+    /// a jump to an address which isn't known until runtime, which may fall through if conditional
+    Indirect {
+        not_taken: Option<usize>,
+    },
+
+    /// the next instruction can't be decoded, so is probably data
+    Invalid,
+}
+
+impl Exit {
+    /// the addresses control can pass to directly, including where a call returns to
+    pub fn successors(&self) -> Vec<usize> {
+        match *self {
+            Exit::FallThrough(next) | Exit::Jump(next) => vec![next],
+            Exit::Branch { taken, not_taken } => vec![taken, not_taken],
+            Exit::Call { target, returns } => vec![target, returns],
+            Exit::Indirect {
+                not_taken: Some(next),
+            } => vec![next],
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// A straight-line run of instructions which is only entered at the top and left at the bottom
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub start: usize,
+
+    /// the address after the last instruction
+    pub end: usize,
+
+    pub instructions: Vec<(usize, Instruction)>,
+    pub exit: Exit,
+}
+
+/// The control-flow graph of the code reachable from address 0
+#[derive(Debug, Clone, PartialEq)]
+pub struct Graph {
+    pub blocks: BTreeMap<usize, Block>,
+
+    /// the entry points of functions, found from calls
+    pub functions: BTreeSet<usize>,
+}
+
+/// Builds the control-flow graph of a program by following every path from address 0.
+///
+/// Only jumps with immediate mode targets can be followed. Calls are recognised by the usual
+/// convention of storing a constant return address relative to the base before jumping
+/// unconditionally, and returns by an unconditional jump to an address read relative to the base.
+/// Code which is only reached through other indirect jumps won't be found.
+pub fn analyse(program: &[i64]) -> Graph {
+    let mut leaders = BTreeSet::new();
+    leaders.insert(0);
+
+    // the first pass finds every block's start, but blocks found early may need to be split by
+    // targets found later, so build them all again once every start is known
+    let mut pending = vec![0];
+    let mut explored = BTreeSet::new();
+
+    while let Some(start) = pending.pop() {
+        if !explored.insert(start) {
+            continue;
+        }
+
+        for next in build_block(program, start, &leaders).exit.successors() {
+            leaders.insert(next);
+            pending.push(next);
+        }
+    }
+
+    let mut blocks = BTreeMap::new();
+    let mut functions = BTreeSet::new();
+
+    for &start in &leaders {
+        let block = build_block(program, start, &leaders);
+
+        if let Exit::Call { target, .. } = block.exit {
+            functions.insert(target);
+        }
+
+        blocks.insert(start, block);
+    }
+
+    Graph { blocks, functions }
+}
+
+fn build_block(program: &[i64], start: usize, leaders: &BTreeSet<usize>) -> Block {
+    let mut instructions = Vec::new();
+    let mut address = start;
+
+    // constants written relative to the base in this block, which may be return addresses
+    let mut stored = Vec::new();
+
+    let exit = loop {
+        if address != start && leaders.contains(&address) {
+            break Exit::FallThrough(address);
+        }
+
+        let instruction = match Instruction::parse(program, address) {
+            Ok(instruction) => instruction,
+            Err(_) => break Exit::Invalid,
+        };
+
+        instructions.push((address, instruction));
+        let next = address + instruction.size();
+
+        if let Some(value) = stored_constant(&instruction) {
+            stored.push(value);
+        }
+
+        let (condition, dest, when) = match instruction {
+            Instruction::Halt => break Exit::Halt,
+            Instruction::JumpTrue(condition, dest) => (condition, dest, true),
+            Instruction::JumpFalse(condition, dest) => (condition, dest, false),
+            _ => {
+                address = next;
+                continue;
+            }
+        };
+
+        let always = match condition {
+            ReadValue::Immediate(value) if (value != 0) == when => true,
+            ReadValue::Immediate(_) => {
+                // the jump is never taken
+                address = next;
+                continue;
+            }
+            _ => false,
+        };
+
+        break match (dest, always) {
+            (ReadValue::Immediate(target), true) if target >= 0 => {
+                let target = target as usize;
+
+                // a call stores the address to return to, which is straight after it
+                if next < program.len() && stored.contains(&(next as i64)) {
+                    Exit::Call {
+                        target,
+                        returns: next,
+                    }
+                } else {
+                    Exit::Jump(target)
+                }
+            }
+            (ReadValue::Immediate(target), false) if target >= 0 => Exit::Branch {
+                taken: target as usize,
+                not_taken: next,
+            },
+            (ReadValue::Immediate(_), true) => Exit::Invalid,
+            (ReadValue::Immediate(_), false) => Exit::FallThrough(next),
+            (ReadValue::Relative(_), true) => Exit::Return,
+            (_, true) => Exit::Indirect { not_taken: None },
+            (_, false) => Exit::Indirect {
+                not_taken: Some(next),
+            },
+        };
+    };
+
+    Block {
+        start,
+        end: instructions
+            .last()
+            .map_or(start, |(address, instruction)| address + instruction.size()),
+        instructions,
+        exit,
+    }
+}
+
+/// returns the value written by an instruction which stores a constant relative to the base
+fn stored_constant(instruction: &Instruction) -> Option<i64> {
+    match *instruction {
+        Instruction::Add(
+            ReadValue::Immediate(left),
+            ReadValue::Immediate(right),
+            WriteValue::Relative(_),
+        ) => Some(left.wrapping_add(right)),
+        Instruction::Multiply(
+            ReadValue::Immediate(left),
+            ReadValue::Immediate(right),
+            WriteValue::Relative(_),
+        ) => Some(left.wrapping_mul(right)),
+        _ => None,
+    }
+}
+
+impl Graph {
+    /// formats the graph in Graphviz's DOT language. Function entry points are drawn with a double
+    /// border, and the edge from a call to where it returns is dashed
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::new();
+
+        let _ = writeln!(dot, "digraph intcode {{");
+        let _ = writeln!(dot, "    node [shape=box, fontname=\"monospace\"];");
+
+        for block in self.blocks.values() {
+            let mut label = String::new();
+
+            for (address, instruction) in &block.instructions {
+                let _ = write!(label, "{:04}  {}\\l", address, instruction);
+            }
+
+            match block.exit {
+                Exit::Return => label.push_str("return\\l"),
+                Exit::Indirect { .. } => label.push_str("indirect jump\\l"),
+                Exit::Invalid => label.push_str("invalid instruction\\l"),
+                _ => {}
+            }
+
+            let peripheries = if self.functions.contains(&block.start) {
+                ", peripheries=2"
+            } else {
+                ""
+            };
+
+            let _ = writeln!(
+                dot,
+                "    b{} [label=\"{}\"{}];",
+                block.start, label, peripheries
+            );
+        }
+
+        for block in self.blocks.values() {
+            let from = block.start;
+
+            match block.exit {
+                Exit::FallThrough(to) | Exit::Jump(to) => {
+                    let _ = writeln!(dot, "    b{} -> b{};", from, to);
+                }
+                Exit::Branch { taken, not_taken } => {
+                    let _ = writeln!(dot, "    b{} -> b{} [label=\"taken\"];", from, taken);
+                    let _ = writeln!(
+                        dot,
+                        "    b{} -> b{} [label=\"not taken\"];",
+                        from, not_taken
+                    );
+                }
+                Exit::Call { target, returns } => {
+                    let _ = writeln!(dot, "    b{} -> b{} [label=\"call\"];", from, target);
+                    let _ = writeln!(dot, "    b{} -> b{} [style=dashed];", from, returns);
+                }
+                Exit::Indirect {
+                    not_taken: Some(next),
+                } => {
+                    let _ = writeln!(dot, "    b{} -> b{} [label=\"not taken\"];", from, next);
+                }
+                _ => {}
+            }
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::assembler::assemble;
+    use crate::intcode::IntCodeEmulator;
+
+    /// calls a function which doubles its argument, unless the input was zero
+    const CALLER: &str = "
+                arb #100
+                in [rb+1]
+                jf [rb+1], #done
+                add #back, #0, [rb+0]
+                jt #1, #double
+        back:   out [rb+1]
+        done:   hlt
+        double: arb #2
+                mul [rb-1], #2, [rb-1]
+                arb #-2
+                jf #0, [rb+0]
+    ";
+
+    #[test]
+    fn finds_blocks_calls_and_returns() {
+        let program = assemble(CALLER).unwrap();
+        let graph = analyse(&program);
+
+        let exits: Vec<(usize, Exit)> = graph
+            .blocks
+            .values()
+            .map(|block| (block.start, block.exit))
+            .collect();
+
+        assert_eq!(
+            exits,
+            vec![
+                (
+                    0,
+                    Exit::Branch {
+                        taken: 16,
+                        not_taken: 7
+                    }
+                ),
+                (
+                    7,
+                    Exit::Call {
+                        target: 17,
+                        returns: 14
+                    }
+                ),
+                (14, Exit::FallThrough(16)),
+                (16, Exit::Halt),
+                (17, Exit::Return),
+            ]
+        );
+        assert_eq!(graph.functions, vec![17].into_iter().collect());
+        assert_eq!(graph.blocks[&17].end, 28);
+    }
+
+    #[test]
+    fn only_calls_when_storing_return_address() {
+        // stores a constant for the callee's frame, but not an address to return to
+        let program = assemble(
+            "
+                    add #5, #0, [rb+1]
+                    jt #1, #end
+                    out #1
+            end:    hlt
+            ",
+        )
+        .unwrap();
+
+        let graph = analyse(&program);
+        assert_eq!(graph.blocks[&0].exit, Exit::Jump(9));
+        assert!(graph.functions.is_empty());
+    }
+
+    #[test]
+    fn exports_dot() {
+        let graph = analyse(&assemble(CALLER).unwrap());
+        let dot = graph.to_dot();
+
+        assert!(dot.starts_with("digraph intcode {\n"));
+        assert!(dot.contains("    b17 [label=\"0017  arb #2\\l"));
+        assert!(dot.contains("return\\l\", peripheries=2];\n"));
+        assert!(dot.contains("    b7 -> b17 [label=\"call\"];\n"));
+        assert!(dot.contains("    b7 -> b14 [style=dashed];\n"));
+        assert!(dot.contains("    b0 -> b16 [label=\"taken\"];\n"));
+    }
+
+    #[test]
+    fn analyses_puzzles() {
+        let program = IntCodeEmulator::parse_input(include_str!("../../input/2019/day9.txt"));
+        let graph = analyse(&program);
+
+        // the boost program computes its answer with a recursive function
+        assert!(graph.functions.contains(&922));
+        assert_eq!(graph.blocks[&922].exit.successors(), vec![964, 931]);
+    }
+}
@@ -0,0 +1,270 @@
+use crate::intcode::{IntCodeEmulator, IntCodeError, Observer, StepResult};
+use std::collections::VecDeque;
+
+/// Everything a single instruction changed, so that it can be undone. Standard instructions change
+/// at most one of each, but custom opcodes from a dialect can do any number of them
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    /// the pointer and base before the instruction executed
+    pub pointer: usize,
+    pub base: i64,
+
+    /// the length of memory before the instruction executed, since writes can grow it
+    pub len: usize,
+
+    /// the instruction budget before the instruction executed, which it spent from
+    pub budget: Option<u64>,
+
+    /// each cell written and the value it held before the instruction, in the order first written
+    pub writes: Vec<(usize, i64)>,
+
+    /// the values taken from stdin, in order
+    pub inputs: Vec<i64>,
+
+    /// the values sent to stdout, in order
+    pub outputs: Vec<i64>,
+}
+
+/// An undo log of every instruction stepped, so execution can be run backwards.
+///
+/// Each step costs around a hundred bytes whatever the size of memory, so the log can cover long
+/// runs, but a limit can be set to only keep the most recent steps.
+#[derive(Debug, Clone, Default)]
+pub struct History {
+    changes: VecDeque<Change>,
+    limit: Option<usize>,
+
+    /// the change being recorded by the current step
+    current: Option<Change>,
+}
+
+impl History {
+    pub fn new() -> History {
+        History::default()
+    }
+
+    /// the most steps which are kept, if the history is limited
+    pub fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    /// limits the number of steps kept, forgetting the oldest
+    pub fn set_limit(&mut self, limit: Option<usize>) {
+        self.limit = limit;
+        self.trim();
+    }
+
+    /// the number of steps which can be undone
+    pub fn len(&self) -> usize {
+        self.changes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.changes.clear();
+    }
+
+    /// every step which can be undone, oldest first
+    pub fn changes(&self) -> impl Iterator<Item = &Change> {
+        self.changes.iter()
+    }
+
+    /// executes a single instruction, recording what it changed
+    pub fn step(&mut self, vm: &mut IntCodeEmulator) -> Result<StepResult, IntCodeError> {
+        self.current = Some(Change {
+            pointer: vm.pointer(),
+            base: vm.base(),
+            len: vm.ram().len(),
+            budget: vm.instruction_budget(),
+            writes: Vec::new(),
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+        });
+
+        let result = vm.step_with(self);
+        let change = self.current.take();
+
+        // instructions which need input or halt don't change anything
+        if let (Ok(StepResult::Continue), Some(change)) = (&result, change) {
+            self.changes.push_back(change);
+            self.trim();
+        }
+
+        result
+    }
+
+    /// reverts the most recent step, returning what it changed, or None if there are no steps left
+    pub fn undo(&mut self, vm: &mut IntCodeEmulator) -> Option<Change> {
+        let change = self.changes.pop_back()?;
+
+        for &(address, old) in &change.writes {
+            vm.poke(address, old);
+        }
+
+        for &value in change.inputs.iter().rev() {
+            vm.stdin().push_front(value);
+        }
+
+        for _ in &change.outputs {
+            vm.stdout().pop_back();
+        }
+
+        vm.ram.truncate(change.len);
+        vm.set_pointer(change.pointer);
+        vm.set_base(change.base);
+        vm.set_instruction_budget(change.budget);
+
+        Some(change)
+    }
+
+    /// the number of steps back to the most recent write of a cell, if it's still in the history
+    pub fn last_write(&self, address: usize) -> Option<usize> {
+        self.changes
+            .iter()
+            .rev()
+            .position(|change| change.writes.iter().any(|&(written, _)| written == address))
+            .map(|steps| steps + 1)
+    }
+
+    fn trim(&mut self) {
+        if let Some(limit) = self.limit {
+            while self.changes.len() > limit {
+                self.changes.pop_front();
+            }
+        }
+    }
+}
+
+impl Observer for History {
+    fn write(&mut self, address: usize, old: i64, _new: i64) {
+        if let Some(change) = &mut self.current {
+            // only the value from before the instruction is needed to undo it
+            if change.writes.iter().all(|&(written, _)| written != address) {
+                change.writes.push((address, old));
+            }
+        }
+    }
+
+    fn input(&mut self, value: i64) {
+        if let Some(change) = &mut self.current {
+            change.inputs.push(value);
+        }
+    }
+
+    fn output(&mut self, value: i64) {
+        if let Some(change) = &mut self.current {
+            change.outputs.push(value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::assembler::assemble;
+    use crate::intcode::dialect::{Dialect, Effect, Opcode, Parameter};
+    use crate::intcode::MemoryBackend;
+
+    /// adds pairs of inputs into a cell far beyond the program
+    const ADDER: &str = "
+        loop:   in [a]
+                in [b]
+                add [a], [b], [5000]
+                out [5000]
+                jt #1, #loop
+        a:      data 0
+        b:      data 0
+    ";
+
+    #[test]
+    fn undoes_every_change() {
+        for &backend in [MemoryBackend::Dense, MemoryBackend::Paged].iter() {
+            let mut vm = IntCodeEmulator::new(assemble(ADDER).unwrap());
+            vm.set_memory_backend(backend);
+            vm.stdin().extend(vec![1, 2, 3, 4]);
+            vm.set_instruction_budget(Some(100));
+
+            let start = vm.clone();
+            let mut history = History::new();
+
+            while history.step(&mut vm).unwrap() == StepResult::Continue {}
+            assert_eq!(vm.stdout(), &[3, 7]);
+            assert_eq!(history.len(), 10);
+
+            while history.undo(&mut vm).is_some() {}
+            assert_eq!(vm.ram(), start.ram());
+            assert_eq!((vm.pointer(), vm.base()), (0, 0));
+            assert_eq!(vm.stdin(), &[1, 2, 3, 4]);
+            assert!(vm.stdout().is_empty());
+            assert_eq!(vm.instruction_budget(), Some(100));
+        }
+    }
+
+    #[test]
+    fn finds_last_write() {
+        let mut vm = IntCodeEmulator::new(assemble(ADDER).unwrap());
+        vm.stdin().extend(vec![1, 2, 3, 4]);
+
+        let mut history = History::new();
+        while history.step(&mut vm).unwrap() == StepResult::Continue {}
+
+        // the second add is three steps back, followed by an output and a jump
+        assert_eq!(history.last_write(5000), Some(3));
+        assert_eq!(history.last_write(1), None);
+    }
+
+    #[test]
+    fn undoes_custom_opcodes() {
+        // takes two inputs, writing both to the first parameter and their sum to the second
+        let dialect = Dialect::day09().opcode(
+            20,
+            Opcode::new("in2", &[Parameter::Write, Parameter::Write], |op| {
+                let (first, second) = match (op.input(), op.input()) {
+                    (Some(first), Some(second)) => (first, second),
+                    _ => return Ok(Effect::InputRequired),
+                };
+
+                op.write(1, first)?;
+                op.write(1, second)?;
+                op.write(2, first + second)?;
+                op.output(first);
+                op.output(second);
+                Ok(Effect::Continue)
+            }),
+        );
+
+        let mut vm = IntCodeEmulator::new(vec![20, 4, 5, 99, 7, 8]);
+        vm.set_dialect(Some(dialect));
+        vm.stdin().extend(vec![1, 2]);
+
+        let start = vm.clone();
+        let mut history = History::new();
+
+        assert_eq!(history.step(&mut vm), Ok(StepResult::Continue));
+        assert_eq!((vm.peek(4), vm.peek(5)), (2, 3));
+        assert_eq!(history.last_write(5), Some(1));
+
+        history.undo(&mut vm).unwrap();
+        assert_eq!(vm.ram(), start.ram());
+        assert_eq!(vm.stdin(), &[1, 2]);
+        assert!(vm.stdout().is_empty());
+    }
+
+    #[test]
+    fn forgets_old_steps() {
+        let mut vm = IntCodeEmulator::new(assemble(ADDER).unwrap());
+        vm.stdin().extend(vec![1, 2, 3, 4]);
+
+        let mut history = History::new();
+        history.set_limit(Some(4));
+        while history.step(&mut vm).unwrap() == StepResult::Continue {}
+
+        assert_eq!(history.len(), 4);
+        while history.undo(&mut vm).is_some() {}
+        assert_eq!(vm.pointer(), 2);
+        assert_eq!(vm.stdin(), &[4]);
+    }
+}
@@ -0,0 +1,346 @@
+use crate::intcode::ErrorKind;
+use std::collections::HashMap;
+use std::ops::Index;
+
+/// the number of cells in each page of paged memory
+pub const PAGE_SIZE: usize = 1024;
+
+/// Anything which instructions can be decoded from
+pub trait Cells {
+    /// returns the value of a cell, or None if it's beyond the end of memory
+    fn cell(&self, address: usize) -> Option<i64>;
+}
+
+impl Cells for [i64] {
+    fn cell(&self, address: usize) -> Option<i64> {
+        self.get(address).copied()
+    }
+}
+
+impl Cells for Vec<i64> {
+    fn cell(&self, address: usize) -> Option<i64> {
+        self.get(address).copied()
+    }
+}
+
+/// How memory is stored
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MemoryBackend {
+    /// a single block of cells, grown to cover the highest address written
+    Dense,
+
+    /// pages of `PAGE_SIZE` cells, only allocated once a cell within them is written
+    Paged,
+}
+
+#[derive(Debug, Clone)]
+enum Storage {
+    Dense(Vec<i64>),
+    Paged(HashMap<usize, Box<[i64]>>),
+}
+
+/// The memory of a VM.
+///
+/// Memory behaves the same whichever backend is used: it extends up to the highest address written,
+/// and any cell which hasn't been written reads as zero. With dense memory, writing to a huge address
+/// allocates every cell below it, whereas paged memory only allocates the page it falls in. Either
+/// way, a limit can be placed on the number of cells allocated, and a write which would take memory
+/// over the limit fails instead.
+#[derive(Debug, Clone)]
+pub struct Memory {
+    storage: Storage,
+    len: usize,
+    limit: Option<usize>,
+}
+
+impl Memory {
+    /// the address of the last cell in memory, plus one
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn backend(&self) -> MemoryBackend {
+        match self.storage {
+            Storage::Dense(_) => MemoryBackend::Dense,
+            Storage::Paged(_) => MemoryBackend::Paged,
+        }
+    }
+
+    /// moves every cell into the given backend
+    pub fn set_backend(&mut self, backend: MemoryBackend) {
+        if self.backend() == backend {
+            return;
+        }
+
+        let mut memory = match backend {
+            MemoryBackend::Dense => Memory::from(self.to_vec()),
+            MemoryBackend::Paged => Memory::paged(&self.to_vec()),
+        };
+
+        memory.len = self.len;
+        memory.limit = self.limit;
+        *self = memory;
+    }
+
+    /// the most cells which can be allocated, if memory is limited
+    pub fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    pub fn set_limit(&mut self, limit: Option<usize>) {
+        self.limit = limit;
+    }
+
+    /// the number of cells allocated
+    pub fn allocated(&self) -> usize {
+        match &self.storage {
+            Storage::Dense(cells) => cells.len(),
+            Storage::Paged(pages) => pages.len() * PAGE_SIZE,
+        }
+    }
+
+    /// returns the value of a cell, or None if it's beyond the end of memory
+    pub fn get(&self, address: usize) -> Option<i64> {
+        if address >= self.len {
+            return None;
+        }
+
+        Some(self[address])
+    }
+
+    /// returns the value of a cell, treating anything outside of memory as 0
+    pub fn read(&self, address: usize) -> i64 {
+        self[address]
+    }
+
+    /// checks that writing to the address wouldn't take memory over its limit
+    pub fn check(&self, address: usize) -> Result<(), ErrorKind> {
+        let limit = match self.limit {
+            Some(limit) => limit,
+            None => return Ok(()),
+        };
+
+        let allocated = match &self.storage {
+            Storage::Dense(cells) => cells.len().max(address.saturating_add(1)),
+            Storage::Paged(pages) if pages.contains_key(&(address / PAGE_SIZE)) => self.allocated(),
+            Storage::Paged(_) => self.allocated() + PAGE_SIZE,
+        };
+
+        if allocated > limit {
+            return Err(ErrorKind::MemoryLimit { address, limit });
+        }
+
+        Ok(())
+    }
+
+    /// writes a cell, growing memory if required. The limit isn't checked, so use `check` first if
+    /// it should be
+    pub fn set(&mut self, address: usize, value: i64) {
+        match &mut self.storage {
+            Storage::Dense(cells) => {
+                if address >= cells.len() {
+                    cells.resize(address + 1, 0);
+                }
+                cells[address] = value;
+            }
+            Storage::Paged(pages) => {
+                let page = pages
+                    .entry(address / PAGE_SIZE)
+                    .or_insert_with(|| vec![0; PAGE_SIZE].into_boxed_slice());
+                page[address % PAGE_SIZE] = value;
+            }
+        }
+
+        self.len = self.len.max(address + 1);
+    }
+
+    /// shrinks memory back to the given length, clearing every cell beyond it
+    pub(crate) fn truncate(&mut self, len: usize) {
+        if len >= self.len {
+            return;
+        }
+
+        match &mut self.storage {
+            Storage::Dense(cells) => cells.truncate(len),
+            Storage::Paged(pages) => {
+                pages.retain(|&index, _| index * PAGE_SIZE < len);
+
+                if let Some(page) = pages.get_mut(&(len / PAGE_SIZE)) {
+                    for cell in page[len % PAGE_SIZE..].iter_mut() {
+                        *cell = 0;
+                    }
+                }
+            }
+        }
+
+        self.len = len;
+    }
+
+    /// iterates over every cell in memory
+    pub fn iter(&self) -> impl Iterator<Item = i64> + '_ {
+        (0..self.len).map(move |address| self[address])
+    }
+
+    pub fn to_vec(&self) -> Vec<i64> {
+        match &self.storage {
+            Storage::Dense(cells) => cells.clone(),
+            Storage::Paged(_) => self.iter().collect(),
+        }
+    }
+
+    /// creates paged memory containing a program
+    pub fn paged(program: &[i64]) -> Memory {
+        let pages = program
+            .chunks(PAGE_SIZE)
+            .enumerate()
+            .filter(|(_, chunk)| chunk.iter().any(|&cell| cell != 0))
+            .map(|(index, chunk)| {
+                let mut page = vec![0; PAGE_SIZE];
+                page[..chunk.len()].copy_from_slice(chunk);
+                (index, page.into_boxed_slice())
+            })
+            .collect();
+
+        Memory {
+            storage: Storage::Paged(pages),
+            len: program.len(),
+            limit: None,
+        }
+    }
+
+    /// the allocated pages in address order, or None if memory isn't paged
+    pub(crate) fn pages(&self) -> Option<Vec<(usize, &[i64])>> {
+        let mut pages: Vec<(usize, &[i64])> = match &self.storage {
+            Storage::Dense(_) => return None,
+            Storage::Paged(pages) => pages
+                .iter()
+                .map(|(&index, page)| (index, &page[..]))
+                .collect(),
+        };
+
+        pages.sort_by_key(|&(index, _)| index);
+        Some(pages)
+    }
+
+    /// creates paged memory of the given length from its pages
+    pub(crate) fn from_pages(len: usize, pages: HashMap<usize, Box<[i64]>>) -> Memory {
+        Memory {
+            storage: Storage::Paged(pages),
+            len,
+            limit: None,
+        }
+    }
+}
+
+impl From<Vec<i64>> for Memory {
+    fn from(program: Vec<i64>) -> Memory {
+        Memory {
+            len: program.len(),
+            storage: Storage::Dense(program),
+            limit: None,
+        }
+    }
+}
+
+impl Index<usize> for Memory {
+    type Output = i64;
+
+    fn index(&self, address: usize) -> &i64 {
+        let cell = match &self.storage {
+            Storage::Dense(cells) => cells.get(address),
+            Storage::Paged(pages) => pages
+                .get(&(address / PAGE_SIZE))
+                .map(|page| &page[address % PAGE_SIZE]),
+        };
+
+        cell.unwrap_or(&0)
+    }
+}
+
+/// memories are equal if they hold the same cells, whichever backend they use
+impl PartialEq for Memory {
+    fn eq(&self, other: &Memory) -> bool {
+        self.len == other.len && self.iter().eq(other.iter())
+    }
+}
+
+impl PartialEq<Vec<i64>> for Memory {
+    fn eq(&self, other: &Vec<i64>) -> bool {
+        self.len == other.len() && self.iter().eq(other.iter().copied())
+    }
+}
+
+impl Cells for Memory {
+    fn cell(&self, address: usize) -> Option<i64> {
+        self.get(address)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::assembler::assemble;
+    use crate::intcode::IntCodeEmulator;
+
+    const DAY09: &str = include_str!("../../input/2019/day9.txt");
+
+    #[test]
+    fn backends_behave_identically() {
+        let program = vec![1, 2, 3];
+        let mut dense = Memory::from(program.clone());
+        let mut paged = Memory::paged(&program);
+
+        for memory in [&mut dense, &mut paged].iter_mut() {
+            memory.set(5000, 7);
+            assert_eq!(memory.len(), 5001);
+            assert_eq!(memory.get(4000), Some(0));
+            assert_eq!(memory.get(5001), None);
+            assert_eq!(memory.read(1_000_000), 0);
+        }
+
+        assert_eq!(dense, paged);
+        assert_eq!(dense.allocated(), 5001);
+        assert_eq!(paged.allocated(), 2 * PAGE_SIZE);
+    }
+
+    #[test]
+    fn runs_programs_in_paged_memory() {
+        let mut dense = IntCodeEmulator::from_input(DAY09);
+        let mut paged = dense.clone();
+        paged.set_memory_backend(MemoryBackend::Paged);
+
+        for vm in [&mut dense, &mut paged].iter_mut() {
+            vm.stdin().push_back(1);
+            vm.execute().unwrap();
+        }
+
+        assert_eq!(paged.stdout(), dense.stdout());
+        assert_eq!(paged.ram(), dense.ram());
+    }
+
+    #[test]
+    fn enforces_memory_limit() {
+        let program = assemble("add #1, #2, [1000000000000]\nhlt").unwrap();
+
+        let mut vm = IntCodeEmulator::new(program.clone());
+        vm.set_memory_limit(Some(1 << 20));
+        let error = vm.execute().unwrap_err();
+        assert_eq!(
+            error.kind,
+            ErrorKind::MemoryLimit {
+                address: 1_000_000_000_000,
+                limit: 1 << 20
+            }
+        );
+
+        let mut vm = IntCodeEmulator::new(program);
+        vm.set_memory_backend(MemoryBackend::Paged);
+        vm.set_memory_limit(Some(1 << 20));
+        vm.execute().unwrap();
+        assert_eq!(vm.peek(1_000_000_000_000), 3);
+    }
+}
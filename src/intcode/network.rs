@@ -0,0 +1,484 @@
+use crate::intcode::{IntCodeEmulator, IntCodeError, IoDevice, StepResult};
+use std::collections::VecDeque;
+use std::fmt;
+
+/// A packet sent between VMs. Each VM sends a packet by outputting the address, x and y in turn, and
+/// receives one as x followed by y on its input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Packet {
+    pub address: i64,
+    pub x: i64,
+    pub y: i64,
+}
+
+impl fmt::Display for Packet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} <- ({}, {})", self.address, self.x, self.y)
+    }
+}
+
+/// When packets sent between VMs are received
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Delivery {
+    /// straight away, so a VM later in the round can read a packet sent earlier in it
+    Immediate,
+
+    /// once every VM has had its turn, so no VM sees a packet sent in the same round
+    NextRound,
+}
+
+/// What a monitor wants the network to do next
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Control {
+    Continue,
+    Stop,
+}
+
+/// Why the network stopped running
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Outcome {
+    /// the monitor asked to stop
+    Stopped,
+
+    /// every VM halted
+    Halted,
+
+    /// every VM is waiting for input and the monitor didn't send anything, so nothing can change
+    Idle,
+}
+
+/// Watches a network, handling packets sent outside of it and deciding what to do when it's idle
+pub trait Monitor {
+    /// a packet was sent to an address which doesn't belong to any VM
+    fn packet(&mut self, _packet: Packet) -> Control {
+        Control::Continue
+    }
+
+    /// every VM spent a whole round waiting for input without any packets being sent
+    fn idle(&mut self, _network: &mut Network) -> Control {
+        Control::Stop
+    }
+}
+
+/// the unit monitor drops packets which leave the network, and stops once it's idle
+impl Monitor for () {}
+
+/// Stores the last packet sent to its address, and resends it to address 0 whenever the network is
+/// idle. Stops once the same y value is resent twice in a row, and leaves the network idle if it
+/// hasn't received anything to resend.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Nat {
+    address: i64,
+
+    /// the first packet the NAT received
+    pub first: Option<Packet>,
+
+    /// the packet the NAT will resend when the network is next idle
+    pub last: Option<Packet>,
+
+    /// the y value of every packet the NAT has resent
+    pub resent: Vec<i64>,
+}
+
+impl Nat {
+    pub fn new(address: i64) -> Nat {
+        Nat {
+            address,
+            first: None,
+            last: None,
+            resent: Vec::new(),
+        }
+    }
+
+    /// the first y value resent twice in a row, if the NAT stopped the network
+    pub fn repeated(&self) -> Option<i64> {
+        match self.resent.as_slice() {
+            [.., a, b] if a == b => Some(*b),
+            _ => None,
+        }
+    }
+}
+
+impl Monitor for Nat {
+    fn packet(&mut self, packet: Packet) -> Control {
+        if packet.address == self.address {
+            self.first.get_or_insert(packet);
+            self.last = Some(packet);
+        }
+
+        Control::Continue
+    }
+
+    fn idle(&mut self, network: &mut Network) -> Control {
+        let packet = match self.last {
+            Some(packet) => packet,
+            None => return Control::Continue,
+        };
+
+        network.send(Packet {
+            address: 0,
+            ..packet
+        });
+        self.resent.push(packet.y);
+
+        match self.repeated() {
+            Some(_) => Control::Stop,
+            None => Control::Continue,
+        }
+    }
+}
+
+/// The device attached to each VM in a network, which queues the packets it receives and assembles
+/// the packets it sends
+#[derive(Debug, Clone, Default)]
+pub struct NetworkDevice {
+    inbox: VecDeque<i64>,
+    outbox: Vec<i64>,
+    sent: Vec<Packet>,
+
+    /// the input given when the inbox is empty, or None to yield instead
+    idle_input: Option<i64>,
+
+    /// whether the VM read from an empty inbox, or received anything, during its current turn
+    starved: bool,
+    received: bool,
+}
+
+impl NetworkDevice {
+    /// the values waiting to be read
+    pub fn inbox(&self) -> &VecDeque<i64> {
+        &self.inbox
+    }
+}
+
+impl IoDevice for NetworkDevice {
+    fn input(&mut self) -> Option<i64> {
+        match self.inbox.pop_front() {
+            Some(value) => {
+                self.received = true;
+                Some(value)
+            }
+            None => {
+                self.starved = true;
+                self.idle_input
+            }
+        }
+    }
+
+    fn output(&mut self, value: i64) {
+        self.outbox.push(value);
+
+        if let [address, x, y] = self.outbox[..] {
+            self.sent.push(Packet { address, x, y });
+            self.outbox.clear();
+        }
+    }
+}
+
+/// A network of VMs which send each other packets, scheduled deterministically.
+///
+/// Each VM is booted with its address as its first input. The VMs take turns in address order, each
+/// running until it reads from an empty inbox, halts or has executed `quantum` instructions. An empty
+/// inbox reads as -1 by default. Packets sent to an address outside of the network are passed to the
+/// monitor, which is also told whenever a full round passes in which every VM was waiting for input.
+#[derive(Debug, Clone)]
+pub struct Network {
+    nodes: Vec<IntCodeEmulator<NetworkDevice>>,
+    halted: Vec<bool>,
+    pending: Vec<Packet>,
+    delivery: Delivery,
+    quantum: usize,
+    rounds: usize,
+}
+
+impl Network {
+    pub fn new(program: &[i64], size: usize) -> Network {
+        let nodes = (0..size)
+            .map(|address| {
+                let device = NetworkDevice {
+                    inbox: VecDeque::from(vec![address as i64]),
+                    idle_input: Some(-1),
+                    ..NetworkDevice::default()
+                };
+
+                IntCodeEmulator::new(program.to_vec()).with_device(device)
+            })
+            .collect();
+
+        Network {
+            nodes,
+            halted: vec![false; size],
+            pending: Vec::new(),
+            delivery: Delivery::Immediate,
+            quantum: 10_000,
+            rounds: 0,
+        }
+    }
+
+    pub fn set_delivery(&mut self, delivery: Delivery) {
+        self.delivery = delivery;
+    }
+
+    /// sets the most instructions a VM can execute in a single turn
+    pub fn set_quantum(&mut self, quantum: usize) {
+        self.quantum = quantum.max(1);
+    }
+
+    /// sets the input a VM receives when its inbox is empty, or None for VMs to yield until the next
+    /// round instead
+    pub fn set_idle_input(&mut self, idle_input: Option<i64>) {
+        for vm in &mut self.nodes {
+            vm.device_mut().idle_input = idle_input;
+        }
+    }
+
+    pub fn node(&self, address: usize) -> &IntCodeEmulator<NetworkDevice> {
+        &self.nodes[address]
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// the number of rounds run so far
+    pub fn rounds(&self) -> usize {
+        self.rounds
+    }
+
+    /// sends a packet from outside the network, delivering it according to the delivery policy
+    pub fn send(&mut self, packet: Packet) {
+        match self.delivery {
+            Delivery::Immediate => self.deliver(packet),
+            Delivery::NextRound => self.pending.push(packet),
+        }
+    }
+
+    /// runs rounds until the monitor stops the network, or nothing more can happen
+    pub fn run<M>(&mut self, monitor: &mut M) -> Result<Outcome, IntCodeError>
+    where
+        M: Monitor + ?Sized,
+    {
+        loop {
+            if let Some(outcome) = self.round(monitor)? {
+                return Ok(outcome);
+            }
+        }
+    }
+
+    /// gives every VM a single turn, returning the outcome if the network stopped
+    pub fn round<M>(&mut self, monitor: &mut M) -> Result<Option<Outcome>, IntCodeError>
+    where
+        M: Monitor + ?Sized,
+    {
+        self.rounds += 1;
+        let mut active = false;
+
+        for address in 0..self.nodes.len() {
+            if self.halted[address] {
+                continue;
+            }
+
+            let vm = &mut self.nodes[address];
+            vm.device_mut().starved = false;
+            vm.device_mut().received = false;
+
+            for _ in 0..self.quantum {
+                match vm.step()? {
+                    StepResult::Continue if !vm.device().starved => continue,
+                    StepResult::Continue | StepResult::InputRequired => break,
+                    StepResult::Halted => {
+                        self.halted[address] = true;
+                        break;
+                    }
+                }
+            }
+
+            let device = vm.device_mut();
+            active |= device.received || !(device.starved || self.halted[address]);
+
+            let sent: Vec<Packet> = device.sent.drain(..).collect();
+            active |= !sent.is_empty();
+
+            for packet in sent {
+                if self.address(packet.address).is_some() {
+                    self.send(packet);
+                } else if monitor.packet(packet) == Control::Stop {
+                    return Ok(Some(Outcome::Stopped));
+                }
+            }
+        }
+
+        for packet in std::mem::take(&mut self.pending) {
+            active = true;
+            self.deliver(packet);
+        }
+
+        if self.halted.iter().all(|&halted| halted) {
+            return Ok(Some(Outcome::Halted));
+        }
+
+        if active {
+            return Ok(None);
+        }
+
+        if monitor.idle(self) == Control::Stop {
+            return Ok(Some(Outcome::Stopped));
+        }
+
+        // packets sent to halted VMs will never be read, so they don't keep the network going
+        let waiting = self
+            .nodes
+            .iter()
+            .zip(&self.halted)
+            .any(|(vm, &halted)| !halted && !vm.device().inbox.is_empty());
+        if waiting || !self.pending.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(Outcome::Idle))
+        }
+    }
+
+    /// converts a packet address into the index of a VM, if it's in the network
+    fn address(&self, address: i64) -> Option<usize> {
+        if address >= 0 && (address as usize) < self.nodes.len() {
+            Some(address as usize)
+        } else {
+            None
+        }
+    }
+
+    fn deliver(&mut self, packet: Packet) {
+        if let Some(address) = self.address(packet.address) {
+            let inbox = &mut self.nodes[address].device_mut().inbox;
+            inbox.push_back(packet.x);
+            inbox.push_back(packet.y);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::assembler::assemble;
+
+    /// forwards each packet to the next address, adding its own address to x
+    const FORWARDER: &str = "
+                in [address]
+                add [address], #1, [next]
+        loop:   in [x]
+                eq [x], #-1, [empty]
+                jt [empty], #loop
+                in [y]
+                add [x], [address], [x]
+                out [next]
+                out [x]
+                out [y]
+                jt #1, #loop
+        address: data 0
+        next:   data 0
+        empty:  data 0
+        x:      data 0
+        y:      data 0
+    ";
+
+    /// stops at the first packet which leaves the network
+    #[derive(Default)]
+    struct FirstPacket(Option<Packet>);
+
+    impl Monitor for FirstPacket {
+        fn packet(&mut self, packet: Packet) -> Control {
+            self.0 = Some(packet);
+            Control::Stop
+        }
+    }
+
+    fn network() -> Network {
+        let mut network = Network::new(&assemble(FORWARDER).unwrap(), 3);
+        network.send(Packet {
+            address: 0,
+            x: 7,
+            y: 100,
+        });
+        network
+    }
+
+    #[test]
+    fn routes_packets() {
+        let mut network = network();
+        let mut monitor = FirstPacket::default();
+
+        assert_eq!(network.run(&mut monitor).unwrap(), Outcome::Stopped);
+        assert_eq!(
+            monitor.0,
+            Some(Packet {
+                address: 3,
+                x: 10,
+                y: 100
+            })
+        );
+        assert_eq!(network.rounds(), 1);
+    }
+
+    #[test]
+    fn applies_delivery_policies() {
+        let mut network = network();
+        network.set_delivery(Delivery::NextRound);
+        network.set_idle_input(None);
+
+        let mut monitor = FirstPacket::default();
+        network.run(&mut monitor).unwrap();
+
+        assert_eq!(monitor.0.map(|p| p.x), Some(10));
+        // the first packet was sent before the policy changed, so node 0 still read it straight away
+        assert_eq!(network.rounds(), 3);
+    }
+
+    #[test]
+    fn resends_from_nat_when_idle() {
+        let mut network = network();
+        let mut nat = Nat::new(3);
+
+        assert_eq!(network.run(&mut nat).unwrap(), Outcome::Stopped);
+        assert_eq!(nat.first.map(|p| p.x), Some(10));
+        assert_eq!(nat.last.map(|p| p.x), Some(13));
+        assert_eq!(nat.resent, vec![100, 100]);
+        assert_eq!(nat.repeated(), Some(100));
+    }
+
+    #[test]
+    fn detects_idle_networks() {
+        let mut network = Network::new(&assemble(FORWARDER).unwrap(), 2);
+        assert_eq!(network.run(&mut ()).unwrap(), Outcome::Stopped);
+
+        let mut network = Network::new(&assemble(FORWARDER).unwrap(), 2);
+        assert_eq!(network.run(&mut Nat::new(255)).unwrap(), Outcome::Idle);
+        assert_eq!(network.rounds(), 2);
+    }
+
+    #[test]
+    fn ignores_packets_sent_to_halted_nodes() {
+        let program = assemble(
+            "
+                    in [address]
+                    jf [address], #halt
+                    out #0
+                    out #1
+                    out #2
+            loop:   in [x]
+                    jt #1, #loop
+            halt:   hlt
+            address: data 0
+            x:      data 0
+            ",
+        )
+        .unwrap();
+
+        let mut network = Network::new(&program, 2);
+        assert_eq!(network.run(&mut Nat::new(255)).unwrap(), Outcome::Idle);
+        assert_eq!(network.node(0).device().inbox(), &[1, 2]);
+    }
+}
@@ -0,0 +1,619 @@
+use crate::intcode::fuzz::End;
+use crate::intcode::graph::{self, Exit, Graph};
+use crate::intcode::{Instruction, IntCodeEmulator, ReadValue, WriteValue};
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+
+/// Why a program can't be optimised without risking changing what it does
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Obstacle {
+    /// the instruction at this address may be overwritten by the program as it runs
+    SelfModifying(usize),
+
+    /// the instruction at this address reads or writes relative to the base, so could touch any cell
+    RelativeAccess(usize),
+
+    /// the jump at this address goes somewhere which isn't known until runtime
+    IndirectJump(usize),
+
+    /// the instruction at this address overlaps another, and both can be executed
+    Overlapping(usize),
+}
+
+/// An optimised program, along with what was done to it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Optimised {
+    pub program: Vec<i64>,
+
+    /// the number of instructions rewritten to use constants
+    pub folded: usize,
+
+    /// the number of cells cleared or trimmed from the end because nothing executes or reads them
+    pub removed: usize,
+
+    /// the reason the program was left as it was, if it was
+    pub obstacle: Option<Obstacle>,
+}
+
+/// What's known about the reachable code in a program
+struct Facts {
+    /// every cell occupied by an instruction which can be executed, or which fails to decode
+    code: BTreeSet<usize>,
+
+    /// every cell an instruction writes to
+    written: BTreeSet<usize>,
+
+    /// every cell an instruction reads in position mode
+    read: BTreeSet<usize>,
+
+    /// cells decoded as part of more than one instruction, because something jumps into the middle
+    /// of an instruction
+    shared: BTreeSet<usize>,
+
+    /// the first jump to an address which isn't known, if there is one
+    indirect: Option<usize>,
+}
+
+impl Facts {
+    fn gather(program: &[i64], graph: &Graph) -> Result<Facts, Obstacle> {
+        let mut facts = Facts {
+            code: BTreeSet::new(),
+            written: BTreeSet::new(),
+            read: BTreeSet::new(),
+            shared: BTreeSet::new(),
+            indirect: None,
+        };
+        let mut decoded = BTreeSet::new();
+
+        for block in graph.blocks.values() {
+            for &(address, instruction) in &block.instructions {
+                for cell in address..address + instruction.size() {
+                    if !decoded.insert(cell) {
+                        facts.shared.insert(cell);
+                    }
+                }
+
+                let (reads, write) = operands(&instruction);
+
+                for read in reads {
+                    match read {
+                        ReadValue::Position(position) if position >= 0 => {
+                            facts.read.insert(position as usize);
+                        }
+                        ReadValue::Relative(_) => return Err(Obstacle::RelativeAccess(address)),
+                        _ => {}
+                    }
+                }
+
+                match write {
+                    Some(WriteValue::Position(position)) if position >= 0 => {
+                        facts.written.insert(position as usize);
+                    }
+                    Some(WriteValue::Relative(_)) => return Err(Obstacle::RelativeAccess(address)),
+                    _ => {}
+                }
+            }
+
+            let last = block.instructions.last().map_or(block.start, |&(a, _)| a);
+
+            match block.exit {
+                Exit::Return | Exit::Indirect { .. } => {
+                    facts.indirect = facts.indirect.or(Some(last));
+                }
+                Exit::Invalid => {
+                    // the cells which fail to decode decide the error, so have to be kept
+                    for cell in block.end..program.len().min(block.end + 4) {
+                        if !decoded.insert(cell) {
+                            facts.shared.insert(cell);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        facts.code = decoded;
+
+        // only once every write is known can we tell whether any of them hit the code, including the
+        // cells kept for an instruction which fails to decode, since a write can make it valid
+        for block in graph.blocks.values() {
+            let mut extents: Vec<(usize, usize)> = block
+                .instructions
+                .iter()
+                .map(|&(address, instruction)| (address, address + instruction.size()))
+                .collect();
+
+            if block.exit == Exit::Invalid && block.end < program.len() {
+                extents.push((block.end, program.len().min(block.end + 4)));
+            }
+
+            for (address, end) in extents {
+                if facts.written.range(address..end).next().is_some() {
+                    return Err(Obstacle::SelfModifying(address));
+                }
+            }
+        }
+
+        Ok(facts)
+    }
+}
+
+/// the values an instruction reads and the destination it writes to
+fn operands(instruction: &Instruction) -> (Vec<ReadValue>, Option<WriteValue>) {
+    match *instruction {
+        Instruction::Add(l, r, d)
+        | Instruction::Multiply(l, r, d)
+        | Instruction::LessThan(l, r, d)
+        | Instruction::Equals(l, r, d) => (vec![l, r], Some(d)),
+        Instruction::JumpTrue(c, t) | Instruction::JumpFalse(c, t) => (vec![c, t], None),
+        Instruction::Input(d) => (Vec::new(), Some(d)),
+        Instruction::Output(v) | Instruction::AdjustBase(v) => (vec![v], None),
+        Instruction::Halt => (Vec::new(), None),
+    }
+}
+
+/// encodes an instruction back into memory cells
+fn encode(instruction: &Instruction) -> Vec<i64> {
+    let read = |value: ReadValue| match value {
+        ReadValue::Position(value) => (0, value),
+        ReadValue::Immediate(value) => (1, value),
+        ReadValue::Relative(value) => (2, value),
+    };
+    let write = |value: WriteValue| match value {
+        WriteValue::Position(value) => (0, value),
+        WriteValue::Relative(value) => (2, value),
+    };
+
+    let (opcode, parameters) = match *instruction {
+        Instruction::Add(l, r, d) => (1, vec![read(l), read(r), write(d)]),
+        Instruction::Multiply(l, r, d) => (2, vec![read(l), read(r), write(d)]),
+        Instruction::Input(d) => (3, vec![write(d)]),
+        Instruction::Output(v) => (4, vec![read(v)]),
+        Instruction::JumpTrue(c, t) => (5, vec![read(c), read(t)]),
+        Instruction::JumpFalse(c, t) => (6, vec![read(c), read(t)]),
+        Instruction::LessThan(l, r, d) => (7, vec![read(l), read(r), write(d)]),
+        Instruction::Equals(l, r, d) => (8, vec![read(l), read(r), write(d)]),
+        Instruction::AdjustBase(v) => (9, vec![read(v)]),
+        Instruction::Halt => (99, Vec::new()),
+    };
+
+    let modes = parameters
+        .iter()
+        .rev()
+        .fold(0, |modes, &(mode, _)| modes * 10 + mode);
+
+    Some(opcode + modes * 100)
+        .into_iter()
+        .chain(parameters.into_iter().map(|(_, value)| value))
+        .collect()
+}
+
+/// rewrites an instruction to use the constants it reads, computing its result if every input is
+/// known. The result always has the same size and takes the same number of steps
+fn simplify<F>(instruction: &Instruction, mut constant: F) -> Instruction
+where
+    F: FnMut(ReadValue) -> ReadValue,
+{
+    use self::ReadValue::Immediate;
+
+    let store = |value: i64, dest| Instruction::Add(Immediate(value), Immediate(0), dest);
+
+    match *instruction {
+        Instruction::Add(l, r, d) => match (constant(l), constant(r)) {
+            (Immediate(l), Immediate(r)) => store(l.wrapping_add(r), d),
+            (l, r) => Instruction::Add(l, r, d),
+        },
+        Instruction::Multiply(l, r, d) => match (constant(l), constant(r)) {
+            (Immediate(l), Immediate(r)) => store(l.wrapping_mul(r), d),
+            (l, r) => Instruction::Multiply(l, r, d),
+        },
+        Instruction::LessThan(l, r, d) => match (constant(l), constant(r)) {
+            (Immediate(l), Immediate(r)) => store((l < r) as i64, d),
+            (l, r) => Instruction::LessThan(l, r, d),
+        },
+        Instruction::Equals(l, r, d) => match (constant(l), constant(r)) {
+            (Immediate(l), Immediate(r)) => store((l == r) as i64, d),
+            (l, r) => Instruction::Equals(l, r, d),
+        },
+        Instruction::JumpTrue(c, t) | Instruction::JumpFalse(c, t) => {
+            let when = matches!(instruction, Instruction::JumpTrue(..));
+
+            match constant(c) {
+                // the target is only read when the jump is taken
+                Immediate(c) if (c != 0) != when => {
+                    Instruction::JumpTrue(Immediate(0), Immediate(0))
+                }
+                Immediate(_) => Instruction::JumpTrue(Immediate(1), constant(t)),
+                c if when => Instruction::JumpTrue(c, constant(t)),
+                c => Instruction::JumpFalse(c, constant(t)),
+            }
+        }
+        Instruction::Output(v) => Instruction::Output(constant(v)),
+        Instruction::AdjustBase(v) => Instruction::AdjustBase(constant(v)),
+        Instruction::Input(_) | Instruction::Halt => *instruction,
+    }
+}
+
+/// The state of an optimisation in progress
+struct Folding<'a> {
+    original: &'a [i64],
+    program: Vec<i64>,
+
+    /// the cells read as constants, which mustn't turn out to be written
+    assumed: BTreeSet<usize>,
+
+    /// the instructions rewritten so far, by address
+    forms: HashMap<usize, Instruction>,
+}
+
+/// folds constants into every reachable instruction, returning whether anything changed. Each
+/// instruction is rewritten from its original form, so it's only ever simplified once. Cells which
+/// are never written hold their value in the original program for the whole run, which is assumed
+/// until the code is fully known, and within a block the value most recently stored in a cell is
+/// known until something else writes to it
+fn fold(folding: &mut Folding, graph: &Graph, facts: &Facts) -> bool {
+    let original = folding.original;
+    let mut changed = false;
+
+    for block in graph.blocks.values() {
+        let mut known: HashMap<usize, i64> = HashMap::new();
+
+        for &(address, current) in &block.instructions {
+            let cells = address..address + current.size();
+
+            let instruction = match Instruction::parse(original, address) {
+                // changing an instruction which overlaps another would change both
+                Ok(instruction)
+                    if instruction.size() == current.size()
+                        && facts.shared.range(cells.clone()).next().is_none() =>
+                {
+                    instruction
+                }
+                _ => {
+                    known.clear();
+                    continue;
+                }
+            };
+
+            let mut unwritten = Vec::new();
+            let simplified = simplify(&instruction, |value| match value {
+                ReadValue::Position(position) if position >= 0 => {
+                    let position = position as usize;
+
+                    match known.get(&position) {
+                        Some(&value) => ReadValue::Immediate(value),
+                        None if !facts.written.contains(&position) => {
+                            unwritten.push(position);
+                            ReadValue::Immediate(original.get(position).copied().unwrap_or(0))
+                        }
+                        None => value,
+                    }
+                }
+                _ => value,
+            });
+            folding.assumed.extend(unwritten);
+
+            if let (_, Some(WriteValue::Position(dest))) = operands(&simplified) {
+                let dest = dest as usize;
+
+                match simplified {
+                    Instruction::Add(ReadValue::Immediate(value), ReadValue::Immediate(0), _) => {
+                        known.insert(dest, value);
+                    }
+                    _ => {
+                        known.remove(&dest);
+                    }
+                }
+            }
+
+            let encoded = encode(&simplified);
+            if folding.program[cells.clone()] != encoded[..] {
+                folding.program.splice(cells, encoded);
+                folding.forms.insert(address, simplified);
+                changed = true;
+            }
+        }
+    }
+
+    changed
+}
+
+/// Optimises a program without changing its layout, so every address it uses stays the same.
+///
+/// Reads of cells which are never written are replaced with the constants they hold, arithmetic
+/// on constants is computed ahead of time, and conditional jumps which always go the same way
+/// become unconditional, repeating until nothing changes. Cells which are then neither executed
+/// nor read are cleared, and zeros trimmed from the end. The result produces the same outputs,
+/// takes the same number of steps and fails in the same way for any inputs, but its memory may end
+/// up different and it relies on reads past the end of memory returning zero.
+///
+/// Only programs whose behaviour can be worked out from their code are optimised: anything which
+/// modifies its own code, uses relative mode or jumps to computed addresses is returned
+/// unchanged, along with the reason.
+pub fn optimise(program: &[i64]) -> Optimised {
+    let mut folding = Folding {
+        original: program,
+        program: program.to_vec(),
+        assumed: BTreeSet::new(),
+        forms: HashMap::new(),
+    };
+    let mut first = None;
+
+    // folding can turn jumps through constant cells into direct jumps, revealing more code, so
+    // the cells read as constants are only known not to be written once nothing is hidden
+    let (graph, facts) = loop {
+        let graph = graph::analyse(&folding.program);
+
+        let facts = match Facts::gather(&folding.program, &graph) {
+            Ok(facts) => facts,
+            Err(obstacle) => return unchanged(program, obstacle),
+        };
+        first = first.or(facts.indirect);
+
+        if facts
+            .written
+            .intersection(&folding.assumed)
+            .next()
+            .is_some()
+        {
+            return unchanged(program, Obstacle::IndirectJump(first.unwrap_or_default()));
+        }
+
+        if !fold(&mut folding, &graph, &facts) {
+            break (graph, facts);
+        }
+    };
+
+    if let Some(address) = facts.indirect {
+        return unchanged(program, Obstacle::IndirectJump(address));
+    }
+
+    // an instruction rewritten while it overlapped code which is no longer reachable may have been
+    // changed by both, so everything executed has to be either untouched or as it was rewritten
+    for block in graph.blocks.values() {
+        for &(address, instruction) in &block.instructions {
+            let cells = address..address + instruction.size();
+
+            if folding.program[cells.clone()] != program[cells.clone()]
+                && folding.forms.get(&address) != Some(&instruction)
+            {
+                return unchanged(program, Obstacle::Overlapping(address));
+            }
+        }
+    }
+
+    let mut optimised = Optimised {
+        program: folding.program,
+        folded: 0,
+        removed: 0,
+        obstacle: None,
+    };
+
+    optimised.folded = graph
+        .blocks
+        .values()
+        .flat_map(|block| &block.instructions)
+        .filter(|(address, instruction)| {
+            let cells = *address..address + instruction.size();
+            optimised.program[cells.clone()] != program[cells]
+        })
+        .count();
+
+    // cells which are only read as data may have been rewritten while they looked like code
+    for &cell in &facts.read {
+        if cell < program.len() && !facts.code.contains(&cell) {
+            optimised.program[cell] = program[cell];
+        }
+    }
+
+    let keep = |cell: usize| facts.code.contains(&cell) || facts.read.contains(&cell);
+
+    let len = (0..optimised.program.len())
+        .rev()
+        .find(|&cell| {
+            // reads past the end of memory return zero, so zeros which are only read can go
+            let value = optimised.program[cell];
+            keep(cell) && (value != 0 || facts.code.contains(&cell))
+        })
+        .map_or(0, |cell| cell + 1);
+
+    for cell in 0..len {
+        if !keep(cell) && optimised.program[cell] != 0 {
+            optimised.program[cell] = 0;
+            optimised.removed += 1;
+        }
+    }
+
+    optimised.removed += optimised.program.len() - len;
+    optimised.program.truncate(len);
+    optimised
+}
+
+fn unchanged(program: &[i64], obstacle: Obstacle) -> Optimised {
+    Optimised {
+        program: program.to_vec(),
+        folded: 0,
+        removed: 0,
+        obstacle: Some(obstacle),
+    }
+}
+
+/// What a run did which can be seen from outside
+#[derive(Debug, Clone, PartialEq)]
+pub struct Behaviour {
+    pub end: End,
+    pub pointer: usize,
+    pub output: Vec<i64>,
+}
+
+/// runs a program on some inputs, stopping after `budget` instructions
+pub fn behaviour(program: &[i64], inputs: &[i64], budget: u64) -> Behaviour {
+    let mut vm = IntCodeEmulator::new(program.to_vec());
+    vm.stdin().extend(inputs.iter().copied());
+    vm.set_instruction_budget(Some(budget));
+
+    let end = match vm.execute_until_yield() {
+        Ok(reason) => End::Yielded(reason),
+        Err(e) => End::Failed(e.kind),
+    };
+
+    Behaviour {
+        end,
+        pointer: vm.pointer(),
+        output: vm.stdout().iter().copied().collect(),
+    }
+}
+
+/// A recorded input on which an optimised program behaves differently to the original
+#[derive(Debug, Clone, PartialEq)]
+pub struct Divergence {
+    pub inputs: Vec<i64>,
+    pub expected: Behaviour,
+    pub actual: Behaviour,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "optimised program differs from the original")?;
+        writeln!(f, "inputs: {:?}", self.inputs)?;
+        writeln!(f, "expected: {:?}", self.expected)?;
+        write!(f, "actual: {:?}", self.actual)
+    }
+}
+
+/// checks an optimised program behaves the same as the original on every recorded set of inputs
+pub fn verify(
+    original: &[i64],
+    optimised: &[i64],
+    recordings: &[Vec<i64>],
+    budget: u64,
+) -> Result<(), Box<Divergence>> {
+    for inputs in recordings {
+        let expected = behaviour(original, inputs, budget);
+        let actual = behaviour(optimised, inputs, budget);
+
+        if actual != expected {
+            return Err(Box::new(Divergence {
+                inputs: inputs.clone(),
+                expected,
+                actual,
+            }));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::assembler::assemble;
+    use crate::intcode::fuzz::{generate, Rng};
+
+    #[test]
+    fn folds_constants_and_removes_dead_code() {
+        let program = assemble(
+            "
+                in [n]
+                eq [debug], #1, [t]
+                jt [t], #trace
+                mul [n], [scale], [n]
+                out [n]
+                hlt
+        trace:  out #-1
+                out [n]
+                hlt
+        n:      data 0
+        t:      data 0
+        scale:  data 3
+        debug:  data 0
+            ",
+        )
+        .unwrap();
+
+        let optimised = optimise(&program);
+
+        assert_eq!(optimised.obstacle, None);
+        assert_eq!(optimised.folded, 3);
+        assert_eq!(
+            optimised.program,
+            vec![3, 21, 1101, 0, 0, 22, 1105, 0, 0, 1002, 21, 3, 21, 4, 21, 99]
+        );
+        assert_eq!(optimised.removed, 9);
+
+        let recordings = vec![vec![0], vec![5], vec![-7], Vec::new()];
+        verify(&program, &optimised.program, &recordings, 1000).unwrap();
+    }
+
+    #[test]
+    fn leaves_self_modifying_code() {
+        let program = assemble(
+            "
+                add #1101, #0, [next]
+        next:   add [a], [a], [a]
+                out [a]
+                hlt
+        a:      data 4
+            ",
+        )
+        .unwrap();
+
+        let optimised = optimise(&program);
+
+        assert_eq!(optimised.obstacle, Some(Obstacle::SelfModifying(4)));
+        assert_eq!(optimised.program, program);
+    }
+
+    #[test]
+    fn explains_why_puzzles_are_left_alone() {
+        // day 7 patches the operand of a jump with its input to index a jump table
+        let day7 = IntCodeEmulator::parse_input(include_str!("../../input/2019/day7.txt"));
+        let optimised = optimise(&day7);
+
+        assert_eq!(optimised.obstacle, Some(Obstacle::SelfModifying(6)));
+        assert_eq!(optimised.program, day7);
+
+        // day 5 starts by writing over the invalid instruction which follows it
+        let day5 = IntCodeEmulator::parse_input(include_str!("../../input/2019/day5.txt"));
+        let optimised = optimise(&day5);
+
+        assert_eq!(optimised.obstacle, Some(Obstacle::SelfModifying(6)));
+        assert_eq!(optimised.program, day5);
+        assert_eq!(
+            verify(&day5, &optimised.program, &[vec![1], vec![5]], 100_000),
+            Ok(())
+        );
+
+        let day9 = IntCodeEmulator::parse_input(include_str!("../../input/2019/day9.txt"));
+        let optimised = optimise(&day9);
+
+        assert!(matches!(
+            optimised.obstacle,
+            Some(Obstacle::RelativeAccess(_))
+        ));
+        assert_eq!(optimised.program, day9);
+    }
+
+    #[test]
+    fn preserves_behaviour_of_random_programs() {
+        let mut rng = Rng::new(23);
+        let mut changed = 0;
+
+        for _ in 0..2000 {
+            let case = generate(&mut rng);
+            let optimised = optimise(&case.program);
+
+            if optimised.folded > 0 {
+                changed += 1;
+            }
+
+            let recordings = vec![case.inputs.clone(), Vec::new(), vec![0; 8], vec![1; 8]];
+            if let Err(divergence) = verify(&case.program, &optimised.program, &recordings, 1000) {
+                panic!("{}\nprogram: {:?}", divergence, case.program);
+            }
+        }
+
+        // most random programs use relative mode, but plenty of the rest can be folded
+        assert!(changed > 50);
+    }
+}
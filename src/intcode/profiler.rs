@@ -0,0 +1,398 @@
+use crate::intcode::dialect::Opcode;
+use crate::intcode::{Instruction, Observer};
+use std::borrow::Cow;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{self, Write};
+
+/// An executed instruction, which is either standard or one of the custom opcodes of a dialect
+#[derive(Debug, Clone, PartialEq)]
+pub enum Profiled {
+    Standard(Instruction),
+
+    /// the mnemonic of a custom opcode
+    Custom(String),
+}
+
+/// How often the instruction at a single address was executed
+#[derive(Debug, Clone, PartialEq)]
+pub struct HotSpot {
+    pub address: usize,
+    pub count: u64,
+
+    /// the instruction last executed at the address, since self-modifying code can change it
+    pub instruction: Profiled,
+}
+
+/// Counts everything a program does, to find where it spends its time.
+///
+/// Attach it to an emulator with `execute_with` or any of the other observed execute methods. The
+/// counts can then be read directly or reported as a text table or JSON.
+#[derive(Debug, Clone, Default)]
+pub struct Profiler {
+    instructions: u64,
+    addresses: HashMap<usize, (u64, Profiled)>,
+    opcodes: BTreeMap<Cow<'static, str>, u64>,
+    base_adjustments: u64,
+    base_range: Option<(i64, i64)>,
+    highest_read: Option<usize>,
+    highest_write: Option<usize>,
+    reads: u64,
+    writes: u64,
+    inputs: u64,
+    outputs: u64,
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler::default()
+    }
+
+    /// the total number of instructions executed
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    /// the number of times the instruction at an address was executed
+    pub fn count(&self, address: usize) -> u64 {
+        self.addresses.get(&address).map_or(0, |&(count, _)| count)
+    }
+
+    /// the number of times instructions with the given mnemonic were executed
+    pub fn opcode_count(&self, mnemonic: &str) -> u64 {
+        self.opcodes.get(mnemonic).copied().unwrap_or(0)
+    }
+
+    /// every executed address, most executed first. Ties are broken by address
+    pub fn hot_spots(&self) -> Vec<HotSpot> {
+        let mut spots: Vec<HotSpot> = self
+            .addresses
+            .iter()
+            .map(|(&address, (count, instruction))| HotSpot {
+                address,
+                count: *count,
+                instruction: instruction.clone(),
+            })
+            .collect();
+
+        spots.sort_by(|a, b| b.count.cmp(&a.count).then(a.address.cmp(&b.address)));
+        spots
+    }
+
+    /// the number of relative base adjustment instructions executed
+    pub fn base_adjustments(&self) -> u64 {
+        self.base_adjustments
+    }
+
+    /// the lowest and highest relative base seen
+    pub fn base_range(&self) -> Option<(i64, i64)> {
+        self.base_range
+    }
+
+    /// the highest address read from or written to, i.e. how much memory the program needed
+    pub fn high_water_mark(&self) -> Option<usize> {
+        self.highest_read.max(self.highest_write)
+    }
+
+    pub fn reads(&self) -> u64 {
+        self.reads
+    }
+
+    pub fn writes(&self) -> u64 {
+        self.writes
+    }
+
+    pub fn inputs(&self) -> u64 {
+        self.inputs
+    }
+
+    pub fn outputs(&self) -> u64 {
+        self.outputs
+    }
+
+    /// the opcode counts, most executed first
+    fn opcodes_by_count(&self) -> Vec<(&str, u64)> {
+        let mut opcodes: Vec<(&str, u64)> = self.opcodes.iter().map(|(m, &c)| (&**m, c)).collect();
+        opcodes.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        opcodes
+    }
+
+    fn percent(&self, count: u64) -> f64 {
+        if self.instructions == 0 {
+            return 0.0;
+        }
+
+        count as f64 * 100.0 / self.instructions as f64
+    }
+
+    /// formats the profile as text, listing the `top` most executed addresses
+    pub fn report(&self, top: usize) -> String {
+        let optional = |value: Option<String>| value.unwrap_or_else(|| "-".to_string());
+        let mut report = String::new();
+
+        let _ = writeln!(report, "instructions     {}", self.instructions);
+        let _ = writeln!(report, "reads            {}", self.reads);
+        let _ = writeln!(report, "writes           {}", self.writes);
+        let _ = writeln!(report, "inputs           {}", self.inputs);
+        let _ = writeln!(report, "outputs          {}", self.outputs);
+        let _ = writeln!(report, "base adjustments {}", self.base_adjustments);
+        let _ = writeln!(
+            report,
+            "base range       {}",
+            optional(
+                self.base_range
+                    .map(|(min, max)| format!("{}..={}", min, max))
+            )
+        );
+        let _ = writeln!(
+            report,
+            "high water mark  {}",
+            optional(self.high_water_mark().map(|a| a.to_string()))
+        );
+
+        let _ = writeln!(
+            report,
+            "\n{:>6}  {:>12}  {:>6}  instruction",
+            "addr", "count", "%"
+        );
+        for spot in self.hot_spots().iter().take(top) {
+            let _ = writeln!(
+                report,
+                "{:>6}  {:>12}  {:>6.2}  {}",
+                spot.address,
+                spot.count,
+                self.percent(spot.count),
+                spot.instruction
+            );
+        }
+
+        let _ = writeln!(report, "\n{:>6}  {:>12}  {:>6}", "opcode", "count", "%");
+        for (mnemonic, count) in self.opcodes_by_count() {
+            let _ = writeln!(
+                report,
+                "{:>6}  {:>12}  {:>6.2}",
+                mnemonic,
+                count,
+                self.percent(count)
+            );
+        }
+
+        report
+    }
+
+    /// formats the profile as JSON, with every executed address in address order
+    pub fn to_json(&self) -> String {
+        let optional = |value: Option<String>| value.unwrap_or_else(|| "null".to_string());
+
+        let mut addresses: Vec<_> = self.addresses.iter().collect();
+        addresses.sort_by_key(|(&address, _)| address);
+
+        let addresses: Vec<String> = addresses
+            .into_iter()
+            .map(|(address, (count, instruction))| {
+                format!(
+                    "{{\"address\":{},\"count\":{},\"instruction\":{}}}",
+                    address,
+                    count,
+                    json_string(&instruction.to_string())
+                )
+            })
+            .collect();
+
+        let opcodes: Vec<String> = self
+            .opcodes
+            .iter()
+            .map(|(mnemonic, count)| format!("{}:{}", json_string(mnemonic), count))
+            .collect();
+
+        format!(
+            "{{\"instructions\":{},\"reads\":{},\"writes\":{},\"inputs\":{},\"outputs\":{},\
+             \"base_adjustments\":{},\"base_range\":{},\"high_water_mark\":{},\
+             \"opcodes\":{{{}}},\"addresses\":[{}]}}",
+            self.instructions,
+            self.reads,
+            self.writes,
+            self.inputs,
+            self.outputs,
+            self.base_adjustments,
+            optional(
+                self.base_range
+                    .map(|(min, max)| format!("[{},{}]", min, max))
+            ),
+            optional(self.high_water_mark().map(|a| a.to_string())),
+            opcodes.join(","),
+            addresses.join(",")
+        )
+    }
+}
+
+impl Profiler {
+    /// counts an instruction about to execute at the pointer
+    fn executed(
+        &mut self,
+        pointer: usize,
+        base: i64,
+        instruction: Profiled,
+        mnemonic: Cow<'static, str>,
+    ) {
+        self.instructions += 1;
+
+        match self.addresses.entry(pointer) {
+            Entry::Occupied(mut entry) => {
+                let (count, last) = entry.get_mut();
+                *count += 1;
+                *last = instruction;
+            }
+            Entry::Vacant(entry) => {
+                entry.insert((1, instruction));
+            }
+        }
+
+        *self.opcodes.entry(mnemonic).or_insert(0) += 1;
+
+        self.base_range = match self.base_range {
+            Some((min, max)) => Some((min.min(base), max.max(base))),
+            None => Some((base, base)),
+        };
+    }
+}
+
+impl fmt::Display for Profiled {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Profiled::Standard(instruction) => write!(f, "{}", instruction),
+            Profiled::Custom(mnemonic) => write!(f, "{}", mnemonic),
+        }
+    }
+}
+
+impl Observer for Profiler {
+    fn instruction(&mut self, pointer: usize, base: i64, instruction: &Instruction) {
+        let mnemonic = Cow::Borrowed(instruction.mnemonic());
+        self.executed(pointer, base, Profiled::Standard(*instruction), mnemonic);
+
+        if let Instruction::AdjustBase(_) = instruction {
+            self.base_adjustments += 1;
+        }
+    }
+
+    fn custom(&mut self, pointer: usize, base: i64, opcode: &Opcode) {
+        let mnemonic = opcode.mnemonic().to_string();
+        let instruction = Profiled::Custom(mnemonic.clone());
+        self.executed(pointer, base, instruction, Cow::Owned(mnemonic));
+    }
+
+    fn read(&mut self, address: usize, _value: i64) {
+        self.reads += 1;
+        self.highest_read = self.highest_read.max(Some(address));
+    }
+
+    fn write(&mut self, address: usize, _old: i64, _new: i64) {
+        self.writes += 1;
+        self.highest_write = self.highest_write.max(Some(address));
+    }
+
+    fn input(&mut self, _value: i64) {
+        self.inputs += 1;
+    }
+
+    fn output(&mut self, _value: i64) {
+        self.outputs += 1;
+    }
+}
+
+/// quotes a string for JSON, escaping anything which can't appear in it literally
+fn json_string(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::assembler::assemble;
+    use crate::intcode::dialect::{Dialect, Effect};
+    use crate::intcode::IntCodeEmulator;
+
+    /// reads a count, then outputs it and counts down to zero inside a stack frame
+    const COUNTDOWN: &str = "
+                arb #100
+                in [rb+0]
+        loop:   out [rb+0]
+                add [rb+0], #-1, [rb+0]
+                jt [rb+0], #loop
+                arb #-100
+                hlt
+    ";
+
+    fn profile() -> Profiler {
+        let mut vm = IntCodeEmulator::new(assemble(COUNTDOWN).unwrap());
+        vm.stdin().push_back(3);
+
+        let mut profiler = Profiler::new();
+        vm.execute_with(&mut profiler).unwrap();
+        profiler
+    }
+
+    #[test]
+    fn counts_execution() {
+        let profiler = profile();
+
+        assert_eq!(profiler.instructions(), 13);
+        assert_eq!(profiler.count(4), 3);
+        assert_eq!(profiler.opcode_count("out"), 3);
+        assert_eq!(profiler.opcode_count("arb"), 2);
+        assert_eq!(profiler.base_adjustments(), 2);
+        assert_eq!(profiler.base_range(), Some((0, 100)));
+        assert_eq!(profiler.high_water_mark(), Some(100));
+        assert_eq!((profiler.inputs(), profiler.outputs()), (1, 3));
+
+        let hottest = &profiler.hot_spots()[0];
+        assert_eq!((hottest.address, hottest.count), (4, 3));
+    }
+
+    #[test]
+    fn reports_text_and_json() {
+        let profiler = profile();
+
+        let report = profiler.report(1);
+        assert!(report.contains("instructions     13\n"));
+        assert!(report.contains("     4             3   23.08  out [rb+0]\n"));
+        assert!(report.contains("   add             3   23.08\n"));
+
+        let json = profiler.to_json();
+        assert!(json.starts_with("{\"instructions\":13,"));
+        assert!(json.contains("\"base_range\":[0,100]"));
+        assert!(json.contains("{\"address\":4,\"count\":3,\"instruction\":\"out [rb+0]\"}"));
+    }
+
+    #[test]
+    fn escapes_json_strings() {
+        let quote = Opcode::new("a\"b", &[], |_| Ok(Effect::Continue));
+        let mut vm = IntCodeEmulator::new(vec![20, 99]);
+        vm.set_dialect(Some(Dialect::day09().opcode(20, quote)));
+
+        let mut profiler = Profiler::new();
+        vm.execute_with(&mut profiler).unwrap();
+
+        let json = profiler.to_json();
+        assert!(json.contains("\"opcodes\":{\"a\\\"b\":1,\"hlt\":1}"));
+        assert!(json.contains("{\"address\":0,\"count\":1,\"instruction\":\"a\\\"b\"}"));
+        assert_eq!(json_string("\\\n\u{1}"), "\"\\\\\\n\\u0001\"");
+    }
+}
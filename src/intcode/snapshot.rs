@@ -0,0 +1,379 @@
+use crate::intcode::device::Ports;
+use crate::intcode::memory::PAGE_SIZE;
+use crate::intcode::{IntCodeEmulator, Memory, Strictness};
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+const MAGIC: &[u8; 4] = b"ICVM";
+const VERSION: u8 = 3;
+
+const DENSE: u8 = 0;
+const PAGED: u8 = 1;
+
+const PERMISSIVE: u8 = 0;
+const STRICT: u8 = 1;
+const LEGACY: u8 = 2;
+
+/// Snapshots store the full state of a VM so that it can be resumed later, possibly in another
+/// process.
+///
+/// The format is the magic bytes `ICVM` and a version byte, followed by the pointer, relative base,
+/// memory, stdin and stdout. Numbers are zig-zag encoded variable length integers so that the
+/// small values that make up most programs only take a byte or two, and each sequence of cells is
+/// prefixed by its length.
+///
+/// Memory starts with a byte giving its backend. Dense memory is a single sequence of cells, whereas
+/// paged memory is its length and number of pages, followed by the index and cells of each page.
+/// Version 1 snapshots, which always have dense memory without the backend byte, can still be
+/// loaded.
+///
+/// Since version 3, these are followed by a byte giving the strictness, the length of the program
+/// as it was loaded, and the instruction budget as a byte saying whether there is one and then its
+/// value. Older snapshots load as permissive, with all of their memory counted as the program and no
+/// budget. The memory limit, deadline and dialect aren't saved, so have to be set again after
+/// loading.
+///
+/// Only VMs using the default queue device can be snapshotted, since other devices may hold state
+/// outside of the VM.
+impl IntCodeEmulator {
+    pub fn save<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[VERSION])?;
+
+        write_varint(&mut writer, self.pointer as i64)?;
+        write_varint(&mut writer, self.base)?;
+        write_memory(&mut writer, &self.ram)?;
+        write_cells(
+            &mut writer,
+            self.device.input.iter().copied(),
+            self.device.input.len(),
+        )?;
+        write_cells(
+            &mut writer,
+            self.device.output.iter().copied(),
+            self.device.output.len(),
+        )?;
+
+        let strictness = match self.strictness {
+            Strictness::Permissive => PERMISSIVE,
+            Strictness::Strict => STRICT,
+            Strictness::Legacy => LEGACY,
+        };
+        writer.write_all(&[strictness])?;
+        write_varint(&mut writer, self.image as i64)?;
+
+        match self.budget {
+            Some(budget) => {
+                writer.write_all(&[1])?;
+                write_varint(&mut writer, budget as i64)?;
+            }
+            None => writer.write_all(&[0])?,
+        }
+
+        writer.flush()
+    }
+
+    pub fn load<R: Read>(mut reader: R) -> io::Result<IntCodeEmulator> {
+        let mut header = [0; 5];
+        reader.read_exact(&mut header)?;
+
+        if &header[..4] != MAGIC {
+            return Err(invalid("not an IntCode snapshot"));
+        }
+
+        if header[4] == 0 || header[4] > VERSION {
+            return Err(invalid("unsupported snapshot version"));
+        }
+
+        let pointer = read_varint(&mut reader)?;
+        if pointer < 0 {
+            return Err(invalid("negative pointer"));
+        }
+
+        let base = read_varint(&mut reader)?;
+        let ram = match header[4] {
+            1 => Memory::from(read_cells(&mut reader)?),
+            _ => read_memory(&mut reader)?,
+        };
+        let stdin = read_cells(&mut reader)?;
+        let stdout = read_cells(&mut reader)?;
+
+        // older snapshots don't record how long the program was, so everything in them counts as code
+        let (strictness, image, budget) = match header[4] {
+            1 | 2 => (Strictness::Permissive, ram.len(), None),
+            _ => read_limits(&mut reader)?,
+        };
+
+        Ok(IntCodeEmulator {
+            ram,
+            pointer: pointer as usize,
+            base,
+            device: Ports::new(VecDeque::from(stdin), VecDeque::from(stdout)),
+            cache: None,
+            budget,
+            deadline: None,
+            dialect: None,
+            strictness,
+            image,
+        })
+    }
+
+    pub fn save_to_file<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        self.save(BufWriter::new(File::create(path)?))
+    }
+
+    pub fn load_from_file<P: AsRef<Path>>(path: P) -> io::Result<IntCodeEmulator> {
+        IntCodeEmulator::load(BufReader::new(File::open(path)?))
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// reads the strictness, program length and instruction budget
+fn read_limits<R: Read>(reader: &mut R) -> io::Result<(Strictness, usize, Option<u64>)> {
+    let mut strictness = [0];
+    reader.read_exact(&mut strictness)?;
+
+    let strictness = match strictness[0] {
+        PERMISSIVE => Strictness::Permissive,
+        STRICT => Strictness::Strict,
+        LEGACY => Strictness::Legacy,
+        _ => return Err(invalid("unknown strictness")),
+    };
+
+    let image = read_varint(reader)?;
+    if image < 0 {
+        return Err(invalid("negative length"));
+    }
+
+    let mut has_budget = [0];
+    reader.read_exact(&mut has_budget)?;
+
+    let budget = match has_budget[0] {
+        0 => None,
+        1 => Some(read_varint(reader)? as u64),
+        _ => return Err(invalid("invalid budget")),
+    };
+
+    Ok((strictness, image as usize, budget))
+}
+
+fn write_memory<W: Write>(writer: &mut W, memory: &Memory) -> io::Result<()> {
+    let pages = match memory.pages() {
+        Some(pages) => pages,
+        None => {
+            writer.write_all(&[DENSE])?;
+            return write_cells(writer, memory.iter(), memory.len());
+        }
+    };
+
+    writer.write_all(&[PAGED])?;
+    write_varint(writer, memory.len() as i64)?;
+    write_varint(writer, pages.len() as i64)?;
+
+    for (index, page) in pages {
+        write_varint(writer, index as i64)?;
+        write_cells(writer, page.iter().copied(), page.len())?;
+    }
+
+    Ok(())
+}
+
+fn read_memory<R: Read>(reader: &mut R) -> io::Result<Memory> {
+    let mut backend = [0];
+    reader.read_exact(&mut backend)?;
+
+    match backend[0] {
+        DENSE => return Ok(Memory::from(read_cells(reader)?)),
+        PAGED => {}
+        _ => return Err(invalid("unknown memory backend")),
+    }
+
+    let len = read_varint(reader)?;
+    let count = read_varint(reader)?;
+    if len < 0 || count < 0 {
+        return Err(invalid("negative length"));
+    }
+
+    let mut pages = HashMap::new();
+
+    for _ in 0..count {
+        let index = read_varint(reader)?;
+        let cells = read_cells(reader)?;
+
+        if index < 0 || cells.len() != PAGE_SIZE {
+            return Err(invalid("invalid page"));
+        }
+
+        pages.insert(index as usize, cells.into_boxed_slice());
+    }
+
+    Ok(Memory::from_pages(len as usize, pages))
+}
+
+fn write_cells<W, I>(writer: &mut W, cells: I, len: usize) -> io::Result<()>
+where
+    W: Write,
+    I: Iterator<Item = i64>,
+{
+    write_varint(writer, len as i64)?;
+
+    for cell in cells {
+        write_varint(writer, cell)?;
+    }
+
+    Ok(())
+}
+
+fn read_cells<R: Read>(reader: &mut R) -> io::Result<Vec<i64>> {
+    let len = read_varint(reader)?;
+    if len < 0 {
+        return Err(invalid("negative length"));
+    }
+
+    // don't trust the length for allocation in case the snapshot is corrupt
+    let mut cells = Vec::with_capacity((len as usize).min(4096));
+
+    for _ in 0..len {
+        cells.push(read_varint(reader)?);
+    }
+
+    Ok(cells)
+}
+
+fn write_varint<W: Write>(writer: &mut W, value: i64) -> io::Result<()> {
+    let mut zigzag = ((value << 1) ^ (value >> 63)) as u64;
+
+    loop {
+        let byte = (zigzag & 0x7f) as u8;
+        zigzag >>= 7;
+
+        if zigzag == 0 {
+            return writer.write_all(&[byte]);
+        }
+
+        writer.write_all(&[byte | 0x80])?;
+    }
+}
+
+fn read_varint<R: Read>(reader: &mut R) -> io::Result<i64> {
+    let mut zigzag = 0_u64;
+    let mut byte = [0];
+
+    for shift in (0..64).step_by(7) {
+        reader.read_exact(&mut byte)?;
+        zigzag |= u64::from(byte[0] & 0x7f) << shift;
+
+        if byte[0] & 0x80 == 0 {
+            return Ok((zigzag >> 1) as i64 ^ -((zigzag & 1) as i64));
+        }
+    }
+
+    Err(invalid("number too long"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::{ErrorKind, MemoryBackend, YieldReason};
+
+    const INPUT: &str = include_str!("../../input/2019/day11.txt");
+
+    #[test]
+    fn round_trips_varints() {
+        for &value in &[0, 1, -1, 63, -64, 64, 1 << 40, i64::MAX, i64::MIN] {
+            let mut bytes = Vec::new();
+            write_varint(&mut bytes, value).unwrap();
+            assert_eq!(read_varint(&mut &bytes[..]).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn resumes_from_snapshot() {
+        let mut vm = IntCodeEmulator::from_input(INPUT);
+        vm.stdin().push_back(0);
+        assert_eq!(
+            vm.execute_until_yield().unwrap(),
+            YieldReason::InputRequired
+        );
+
+        let mut bytes = Vec::new();
+        vm.save(&mut bytes).unwrap();
+        let mut restored = IntCodeEmulator::load(&bytes[..]).unwrap();
+
+        for vm in [&mut vm, &mut restored].iter_mut() {
+            vm.stdin().push_back(1);
+            vm.execute_until_yield().unwrap();
+        }
+
+        assert_eq!(restored.ram(), vm.ram());
+        assert_eq!(restored.stdout(), vm.stdout());
+        assert_eq!(restored.pointer(), vm.pointer());
+        assert_eq!(restored.base(), vm.base());
+    }
+
+    #[test]
+    fn round_trips_paged_memory() {
+        let mut vm = IntCodeEmulator::from_input(INPUT);
+        vm.set_memory_backend(MemoryBackend::Paged);
+        vm.poke(1 << 40, 42);
+
+        let mut bytes = Vec::new();
+        vm.save(&mut bytes).unwrap();
+        let restored = IntCodeEmulator::load(&bytes[..]).unwrap();
+
+        assert_eq!(restored.ram().backend(), MemoryBackend::Paged);
+        assert_eq!(restored.ram().len(), (1 << 40) + 1);
+        assert_eq!(restored.peek(1 << 40), 42);
+        assert_eq!(restored.peek(0), vm.peek(0));
+    }
+
+    #[test]
+    fn round_trips_strictness_and_budget() {
+        // writes an instruction beyond the end of the program, then jumps to it
+        let mut vm = IntCodeEmulator::new(vec![1101, 99, 0, 10, 1105, 1, 10]);
+        vm.set_strictness(Strictness::Strict);
+        vm.set_instruction_budget(Some(1));
+        assert_eq!(
+            vm.execute_until_yield().unwrap(),
+            YieldReason::BudgetExhausted
+        );
+
+        let mut bytes = Vec::new();
+        vm.save(&mut bytes).unwrap();
+        let mut restored = IntCodeEmulator::load(&bytes[..]).unwrap();
+
+        assert_eq!(restored.strictness(), Strictness::Strict);
+        assert_eq!(restored.instruction_budget(), Some(0));
+
+        restored.set_instruction_budget(None);
+        let error = restored.execute().unwrap_err();
+        assert_eq!(error.kind, ErrorKind::ExecutedData { address: 10 });
+
+        // version 2 snapshots have nothing after stdout, and load as permissive
+        let mut vm = IntCodeEmulator::new(vec![1101, 99, 0, 10, 1105, 1, 10]);
+        vm.set_strictness(Strictness::Strict);
+        let mut bytes = Vec::new();
+        vm.save(&mut bytes).unwrap();
+
+        bytes[4] = 2;
+        bytes.truncate(bytes.len() - 3);
+        let mut restored = IntCodeEmulator::load(&bytes[..]).unwrap();
+
+        assert_eq!(restored.strictness(), Strictness::Permissive);
+        assert!(restored.execute().is_ok());
+    }
+
+    #[test]
+    fn rejects_invalid_snapshots() {
+        let error = IntCodeEmulator::load(&b"nope!"[..]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        let error = IntCodeEmulator::load(&b"ICVM\x01\x02"[..]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
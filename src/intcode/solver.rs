@@ -0,0 +1,626 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryFrom;
+use std::fmt;
+use std::ops::Index;
+
+/// An unknown value, such as a symbolic input
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Symbol(pub usize);
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "x{}", self.0)
+    }
+}
+
+/// A constant plus a sum of symbols multiplied by constants. Arithmetic is exact, like the
+/// solver's, so anything which doesn't fit in an i64 fails rather than wrapping like the emulator
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Linear {
+    pub constant: i64,
+
+    /// the coefficient of each symbol, which is never zero
+    pub terms: BTreeMap<Symbol, i64>,
+}
+
+impl Linear {
+    pub fn constant(value: i64) -> Linear {
+        Linear {
+            constant: value,
+            terms: BTreeMap::new(),
+        }
+    }
+
+    pub fn symbol(symbol: Symbol) -> Linear {
+        let mut terms = BTreeMap::new();
+        terms.insert(symbol, 1);
+
+        Linear { constant: 0, terms }
+    }
+
+    /// the value, if it doesn't depend on any symbols
+    pub fn as_constant(&self) -> Option<i64> {
+        if self.terms.is_empty() {
+            Some(self.constant)
+        } else {
+            None
+        }
+    }
+
+    /// the sum, or None if the constant or a coefficient overflows
+    pub fn add(&self, other: &Linear) -> Option<Linear> {
+        let mut sum = self.clone();
+        sum.constant = sum.constant.checked_add(other.constant)?;
+
+        for (&symbol, &coefficient) in &other.terms {
+            let total = sum
+                .terms
+                .get(&symbol)
+                .map_or(0, |&c| c)
+                .checked_add(coefficient)?;
+
+            if total == 0 {
+                sum.terms.remove(&symbol);
+            } else {
+                sum.terms.insert(symbol, total);
+            }
+        }
+
+        Some(sum)
+    }
+
+    /// the product, or None if the constant or a coefficient overflows
+    pub fn scale(&self, factor: i64) -> Option<Linear> {
+        if factor == 0 {
+            return Some(Linear::constant(0));
+        }
+
+        Some(Linear {
+            constant: self.constant.checked_mul(factor)?,
+            terms: self
+                .terms
+                .iter()
+                .map(|(&symbol, &coefficient)| Some((symbol, coefficient.checked_mul(factor)?)))
+                .collect::<Option<_>>()?,
+        })
+    }
+
+    pub fn subtract(&self, other: &Linear) -> Option<Linear> {
+        self.add(&other.scale(-1)?)
+    }
+
+    /// the value given a value for every symbol, or None if one is missing or the value doesn't
+    /// fit in an i64
+    pub fn evaluate(&self, model: &Model) -> Option<i64> {
+        let total = self.terms.iter().try_fold(
+            self.constant as i128,
+            |total, (symbol, &coefficient)| {
+                let value = model.get(*symbol)?;
+                total.checked_add((coefficient as i128).checked_mul(value as i128)?)
+            },
+        )?;
+
+        i64::try_from(total).ok()
+    }
+}
+
+impl fmt::Display for Linear {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut first = true;
+
+        for (symbol, &coefficient) in &self.terms {
+            let sign = if coefficient < 0 { "-" } else { "+" };
+            match (first, coefficient.unsigned_abs()) {
+                (true, 1) if coefficient < 0 => write!(f, "-{}", symbol)?,
+                (true, 1) => write!(f, "{}", symbol)?,
+                (true, _) => write!(f, "{}*{}", coefficient, symbol)?,
+                (false, 1) => write!(f, " {} {}", sign, symbol)?,
+                (false, magnitude) => write!(f, " {} {}*{}", sign, magnitude, symbol)?,
+            }
+            first = false;
+        }
+
+        match (first, self.constant) {
+            (true, constant) => write!(f, "{}", constant),
+            (false, 0) => Ok(()),
+            (false, constant) if constant < 0 => write!(f, " - {}", -(constant as i128)),
+            (false, constant) => write!(f, " + {}", constant),
+        }
+    }
+}
+
+/// A linear constraint the solver can handle
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Constraint {
+    /// the expression equals zero
+    Zero(Linear),
+    NonZero(Linear),
+
+    /// the expression is less than zero
+    Negative(Linear),
+    NonNegative(Linear),
+}
+
+impl Constraint {
+    /// constrains two expressions to be equal, or returns None if their difference overflows
+    pub fn equal(left: &Linear, right: &Linear) -> Option<Constraint> {
+        Some(Constraint::Zero(left.subtract(right)?))
+    }
+
+    fn expression(&self) -> &Linear {
+        match self {
+            Constraint::Zero(e)
+            | Constraint::NonZero(e)
+            | Constraint::Negative(e)
+            | Constraint::NonNegative(e) => e,
+        }
+    }
+
+    /// whether the constraint holds, or None if a symbol has no value
+    pub fn holds(&self, model: &Model) -> Option<bool> {
+        let value = self.expression().evaluate(model)?;
+
+        Some(match self {
+            Constraint::Zero(_) => value == 0,
+            Constraint::NonZero(_) => value != 0,
+            Constraint::Negative(_) => value < 0,
+            Constraint::NonNegative(_) => value >= 0,
+        })
+    }
+}
+
+impl fmt::Display for Constraint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Constraint::Zero(e) => write!(f, "{} == 0", e),
+            Constraint::NonZero(e) => write!(f, "{} != 0", e),
+            Constraint::Negative(e) => write!(f, "{} < 0", e),
+            Constraint::NonNegative(e) => write!(f, "{} >= 0", e),
+        }
+    }
+}
+
+/// A value for each symbol
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Model(BTreeMap<Symbol, i64>);
+
+impl Model {
+    pub fn get(&self, symbol: Symbol) -> Option<i64> {
+        self.0.get(&symbol).copied()
+    }
+
+    pub fn set(&mut self, symbol: Symbol, value: i64) {
+        self.0.insert(symbol, value);
+    }
+}
+
+impl Index<Symbol> for Model {
+    type Output = i64;
+
+    fn index(&self, symbol: Symbol) -> &i64 {
+        &self.0[&symbol]
+    }
+}
+
+/// The result of solving a set of constraints
+#[derive(Debug, Clone, PartialEq)]
+pub enum Verdict {
+    Satisfiable(Model),
+    Unsatisfiable,
+
+    /// the search would have been too large, or a symbol it needed to search had no bounds
+    Unknown,
+}
+
+/// an equation `coefficient * symbol + rest == 0`, used to eliminate the symbol
+#[derive(Debug, Clone)]
+struct Pivot {
+    symbol: Symbol,
+    coefficient: i128,
+    rest: Row,
+}
+
+/// a linear expression with room for elimination to combine rows of i64 coefficients. Anything
+/// which still overflows makes the verdict unknown
+#[derive(Debug, Clone, Default)]
+struct Row {
+    constant: i128,
+    terms: BTreeMap<Symbol, i128>,
+}
+
+impl Row {
+    fn from(linear: &Linear) -> Row {
+        Row {
+            constant: linear.constant as i128,
+            terms: linear
+                .terms
+                .iter()
+                .map(|(&symbol, &coefficient)| (symbol, coefficient as i128))
+                .collect(),
+        }
+    }
+
+    /// returns `a * self + b * other`, divided through by the gcd of its coefficients, or None if
+    /// it overflows
+    fn combine(&self, a: i128, other: &Row, b: i128) -> Option<Row> {
+        let sum = |x: i128, y: i128| a.checked_mul(x)?.checked_add(b.checked_mul(y)?);
+
+        let mut row = Row {
+            constant: sum(self.constant, other.constant)?,
+            terms: BTreeMap::new(),
+        };
+
+        let symbols: BTreeSet<Symbol> = self
+            .terms
+            .keys()
+            .chain(other.terms.keys())
+            .copied()
+            .collect();
+        for symbol in symbols {
+            let coefficient = sum(
+                self.terms.get(&symbol).map_or(0, |&c| c),
+                other.terms.get(&symbol).map_or(0, |&c| c),
+            )?;
+
+            if coefficient != 0 {
+                row.terms.insert(symbol, coefficient);
+            }
+        }
+
+        let divisor = row
+            .terms
+            .values()
+            .fold(row.constant.unsigned_abs(), |divisor, &coefficient| {
+                gcd(divisor, coefficient.unsigned_abs())
+            });
+
+        // a divisor which doesn't fit can only be 2^127, when everything is 0 or i128::MIN
+        if let Ok(divisor @ 2..) = i128::try_from(divisor) {
+            row.constant /= divisor;
+            for coefficient in row.terms.values_mut() {
+                *coefficient /= divisor;
+            }
+        }
+
+        Some(row)
+    }
+
+    /// the value given a value for every symbol, or None if it overflows
+    fn evaluate(&self, values: &BTreeMap<Symbol, i128>) -> Option<i128> {
+        self.terms
+            .iter()
+            .try_fold(self.constant, |total, (symbol, coefficient)| {
+                total.checked_add(coefficient.checked_mul(values[symbol])?)
+            })
+    }
+}
+
+fn gcd(a: u128, b: u128) -> u128 {
+    let (mut a, mut b) = (a, b);
+
+    while b != 0 {
+        let remainder = a % b;
+        a = b;
+        b = remainder;
+    }
+
+    a
+}
+
+/// Solves systems of linear integer constraints over bounded symbols.
+///
+/// Equalities are solved exactly by elimination, so a symbol they determine doesn't need to be
+/// searched. Every remaining symbol must be bounded, and their values are searched in ascending
+/// order, so the first solution found is the smallest in that order. The search gives up with
+/// `Verdict::Unknown` rather than trying more than the search limit of combinations.
+#[derive(Debug, Clone)]
+pub struct Solver {
+    constraints: Vec<Constraint>,
+    bounds: BTreeMap<Symbol, (i64, i64)>,
+    limit: u64,
+}
+
+impl Default for Solver {
+    fn default() -> Solver {
+        Solver {
+            constraints: Vec::new(),
+            bounds: BTreeMap::new(),
+            limit: 1_000_000,
+        }
+    }
+}
+
+impl Solver {
+    pub fn new() -> Solver {
+        Solver::default()
+    }
+
+    /// restricts a symbol to `min..=max`
+    pub fn bound(&mut self, symbol: Symbol, min: i64, max: i64) {
+        self.bounds.insert(symbol, (min, max));
+    }
+
+    pub fn assert(&mut self, constraint: Constraint) {
+        self.constraints.push(constraint);
+    }
+
+    /// the most combinations of values to try before giving up
+    pub fn set_search_limit(&mut self, limit: u64) {
+        self.limit = limit;
+    }
+
+    pub fn solve(&self) -> Verdict {
+        let mut symbols: BTreeSet<Symbol> = self.bounds.keys().copied().collect();
+        for constraint in &self.constraints {
+            symbols.extend(constraint.expression().terms.keys());
+        }
+
+        let pivots = match self.eliminate() {
+            Ok(pivots) => pivots,
+            Err(verdict) => return verdict,
+        };
+
+        let pivoted: BTreeSet<Symbol> = pivots.iter().map(|pivot| pivot.symbol).collect();
+        let free: Vec<Symbol> = symbols.difference(&pivoted).copied().collect();
+
+        let mut ranges = Vec::with_capacity(free.len());
+        let mut combinations: u64 = 1;
+
+        for symbol in &free {
+            let (min, max) = match self.bounds.get(symbol) {
+                Some(&(min, max)) if min <= max => (min, max),
+                Some(_) => return Verdict::Unsatisfiable,
+                None => return Verdict::Unknown,
+            };
+
+            let size = (max as i128 - min as i128 + 1).min(u64::MAX as i128) as u64;
+            combinations = combinations.saturating_mul(size);
+            ranges.push((min, max));
+        }
+
+        if combinations > self.limit {
+            return Verdict::Unknown;
+        }
+
+        let mut values: Vec<i64> = ranges.iter().map(|&(min, _)| min).collect();
+
+        loop {
+            if let Some(model) = self.complete(&free, &values, &pivots) {
+                return Verdict::Satisfiable(model);
+            }
+
+            // count through every combination like an odometer, the last symbol fastest
+            let mut index = free.len();
+            loop {
+                if index == 0 {
+                    return Verdict::Unsatisfiable;
+                }
+                index -= 1;
+
+                if values[index] < ranges[index].1 {
+                    values[index] += 1;
+                    break;
+                }
+                values[index] = ranges[index].0;
+            }
+        }
+    }
+
+    /// solves the equalities for as many symbols as possible, or returns the verdict if they
+    /// contradict or are too large to eliminate
+    fn eliminate(&self) -> Result<Vec<Pivot>, Verdict> {
+        let mut pivots: Vec<Pivot> = Vec::new();
+
+        for constraint in &self.constraints {
+            let mut row = match constraint {
+                Constraint::Zero(linear) => Row::from(linear),
+                _ => continue,
+            };
+
+            for pivot in &pivots {
+                if let Some(&a) = row.terms.get(&pivot.symbol) {
+                    let mut equation = pivot.rest.clone();
+                    equation.terms.insert(pivot.symbol, pivot.coefficient);
+                    row = row
+                        .combine(pivot.coefficient, &equation, -a)
+                        .ok_or(Verdict::Unknown)?;
+                }
+            }
+
+            if row.terms.is_empty() {
+                if row.constant != 0 {
+                    return Err(Verdict::Unsatisfiable);
+                }
+                continue;
+            }
+
+            // prefer a symbol which can be solved for without division, then one which isn't
+            // bounded, as only bounded symbols can be searched
+            let (&symbol, &coefficient) = row
+                .terms
+                .iter()
+                .min_by_key(|&(symbol, coefficient)| {
+                    (
+                        coefficient.unsigned_abs() != 1,
+                        self.bounds.contains_key(symbol),
+                        coefficient.unsigned_abs(),
+                    )
+                })
+                .expect("row has terms");
+
+            let mut rest = row.clone();
+            rest.terms.remove(&symbol);
+            let equation = row;
+
+            // remove the new symbol from the earlier pivots, so each depends only on free symbols
+            for pivot in &mut pivots {
+                if let Some(&b) = pivot.rest.terms.get(&symbol) {
+                    let mut old = pivot.rest.clone();
+                    old.terms.insert(pivot.symbol, pivot.coefficient);
+
+                    let mut combined = old
+                        .combine(coefficient, &equation, -b)
+                        .ok_or(Verdict::Unknown)?;
+                    pivot.coefficient = combined.terms.remove(&pivot.symbol).unwrap_or(0);
+                    pivot.rest = combined;
+                }
+            }
+
+            pivots.push(Pivot {
+                symbol,
+                coefficient,
+                rest,
+            });
+        }
+
+        Ok(pivots)
+    }
+
+    /// works out the pivoted symbols from values for the free ones, returning the model if every
+    /// constraint and bound holds
+    fn complete(&self, free: &[Symbol], values: &[i64], pivots: &[Pivot]) -> Option<Model> {
+        let mut exact: BTreeMap<Symbol, i128> = free
+            .iter()
+            .zip(values)
+            .map(|(&symbol, &value)| (symbol, value as i128))
+            .collect();
+
+        for pivot in pivots {
+            let rest = pivot.rest.evaluate(&exact)?;
+            if pivot.coefficient == 0 || rest.checked_rem(pivot.coefficient)? != 0 {
+                return None;
+            }
+
+            exact.insert(
+                pivot.symbol,
+                rest.checked_div(pivot.coefficient)?.checked_neg()?,
+            );
+        }
+
+        let mut model = Model::default();
+        for (&symbol, &value) in &exact {
+            let value = i64::try_from(value).ok()?;
+            if let Some(&(min, max)) = self.bounds.get(&symbol) {
+                if value < min || value > max {
+                    return None;
+                }
+            }
+
+            model.set(symbol, value);
+        }
+
+        if self
+            .constraints
+            .iter()
+            .all(|constraint| constraint.holds(&model) == Some(true))
+        {
+            Some(model)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn linear(constant: i64, terms: &[(usize, i64)]) -> Linear {
+        terms
+            .iter()
+            .fold(Linear::constant(constant), |sum, &(symbol, coefficient)| {
+                let term = Linear::symbol(Symbol(symbol)).scale(coefficient).unwrap();
+                sum.add(&term).unwrap()
+            })
+    }
+
+    #[test]
+    fn solves_equalities_without_searching() {
+        // x + y = 10, x - y = 4, with nothing bounded
+        let mut solver = Solver::new();
+        solver.assert(Constraint::Zero(linear(-10, &[(0, 1), (1, 1)])));
+        solver.assert(Constraint::Zero(linear(-4, &[(0, 1), (1, -1)])));
+
+        match solver.solve() {
+            Verdict::Satisfiable(model) => {
+                assert_eq!((model[Symbol(0)], model[Symbol(1)]), (7, 3));
+            }
+            verdict => panic!("{:?}", verdict),
+        }
+    }
+
+    #[test]
+    fn searches_bounded_symbols() {
+        // 3x + 2y = 20 has no solution for y alone, so y is searched and x must divide evenly
+        let mut solver = Solver::new();
+        solver.bound(Symbol(0), 0, 10);
+        solver.bound(Symbol(1), 0, 10);
+        solver.assert(Constraint::Zero(linear(-20, &[(0, 3), (1, 2)])));
+        solver.assert(Constraint::Negative(linear(-3, &[(1, 1)])));
+        solver.assert(Constraint::NonZero(linear(0, &[(1, 1)])));
+
+        match solver.solve() {
+            Verdict::Satisfiable(model) => {
+                assert_eq!((model[Symbol(0)], model[Symbol(1)]), (6, 1));
+            }
+            verdict => panic!("{:?}", verdict),
+        }
+    }
+
+    #[test]
+    fn reports_unsatisfiable_and_unknown() {
+        let mut solver = Solver::new();
+        solver.assert(Constraint::Zero(linear(-1, &[(0, 2), (1, 2)])));
+        assert_eq!(solver.solve(), Verdict::Unknown);
+
+        solver.bound(Symbol(1), -100, 100);
+        assert_eq!(solver.solve(), Verdict::Unsatisfiable);
+
+        let mut solver = Solver::new();
+        solver.assert(Constraint::Zero(linear(-5, &[(0, 1)])));
+        solver.assert(Constraint::Zero(linear(-6, &[(0, 1)])));
+        assert_eq!(solver.solve(), Verdict::Unsatisfiable);
+    }
+
+    #[test]
+    fn checks_for_overflow() {
+        let big = linear(i64::MAX, &[(0, i64::MAX)]);
+        assert_eq!(big.add(&Linear::constant(1)), None);
+        assert_eq!(big.scale(2), None);
+        assert_eq!(
+            Linear::symbol(Symbol(0)).scale(i64::MIN).unwrap().scale(-1),
+            None
+        );
+
+        // the value only has to fit once it's complete
+        let mut model = Model::default();
+        model.set(Symbol(0), -1);
+        assert_eq!(big.evaluate(&model), Some(0));
+        model.set(Symbol(0), 1);
+        assert_eq!(big.evaluate(&model), None);
+
+        // eliminating equations with large coprime coefficients overflows even an i128
+        let mut solver = Solver::new();
+        for i in 0..4 {
+            let terms: Vec<_> = (0..4)
+                .map(|j| (j, i64::MAX - (i * 4 + j) as i64 * 2))
+                .collect();
+            solver.assert(Constraint::Zero(linear(i64::MAX - i as i64, &terms)));
+        }
+        assert_eq!(solver.solve(), Verdict::Unknown);
+    }
+
+    #[test]
+    fn formats_expressions() {
+        assert_eq!(linear(-5, &[(0, 3), (1, -1)]).to_string(), "3*x0 - x1 - 5");
+        assert_eq!(linear(7, &[]).to_string(), "7");
+        assert_eq!(
+            Constraint::NonNegative(linear(2, &[(2, -4)])).to_string(),
+            "-4*x2 + 2 >= 0"
+        );
+        assert_eq!(
+            linear(i64::MIN, &[(0, i64::MIN), (1, i64::MIN)]).to_string(),
+            "-9223372036854775808*x0 - 9223372036854775808*x1 - 9223372036854775808"
+        );
+    }
+}
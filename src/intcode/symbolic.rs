@@ -0,0 +1,575 @@
+use crate::intcode::solver::{Constraint, Linear, Symbol};
+use crate::intcode::{address, ErrorKind, Instruction, ReadValue, WriteValue};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+
+/// A value computed from symbols
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    Linear(Linear),
+    Add(Box<Expr>, Box<Expr>),
+    Multiply(Box<Expr>, Box<Expr>),
+
+    /// 1 if the left side is less than the right, otherwise 0
+    LessThan(Box<Expr>, Box<Expr>),
+
+    /// 1 if both sides are equal, otherwise 0
+    Equals(Box<Expr>, Box<Expr>),
+
+    /// the value of memory at an address which depends on symbols
+    Load(Box<Expr>),
+}
+
+impl Expr {
+    pub fn constant(value: i64) -> Expr {
+        Expr::Linear(Linear::constant(value))
+    }
+
+    pub fn symbol(symbol: Symbol) -> Expr {
+        Expr::Linear(Linear::symbol(symbol))
+    }
+
+    /// the expression as a linear sum, if it is one
+    pub fn as_linear(&self) -> Option<&Linear> {
+        match self {
+            Expr::Linear(linear) => Some(linear),
+            _ => None,
+        }
+    }
+
+    /// the value, if it doesn't depend on any symbols
+    pub fn as_constant(&self) -> Option<i64> {
+        self.as_linear().and_then(Linear::as_constant)
+    }
+
+    /// the sum, which wraps like the emulator's if both sides are constants. A linear sum which
+    /// overflows can't be kept exact, so it's left opaque
+    pub fn sum(left: Expr, right: Expr) -> Expr {
+        let linear = match (&left, &right) {
+            (Expr::Linear(l), Expr::Linear(r)) => match (l.as_constant(), r.as_constant()) {
+                (Some(l), Some(r)) => return Expr::constant(l.wrapping_add(r)),
+                _ => l.add(r),
+            },
+            _ => None,
+        };
+
+        match linear {
+            Some(linear) => Expr::Linear(linear),
+            None => Expr::Add(Box::new(left), Box::new(right)),
+        }
+    }
+
+    /// the product, which wraps like the emulator's if both sides are constants. A linear product
+    /// which overflows can't be kept exact, so it's left opaque
+    pub fn product(left: Expr, right: Expr) -> Expr {
+        let linear = match (left.as_constant(), right.as_constant(), &left, &right) {
+            (Some(l), Some(r), ..) => return Expr::constant(l.wrapping_mul(r)),
+            (Some(factor), _, _, Expr::Linear(r)) => r.scale(factor),
+            (_, Some(factor), Expr::Linear(l), _) => l.scale(factor),
+            (Some(0), ..) | (_, Some(0), ..) => return Expr::constant(0),
+            _ => None,
+        };
+
+        match linear {
+            Some(linear) => Expr::Linear(linear),
+            None => Expr::Multiply(Box::new(left), Box::new(right)),
+        }
+    }
+
+    pub fn less_than(left: Expr, right: Expr) -> Expr {
+        match (left.as_constant(), right.as_constant()) {
+            (Some(l), Some(r)) => Expr::constant((l < r) as i64),
+            _ => Expr::LessThan(Box::new(left), Box::new(right)),
+        }
+    }
+
+    pub fn equals(left: Expr, right: Expr) -> Expr {
+        if let (Expr::Linear(l), Expr::Linear(r)) = (&left, &right) {
+            if let Some(difference) = l.subtract(r).and_then(|d| d.as_constant()) {
+                return Expr::constant((difference == 0) as i64);
+            }
+        }
+
+        Expr::Equals(Box::new(left), Box::new(right))
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expr::Linear(linear) => write!(f, "{}", linear),
+            Expr::Add(l, r) => write!(f, "({} + {})", l, r),
+            Expr::Multiply(l, r) => write!(f, "({} * {})", l, r),
+            Expr::LessThan(l, r) => write!(f, "({} < {})", l, r),
+            Expr::Equals(l, r) => write!(f, "({} == {})", l, r),
+            Expr::Load(a) => write!(f, "[{}]", a),
+        }
+    }
+}
+
+/// A condition a path depends on: the expression was non-zero if `holds`, or zero otherwise
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Condition {
+    pub expr: Expr,
+    pub holds: bool,
+}
+
+impl Condition {
+    /// the condition as a linear constraint, if it can be expressed as one
+    pub fn to_constraint(&self) -> Option<Constraint> {
+        let difference = |l: &Expr, r: &Expr| l.as_linear()?.subtract(r.as_linear()?);
+
+        Some(match (&self.expr, self.holds) {
+            (Expr::Linear(e), true) => Constraint::NonZero(e.clone()),
+            (Expr::Linear(e), false) => Constraint::Zero(e.clone()),
+            (Expr::LessThan(l, r), true) => Constraint::Negative(difference(l, r)?),
+            (Expr::LessThan(l, r), false) => Constraint::NonNegative(difference(l, r)?),
+            (Expr::Equals(l, r), true) => Constraint::Zero(difference(l, r)?),
+            (Expr::Equals(l, r), false) => Constraint::NonZero(difference(l, r)?),
+            _ => return None,
+        })
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.holds {
+            true => write!(f, "{} != 0", self.expr),
+            false => write!(f, "{} == 0", self.expr),
+        }
+    }
+}
+
+/// Why a path couldn't be followed any further
+#[derive(Debug, Clone, PartialEq)]
+pub enum Failure {
+    /// the program would have failed with the error however the symbols were chosen
+    Error(ErrorKind),
+
+    /// the instruction's opcode depends on symbols
+    SymbolicCode,
+
+    /// the instruction writes to an address which depends on symbols
+    SymbolicWrite,
+
+    /// the instruction jumps to an address which depends on symbols
+    SymbolicJump,
+
+    /// the instruction adjusts the relative base by an amount which depends on symbols
+    SymbolicBase,
+}
+
+/// How a path ended
+#[derive(Debug, Clone, PartialEq)]
+pub enum End {
+    Halted,
+    BudgetExhausted,
+    Failed(Failure),
+}
+
+/// A single path through a program, and the state at the end of it
+#[derive(Debug, Clone)]
+pub struct Path {
+    pub end: End,
+    pub pointer: usize,
+    pub base: i64,
+
+    /// the conditions which must hold for the program to take this path, in the order met
+    pub conditions: Vec<Condition>,
+
+    /// the symbol given for each input read after the concrete inputs ran out
+    pub inputs: Vec<Symbol>,
+    pub outputs: Vec<Expr>,
+
+    memory: HashMap<usize, Expr>,
+    len: usize,
+    steps: u64,
+}
+
+impl Path {
+    /// the value of a memory cell at the end of the path
+    pub fn cell(&self, address: usize) -> Expr {
+        self.memory
+            .get(&address)
+            .cloned()
+            .unwrap_or_else(|| Expr::constant(0))
+    }
+
+    /// the path's conditions as linear constraints, or None if any of them aren't linear
+    pub fn constraints(&self) -> Option<Vec<Constraint>> {
+        self.conditions
+            .iter()
+            .map(Condition::to_constraint)
+            .collect()
+    }
+}
+
+/// what happened when a path executed an instruction
+enum Step {
+    Continue,
+
+    /// a conditional jump depends on symbols, so the path has to split in two
+    Fork {
+        condition: Expr,
+        jump_if: bool,
+        target: usize,
+    },
+    Halted,
+}
+
+/// Executes a program with unknown values, to work out what it computes in terms of them.
+///
+/// Any cell can be made symbolic before running, and once the concrete inputs run out every value
+/// read by an input instruction is a new symbol. Values computed from symbols are kept as
+/// expressions, which stay linear wherever possible so they can be passed to the solver. When a
+/// conditional jump depends on symbols, execution forks and both paths are followed, each
+/// recording the condition it relies on.
+///
+/// Code itself has to stay concrete: a path fails if its opcodes, write addresses, jump targets
+/// or base adjustments depend on symbols. Reads from symbolic addresses are allowed, but their
+/// values are unknown.
+#[derive(Debug, Clone)]
+pub struct SymbolicEmulator {
+    start: Path,
+    input: VecDeque<i64>,
+    symbols: usize,
+}
+
+impl SymbolicEmulator {
+    pub fn new(program: Vec<i64>) -> SymbolicEmulator {
+        let len = program.len();
+        let memory = program
+            .into_iter()
+            .enumerate()
+            .filter(|&(_, value)| value != 0)
+            .map(|(address, value)| (address, Expr::constant(value)))
+            .collect();
+
+        SymbolicEmulator {
+            start: Path {
+                end: End::Halted,
+                pointer: 0,
+                base: 0,
+                conditions: Vec::new(),
+                inputs: Vec::new(),
+                outputs: Vec::new(),
+                memory,
+                len,
+                steps: 0,
+            },
+            input: VecDeque::new(),
+            symbols: 0,
+        }
+    }
+
+    /// concrete inputs, which are read before any symbolic ones
+    pub fn stdin(&mut self) -> &mut VecDeque<i64> {
+        &mut self.input
+    }
+
+    /// replaces the value of a cell with a new symbol
+    pub fn make_symbolic(&mut self, address: usize) -> Symbol {
+        let symbol = Symbol(self.symbols);
+        self.symbols += 1;
+
+        self.start.memory.insert(address, Expr::symbol(symbol));
+        self.start.len = self.start.len.max(address + 1);
+        symbol
+    }
+
+    /// follows up to `paths` paths through the program, each for up to `budget` instructions.
+    /// Paths are followed depth first, taking jumps before falling through
+    pub fn explore(&self, paths: usize, budget: u64) -> Vec<Path> {
+        let mut pending = vec![(self.start.clone(), self.input.clone())];
+        let mut finished = Vec::new();
+
+        while let Some((mut path, mut input)) = pending.pop() {
+            if finished.len() >= paths {
+                break;
+            }
+
+            loop {
+                if path.steps >= budget {
+                    path.end = End::BudgetExhausted;
+                    finished.push(path);
+                    break;
+                }
+
+                match self.step(&mut path, &mut input) {
+                    Ok(Step::Continue) => path.steps += 1,
+                    Ok(Step::Halted) => {
+                        path.end = End::Halted;
+                        finished.push(path);
+                        break;
+                    }
+                    Ok(Step::Fork {
+                        condition,
+                        jump_if,
+                        target,
+                    }) => {
+                        let mut jumped = path.clone();
+                        jumped.conditions.push(Condition {
+                            expr: condition.clone(),
+                            holds: jump_if,
+                        });
+                        jumped.pointer = target;
+                        jumped.steps += 1;
+
+                        path.conditions.push(Condition {
+                            expr: condition,
+                            holds: !jump_if,
+                        });
+                        path.pointer += 3;
+                        path.steps += 1;
+
+                        pending.push((path, input.clone()));
+                        path = jumped;
+                    }
+                    Err(failure) => {
+                        path.end = End::Failed(failure);
+                        finished.push(path);
+                        break;
+                    }
+                }
+            }
+        }
+
+        finished
+    }
+
+    /// executes a single instruction on a path
+    fn step(&self, path: &mut Path, input: &mut VecDeque<i64>) -> Result<Step, Failure> {
+        let pointer = path.pointer;
+
+        if pointer < path.len && path.cell(pointer).as_constant().is_none() {
+            return Err(Failure::SymbolicCode);
+        }
+
+        // decode with any symbolic parameters as zero, then use their expressions instead
+        let mut window = Vec::with_capacity(4);
+        for offset in 0..4 {
+            if pointer + offset >= path.len {
+                break;
+            }
+            window.push(path.cell(pointer + offset).as_constant().unwrap_or(0));
+        }
+
+        let instruction = Instruction::parse(&window[..], 0).map_err(Failure::Error)?;
+        let raw = |path: &Path, parameter: usize| path.cell(pointer + parameter);
+
+        let read = |path: &Path, value: ReadValue, parameter: usize| -> Result<Expr, Failure> {
+            let location = match value {
+                ReadValue::Immediate(_) => return Ok(raw(path, parameter)),
+                ReadValue::Position(_) => raw(path, parameter),
+                ReadValue::Relative(_) => {
+                    Expr::sum(raw(path, parameter), Expr::constant(path.base))
+                }
+            };
+
+            match location.as_constant() {
+                Some(position) => Ok(path.cell(address(position).map_err(Failure::Error)?)),
+                None => Ok(Expr::Load(Box::new(location))),
+            }
+        };
+
+        let target = |path: &Path, value: WriteValue, parameter: usize| -> Result<usize, Failure> {
+            let location = match value {
+                WriteValue::Position(_) => raw(path, parameter),
+                WriteValue::Relative(_) => {
+                    Expr::sum(raw(path, parameter), Expr::constant(path.base))
+                }
+            };
+
+            let position = location.as_constant().ok_or(Failure::SymbolicWrite)?;
+            address(position).map_err(Failure::Error)
+        };
+
+        let concrete = |expr: Expr, failure: Failure| expr.as_constant().ok_or(failure);
+
+        let written = match instruction {
+            Instruction::Add(left, right, dest) => {
+                let value = Expr::sum(read(path, left, 1)?, read(path, right, 2)?);
+                Some((target(path, dest, 3)?, value))
+            }
+            Instruction::Multiply(left, right, dest) => {
+                let value = Expr::product(read(path, left, 1)?, read(path, right, 2)?);
+                Some((target(path, dest, 3)?, value))
+            }
+            Instruction::Input(dest) => {
+                let value = match input.pop_front() {
+                    Some(value) => Expr::constant(value),
+                    None => {
+                        let symbol = Symbol(self.symbols + path.inputs.len());
+                        path.inputs.push(symbol);
+                        Expr::symbol(symbol)
+                    }
+                };
+                Some((target(path, dest, 1)?, value))
+            }
+            Instruction::Output(src) => {
+                let value = read(path, src, 1)?;
+                path.outputs.push(value);
+                None
+            }
+            Instruction::JumpTrue(condition, dest) | Instruction::JumpFalse(condition, dest) => {
+                let jump_if = matches!(instruction, Instruction::JumpTrue(..));
+                let condition = read(path, condition, 1)?;
+
+                match condition.as_constant() {
+                    Some(value) if (value != 0) == jump_if => {
+                        let dest = concrete(read(path, dest, 2)?, Failure::SymbolicJump)?;
+                        path.pointer = address(dest).map_err(Failure::Error)?;
+                        return Ok(Step::Continue);
+                    }
+                    Some(_) => None,
+                    None => {
+                        let dest = concrete(read(path, dest, 2)?, Failure::SymbolicJump)?;
+                        let target = address(dest).map_err(Failure::Error)?;
+                        return Ok(Step::Fork {
+                            condition,
+                            jump_if,
+                            target,
+                        });
+                    }
+                }
+            }
+            Instruction::LessThan(left, right, dest) => {
+                let value = Expr::less_than(read(path, left, 1)?, read(path, right, 2)?);
+                Some((target(path, dest, 3)?, value))
+            }
+            Instruction::Equals(left, right, dest) => {
+                let value = Expr::equals(read(path, left, 1)?, read(path, right, 2)?);
+                Some((target(path, dest, 3)?, value))
+            }
+            Instruction::AdjustBase(offset) => {
+                let offset = concrete(read(path, offset, 1)?, Failure::SymbolicBase)?;
+                path.base = path.base.wrapping_add(offset);
+                None
+            }
+            Instruction::Halt => return Ok(Step::Halted),
+        };
+
+        if let Some((address, value)) = written {
+            path.memory.insert(address, value);
+            path.len = path.len.max(address + 1);
+        }
+
+        path.pointer += instruction.size();
+        Ok(Step::Continue)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::assembler::assemble;
+    use crate::intcode::solver::{Solver, Verdict};
+
+    fn emulator(source: &str) -> SymbolicEmulator {
+        SymbolicEmulator::new(assemble(source).unwrap())
+    }
+
+    #[test]
+    fn builds_expressions_from_inputs() {
+        let vm = emulator(
+            "
+                in [a]
+                in [b]
+                mul [a], #3, [a]
+                add [a], [b], [a]
+                add [a], #-7, [a]
+                out [a]
+                hlt
+            a:  data 0
+            b:  data 0
+            ",
+        );
+
+        let paths = vm.explore(10, 100);
+        assert_eq!(paths.len(), 1);
+        assert_eq!(paths[0].end, End::Halted);
+        assert_eq!(paths[0].outputs[0].to_string(), "3*x0 + x1 - 7");
+    }
+
+    #[test]
+    fn forks_at_symbolic_jumps() {
+        let vm = emulator(
+            "
+                in [x]
+                lt [x], #10, [small]
+                jt [small], #yes
+                out #0
+                hlt
+            yes: out #1
+                hlt
+            x:  data 0
+            small: data 0
+            ",
+        );
+
+        let paths = vm.explore(10, 100);
+        assert_eq!(paths.len(), 2);
+
+        for path in &paths {
+            let mut solver = Solver::new();
+            for constraint in path.constraints().unwrap() {
+                solver.assert(constraint);
+            }
+            solver.bound(path.inputs[0], 0, 100);
+
+            let model = match solver.solve() {
+                Verdict::Satisfiable(model) => model,
+                verdict => panic!("{:?}", verdict),
+            };
+
+            let small = model[path.inputs[0]] < 10;
+            assert_eq!(path.outputs, vec![Expr::constant(small as i64)]);
+        }
+
+        assert_eq!(paths[0].conditions[0].to_string(), "(x0 < 10) != 0");
+    }
+
+    #[test]
+    fn uses_concrete_inputs_first() {
+        let mut vm = emulator("in [5]\nin [6]\nhlt");
+        vm.stdin().push_back(42);
+
+        let path = &vm.explore(1, 100)[0];
+        assert_eq!(path.cell(5), Expr::constant(42));
+        assert_eq!(path.cell(6), Expr::symbol(Symbol(0)));
+    }
+
+    #[test]
+    fn wraps_constants_but_keeps_linear_sums_exact() {
+        let vm = emulator(
+            "
+                in [x]
+                add #9223372036854775807, #1, [wrapped]
+                mul [x], #9223372036854775807, [scaled]
+                add [scaled], [scaled], [doubled]
+                hlt
+            x:  data 0
+            wrapped: data 0
+            scaled: data 0
+            doubled: data 0
+            ",
+        );
+
+        let path = &vm.explore(1, 100)[0];
+        let (wrapped, scaled, doubled) = (path.cell(16), path.cell(17), path.cell(18));
+        assert_eq!(wrapped, Expr::constant(i64::MIN));
+        assert_eq!(scaled.to_string(), "9223372036854775807*x0");
+        assert_eq!(doubled, Expr::sum(scaled.clone(), scaled));
+        assert!(doubled.as_linear().is_none());
+    }
+
+    #[test]
+    fn stops_at_symbolic_writes() {
+        let mut vm = emulator("add #1, #1, [0]\nhlt");
+        vm.make_symbolic(3);
+
+        let path = &vm.explore(1, 100)[0];
+        assert_eq!(path.end, End::Failed(Failure::SymbolicWrite));
+        assert_eq!(path.pointer, 0);
+    }
+}
@@ -0,0 +1,308 @@
+use crate::intcode::ascii::AsciiEmulator;
+use crate::intcode::{IntCodeEmulator, YieldReason};
+use std::collections::VecDeque;
+use std::fs;
+use std::io::{self, BufRead, Write};
+
+/// What the terminal should do after handling a line of input
+enum Next {
+    /// wait for another line, because the last one was handled by the terminal itself
+    Wait,
+
+    /// resume the VM, because a command was sent to it
+    Resume,
+
+    Quit,
+}
+
+/// Plays an ASCII program interactively, such as a text adventure.
+///
+/// Lines are sent to the program as commands, except for lines starting with `!` which control the
+/// terminal itself: repeating earlier commands, running scripts of commands from a file, and saving
+/// or loading the state of the game. Everything shown on the terminal can also be copied to a
+/// transcript.
+pub struct Terminal {
+    vm: AsciiEmulator,
+    history: Vec<String>,
+
+    /// scripted commands waiting to be sent, which take priority over the user's input
+    script: VecDeque<String>,
+    transcript: Option<Box<dyn Write>>,
+}
+
+impl Terminal {
+    pub fn new(vm: IntCodeEmulator) -> Terminal {
+        Terminal {
+            vm: AsciiEmulator::new(vm),
+            history: Vec::new(),
+            script: VecDeque::new(),
+            transcript: None,
+        }
+    }
+
+    pub fn vm(&self) -> &AsciiEmulator {
+        &self.vm
+    }
+
+    /// every command sent to the program so far
+    pub fn history(&self) -> &[String] {
+        &self.history
+    }
+
+    /// copies everything shown on the terminal, including the commands typed, to a writer
+    pub fn set_transcript(&mut self, transcript: Box<dyn Write>) {
+        self.transcript = Some(transcript);
+    }
+
+    /// queues the commands in a file, one per line, to be sent before reading any more input
+    pub fn load_script(&mut self, path: &str) -> io::Result<()> {
+        let text = fs::read_to_string(path)?;
+        let lines = text.lines().filter(|line| !line.trim().is_empty());
+
+        for (index, line) in lines.enumerate() {
+            self.script.insert(index, line.to_string());
+        }
+
+        Ok(())
+    }
+
+    /// runs the program, reading commands from `input` until it halts, the input is exhausted or
+    /// the user quits
+    pub fn play<R: BufRead, W: Write>(&mut self, input: R, mut output: W) -> io::Result<()> {
+        let mut lines = input.lines();
+
+        loop {
+            let result = self.vm.execute_until_yield();
+
+            let text = self.vm.take_text();
+            self.show(&mut output, &text)?;
+
+            for value in self.vm.take_values() {
+                self.show(&mut output, &format!("{}\n", value))?;
+            }
+
+            match result {
+                Ok(YieldReason::InputRequired) => {}
+                // halted, or stopped by a budget or deadline set on the VM
+                Ok(_) => return self.flush(),
+                Err(e) => {
+                    self.show(&mut output, &format!("error: {}\n", e))?;
+                    return self.flush();
+                }
+            }
+
+            loop {
+                let line = match self.script.pop_front() {
+                    Some(line) => {
+                        // show scripted commands as though they'd been typed
+                        self.show(&mut output, &format!("{}\n", line))?;
+                        line
+                    }
+                    None => match lines.next() {
+                        Some(line) => {
+                            let line = line?;
+                            self.record(&format!("{}\n", line))?;
+                            line
+                        }
+                        None => return self.flush(),
+                    },
+                };
+
+                match self.command(line.trim(), &mut output) {
+                    Ok(Next::Wait) => {}
+                    Ok(Next::Resume) => break,
+                    Ok(Next::Quit) => return self.flush(),
+                    Err(message) => self.show(&mut output, &format!("error: {}\n", message))?,
+                }
+            }
+        }
+    }
+
+    /// handles a single line of input
+    fn command<W: Write>(&mut self, line: &str, output: &mut W) -> Result<Next, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let argument = || {
+            words
+                .get(1)
+                .copied()
+                .ok_or_else(|| format!("'{}' needs an argument", words[0]))
+        };
+        let io_error = |e: io::Error| e.to_string();
+
+        let command = match words.first().copied() {
+            Some("!history") => {
+                let listing: String = self
+                    .history
+                    .iter()
+                    .enumerate()
+                    .map(|(index, command)| format!("{:>4}  {}\n", index + 1, command))
+                    .collect();
+                self.show(output, &listing).map_err(io_error)?;
+                return Ok(Next::Wait);
+            }
+            Some("!script") => {
+                self.load_script(argument()?).map_err(io_error)?;
+                return Ok(Next::Wait);
+            }
+            Some("!save") => {
+                let path = argument()?;
+                self.vm.vm().save_to_file(path).map_err(io_error)?;
+                self.show(output, &format!("saved {}\n", path))
+                    .map_err(io_error)?;
+                return Ok(Next::Wait);
+            }
+            Some("!load") => {
+                let path = argument()?;
+                let vm = IntCodeEmulator::load_from_file(path).map_err(io_error)?;
+                self.vm = AsciiEmulator::new(vm);
+                self.show(output, &format!("loaded {}\n", path))
+                    .map_err(io_error)?;
+                return Ok(Next::Wait);
+            }
+            Some("!quit") => return Ok(Next::Quit),
+            Some("!help") => {
+                self.show(output, HELP).map_err(io_error)?;
+                return Ok(Next::Wait);
+            }
+            Some("!!") => self
+                .history
+                .last()
+                .cloned()
+                .ok_or_else(|| "no commands in history".to_string())?,
+            Some(word) if word.starts_with('!') => {
+                let index: usize = word[1..]
+                    .parse()
+                    .map_err(|_| format!("unknown command '{}', try '!help'", word))?;
+                self.history
+                    .get(index.wrapping_sub(1))
+                    .cloned()
+                    .ok_or_else(|| format!("no command {} in history", index))?
+            }
+            _ => line.to_string(),
+        };
+
+        if command != line {
+            self.show(output, &format!("{}\n", command))
+                .map_err(io_error)?;
+        }
+
+        self.vm.push_line(&command);
+        self.history.push(command);
+        Ok(Next::Resume)
+    }
+
+    /// writes text to the terminal and the transcript
+    fn show<W: Write>(&mut self, output: &mut W, text: &str) -> io::Result<()> {
+        write!(output, "{}", text)?;
+        output.flush()?;
+        self.record(text)
+    }
+
+    /// writes text to the transcript only
+    fn record(&mut self, text: &str) -> io::Result<()> {
+        match &mut self.transcript {
+            Some(transcript) => write!(transcript, "{}", text),
+            None => Ok(()),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.transcript {
+            Some(transcript) => transcript.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+const HELP: &str = "\
+!!                    repeat the last command
+!<n>                  repeat command n from the history
+!history              list the commands sent so far
+!script <file>        send the commands in a file, one per line
+!save <file>          save the game
+!load <file>          load a saved game
+!quit                 stop playing
+!help                 show this message
+";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::assembler::assemble;
+    use std::env;
+    use std::process;
+
+    /// echoes three lines of input, then outputs 1000
+    const GAME: &str = "
+        prompt: out #62
+                out #32
+        loop:   in [char]
+                out [char]
+                eq [char], #10, [done]
+                jf [done], #loop
+                add [count], #-1, [count]
+                jt [count], #prompt
+                out #1000
+                hlt
+        char:   data 0
+        done:   data 0
+        count:  data 3
+    ";
+
+    fn play(terminal: &mut Terminal, input: &str) -> String {
+        let mut output = Vec::new();
+        terminal.play(input.as_bytes(), &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    fn terminal() -> Terminal {
+        Terminal::new(IntCodeEmulator::new(assemble(GAME).unwrap()))
+    }
+
+    fn temp_file(name: &str) -> String {
+        let name = format!("advent2019-{}-{}", process::id(), name);
+        env::temp_dir().join(name).to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn repeats_commands_from_history() {
+        let mut terminal = terminal();
+        let output = play(&mut terminal, "north\n!!\n!history\n!1\n");
+
+        assert_eq!(
+            output,
+            "> north\n> north\nnorth\n>    1  north\n   2  north\nnorth\nnorth\n1000\n"
+        );
+        assert_eq!(terminal.history(), &["north", "north", "north"]);
+    }
+
+    #[test]
+    fn saves_and_loads_games() {
+        let path = temp_file("save.icvm");
+        let mut terminal = terminal();
+        let input = format!("a\n!save {0}\nb\n!load {0}\nc\nd\n", path);
+        let output = play(&mut terminal, &input);
+        fs::remove_file(&path).unwrap();
+
+        assert!(output.contains(&format!("loaded {}\nc\n> d\n1000\n", path)));
+    }
+
+    #[test]
+    fn runs_scripts_and_records_transcripts() {
+        let (script, transcript) = (temp_file("script.txt"), temp_file("transcript.txt"));
+        fs::write(&script, "x\n\ny\n").unwrap();
+
+        let mut terminal = terminal();
+        terminal.set_transcript(Box::new(fs::File::create(&transcript).unwrap()));
+        play(&mut terminal, &format!("!script {}\nz\n", script));
+
+        let recorded = fs::read_to_string(&transcript).unwrap();
+        fs::remove_file(&script).unwrap();
+        fs::remove_file(&transcript).unwrap();
+
+        assert_eq!(
+            recorded,
+            format!("> !script {}\nx\nx\n> y\ny\n> z\nz\n1000\n", script)
+        );
+    }
+}
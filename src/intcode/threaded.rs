@@ -0,0 +1,249 @@
+use crate::intcode::device::{Ports, Sink};
+use crate::intcode::{IntCodeEmulator, IntCodeError, QueueDevice};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+
+/// Sends every output value to each connected channel
+#[derive(Debug, Default)]
+pub struct FanOut(pub Vec<Sender<i64>>);
+
+impl Sink for FanOut {
+    fn push_value(&mut self, value: i64) {
+        for sender in &self.0 {
+            // a VM which has already finished doesn't need any more input
+            let _ = sender.send(value);
+        }
+    }
+}
+
+/// A device which blocks on a channel for input, and sends output to any number of channels
+pub type ChannelDevice = Ports<Receiver<i64>, FanOut>;
+
+/// identifies a VM added to a `ThreadedRunner`
+pub type NodeId = usize;
+
+struct Node {
+    vm: IntCodeEmulator,
+    sender: Sender<i64>,
+    receiver: Receiver<i64>,
+    outputs: Vec<Sender<i64>>,
+}
+
+/// Runs a network of VMs, each on its own thread, connected by channels.
+///
+/// Each VM has a single input channel which any number of other VMs can send to (fan-in), and can
+/// send its output to any number of other VMs (fan-out). Instead of yielding, a VM blocks until input
+/// arrives, so no scheduling is required. A VM only fails with `InputRequired` once it's waiting on
+/// input which can never arrive because every VM that could send it has finished.
+#[derive(Default)]
+pub struct ThreadedRunner {
+    nodes: Vec<Node>,
+}
+
+impl ThreadedRunner {
+    pub fn new() -> ThreadedRunner {
+        ThreadedRunner::default()
+    }
+
+    /// adds a VM to the network. Anything already queued on its stdin is sent to it first
+    pub fn add(&mut self, mut vm: IntCodeEmulator) -> NodeId {
+        let (sender, receiver) = mpsc::channel();
+
+        for value in vm.stdin().drain(..) {
+            sender.send(value).expect("Receiver is held by the runner");
+        }
+
+        self.nodes.push(Node {
+            vm,
+            sender,
+            receiver,
+            outputs: Vec::new(),
+        });
+
+        self.nodes.len() - 1
+    }
+
+    /// sends the output of one VM to the input of another
+    pub fn connect(&mut self, from: NodeId, to: NodeId) {
+        let sender = self.nodes[to].sender.clone();
+        self.nodes[from].outputs.push(sender);
+    }
+
+    /// returns a channel for sending input to a VM from outside the network. The VM will wait for
+    /// input for as long as the sender is kept alive
+    pub fn sender(&self, node: NodeId) -> Sender<i64> {
+        self.nodes[node].sender.clone()
+    }
+
+    /// returns a channel which receives a copy of everything a VM outputs
+    pub fn tap(&mut self, node: NodeId) -> Receiver<i64> {
+        let (sender, receiver) = mpsc::channel();
+        self.nodes[node].outputs.push(sender);
+        receiver
+    }
+
+    /// runs every VM to completion, returning them in the order they were added. Any input which
+    /// was sent to a VM but never read is left on its stdin, and everything it output is added to
+    /// its stdout
+    pub fn run(self) -> Result<Vec<IntCodeEmulator>, IntCodeError> {
+        let handles: Vec<_> = self
+            .nodes
+            .into_iter()
+            .map(|mut node| {
+                // the runner's own sender would stop the VM ever seeing that its input has run dry
+                drop(node.sender);
+
+                let mut stdout = std::mem::take(node.vm.stdout());
+                let (recorder, recorded) = mpsc::channel();
+                node.outputs.push(recorder);
+
+                let device = Ports::new(node.receiver, FanOut(node.outputs));
+                let mut vm = node.vm.with_device(device);
+
+                thread::spawn(move || {
+                    let result = vm.execute();
+
+                    // dropping the channels lets any VMs waiting on this one finish too
+                    let device = vm.device_mut();
+                    let unread = device.input.try_iter().collect();
+                    stdout.extend(recorded.try_iter());
+                    let vm = vm.with_device(QueueDevice::new(unread, stdout));
+
+                    result.map(|_| vm)
+                })
+            })
+            .collect();
+
+        let results: Vec<_> = handles
+            .into_iter()
+            .map(|handle| handle.join().expect("IntCode VM thread panicked"))
+            .collect();
+
+        results.into_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::assembler::assemble;
+    use crate::intcode::ErrorKind;
+    use itertools::Itertools;
+
+    const DAY07: &str = include_str!("../../input/2019/day7.txt");
+
+    /// outputs each input multiplied by the first input
+    const SCALER: &str = "
+                in [factor]
+        loop:   in [value]
+                mul [value], [factor], [value]
+                out [value]
+                jt #1, #loop
+        factor: data 0
+        value:  data 0
+    ";
+
+    /// outputs the running total of four inputs
+    const ADDER: &str = "
+                in [total]
+        loop:   in [value]
+                add [total], [value], [total]
+                out [total]
+                add [count], #-1, [count]
+                jt [count], #loop
+                hlt
+        total:  data 0
+        value:  data 0
+        count:  data 3
+    ";
+
+    fn vm(source: &str, inputs: &[i64]) -> IntCodeEmulator {
+        let mut vm = IntCodeEmulator::new(assemble(source).unwrap());
+        vm.stdin().extend(inputs);
+        vm
+    }
+
+    #[test]
+    fn fans_out_and_in() {
+        let mut runner = ThreadedRunner::new();
+        let source = runner.add(vm(SCALER, &[1]));
+        let double = runner.add(vm(SCALER, &[2]));
+        let triple = runner.add(vm(SCALER, &[3]));
+        let sum = runner.add(vm(ADDER, &[]));
+
+        runner.connect(source, double);
+        runner.connect(source, triple);
+        runner.connect(double, sum);
+        runner.connect(triple, sum);
+
+        let input = runner.sender(source);
+        let output = runner.tap(sum);
+
+        let handle = thread::spawn(move || runner.run());
+        input.send(10).unwrap();
+        input.send(100).unwrap();
+
+        // values from the two branches can arrive in either order, but the total is the same
+        assert_eq!(output.iter().nth(2), Some(550));
+
+        // the scalers loop forever, so they only finish once their input has gone away
+        drop(input);
+        let error = handle.join().unwrap().unwrap_err();
+        assert_eq!(error.kind, ErrorKind::InputRequired);
+    }
+
+    #[test]
+    fn leaves_unread_input() {
+        let mut runner = ThreadedRunner::new();
+        runner.add(vm(ADDER, &[1, 2, 3, 4, 5, 6]));
+
+        let mut vms = runner.run().unwrap();
+        assert_eq!(vms[0].stdin().iter().collect::<Vec<_>>(), vec![&5, &6]);
+    }
+
+    #[test]
+    fn leaves_output() {
+        let mut adder = vm(ADDER, &[1, 2, 3, 4]);
+        adder.stdout().push_back(-1);
+
+        let mut runner = ThreadedRunner::new();
+        runner.add(adder);
+
+        let mut vms = runner.run().unwrap();
+        assert_eq!(vms[0].stdout(), &[-1, 3, 6, 10]);
+    }
+
+    #[test]
+    fn runs_feedback_loops() {
+        let program = IntCodeEmulator::parse_input(DAY07);
+
+        let best = (5..10)
+            .permutations(5)
+            .map(|phases| {
+                let mut runner = ThreadedRunner::new();
+                let nodes: Vec<_> = phases
+                    .iter()
+                    .map(|&phase| runner.add(vm_with(&program, phase)))
+                    .collect();
+
+                for (&from, &to) in nodes.iter().zip(nodes.iter().cycle().skip(1)) {
+                    runner.connect(from, to);
+                }
+
+                runner.sender(nodes[0]).send(0).unwrap();
+                let output = runner.tap(nodes[4]);
+                runner.run().unwrap();
+
+                output.try_iter().last().unwrap()
+            })
+            .max();
+
+        assert_eq!(best, Some(19_741_286));
+    }
+
+    fn vm_with(program: &[i64], phase: i64) -> IntCodeEmulator {
+        let mut vm = IntCodeEmulator::new(program.to_vec());
+        vm.stdin().push_back(phase);
+        vm
+    }
+}
@@ -0,0 +1,291 @@
+use crate::intcode::{IntCodeEmulator, IntCodeError, YieldReason};
+use itertools::Itertools;
+
+/// The best phase settings found by an optimiser, and the signal they produced
+#[derive(Debug, Clone, PartialEq)]
+pub struct Best {
+    pub phases: Vec<i64>,
+    pub signal: i64,
+}
+
+/// Describes how a set of VMs, such as amplifiers, are wired together.
+///
+/// Every VM runs the same program and is given its phase setting as its first input. Each output a
+/// VM produces is sent to the input of every VM it's connected to, and the signal is the last value
+/// output by the output VM. Seeds are extra inputs given to VMs after their phase settings, to start
+/// the signal off.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Topology {
+    targets: Vec<Vec<usize>>,
+    seeds: Vec<(usize, i64)>,
+    output: usize,
+}
+
+impl Topology {
+    /// creates a topology of unconnected VMs, with no seeds and the last VM as the output
+    pub fn new(size: usize) -> Topology {
+        Topology {
+            targets: vec![Vec::new(); size],
+            seeds: Vec::new(),
+            output: size.saturating_sub(1),
+        }
+    }
+
+    /// VMs connected one after another, where the first is seeded with 0 and the last is the output
+    pub fn chain(size: usize) -> Topology {
+        assert!(size > 0, "Expected at least one VM");
+        let mut topology = Topology::new(size);
+
+        for node in 1..size {
+            topology.connect(node - 1, node);
+        }
+
+        topology.seed(0, 0);
+        topology
+    }
+
+    /// a chain where the last VM also feeds back into the first
+    pub fn ring(size: usize) -> Topology {
+        Topology::rings(&[size])
+    }
+
+    /// rings of the given sizes, where the last VM of each ring also feeds the first of the next
+    pub fn rings(sizes: &[usize]) -> Topology {
+        let total = sizes.iter().sum();
+        assert!(total > 0, "Expected at least one VM");
+
+        let mut topology = Topology::new(total);
+        let mut start = 0;
+
+        for &size in sizes.iter().filter(|&&size| size > 0) {
+            let end = start + size - 1;
+
+            for node in start..end {
+                topology.connect(node, node + 1);
+            }
+            topology.connect(end, start);
+
+            if start > 0 {
+                topology.connect(start - 1, start);
+            }
+
+            start += size;
+        }
+
+        topology.seed(0, 0);
+        topology
+    }
+
+    /// the first VM sends its output to every other VM, and the last is the output
+    pub fn broadcast(size: usize) -> Topology {
+        assert!(size > 0, "Expected at least one VM");
+        let mut topology = Topology::new(size);
+
+        for node in 1..size {
+            topology.connect(0, node);
+        }
+
+        topology.seed(0, 0);
+        topology
+    }
+
+    /// the number of VMs in the topology
+    pub fn size(&self) -> usize {
+        self.targets.len()
+    }
+
+    /// the VMs which receive the output of the given VM
+    pub fn targets(&self, node: usize) -> &[usize] {
+        &self.targets[node]
+    }
+
+    pub fn connect(&mut self, from: usize, to: usize) {
+        self.targets[from].push(to);
+    }
+
+    /// gives a VM an extra input after its phase setting
+    pub fn seed(&mut self, node: usize, value: i64) {
+        self.seeds.push((node, value));
+    }
+
+    pub fn set_output(&mut self, node: usize) {
+        self.output = node;
+    }
+
+    /// runs the program on every VM with the given phase settings until they've all halted or none
+    /// can make any more progress, then returns the signal, if the output VM produced one
+    pub fn run(&self, program: &[i64], phases: &[i64]) -> Result<Option<i64>, IntCodeError> {
+        assert_eq!(phases.len(), self.size(), "Expected a phase for every VM");
+
+        let mut vms: Vec<IntCodeEmulator> = phases
+            .iter()
+            .map(|&phase| {
+                let mut vm = IntCodeEmulator::new(program.to_vec());
+                vm.stdin().push_back(phase);
+                vm
+            })
+            .collect();
+
+        for &(node, value) in &self.seeds {
+            vms[node].stdin().push_back(value);
+        }
+
+        let mut halted = vec![false; vms.len()];
+        let mut signal = None;
+        let mut progress = true;
+
+        while progress {
+            progress = false;
+
+            for node in 0..vms.len() {
+                if halted[node] {
+                    continue;
+                }
+
+                if vms[node].execute_until_yield()? == YieldReason::Halted {
+                    halted[node] = true;
+                    progress = true;
+                }
+
+                let outputs: Vec<i64> = vms[node].stdout().drain(..).collect();
+                if outputs.is_empty() {
+                    continue;
+                }
+
+                for &target in &self.targets[node] {
+                    vms[target].stdin().extend(&outputs);
+                }
+
+                if node == self.output {
+                    signal = outputs.last().copied();
+                }
+
+                progress = true;
+            }
+        }
+
+        Ok(signal)
+    }
+
+    /// tries every ordering of the given phase settings, returning the one with the highest signal
+    pub fn optimise_permutations(
+        &self,
+        program: &[i64],
+        phases: &[i64],
+    ) -> Result<Option<Best>, IntCodeError> {
+        let candidates = phases.iter().copied().permutations(self.size());
+        self.optimise_over(program, candidates)
+    }
+
+    /// tries every combination of phase settings, where each VM's setting comes from its own domain,
+    /// returning the one with the highest signal
+    pub fn optimise(
+        &self,
+        program: &[i64],
+        domains: &[Vec<i64>],
+    ) -> Result<Option<Best>, IntCodeError> {
+        assert_eq!(domains.len(), self.size(), "Expected a domain for every VM");
+
+        let candidates = domains
+            .iter()
+            .map(|domain| domain.iter().copied())
+            .multi_cartesian_product();
+        self.optimise_over(program, candidates)
+    }
+
+    fn optimise_over<I>(&self, program: &[i64], candidates: I) -> Result<Option<Best>, IntCodeError>
+    where
+        I: Iterator<Item = Vec<i64>>,
+    {
+        let mut best: Option<Best> = None;
+
+        for phases in candidates {
+            let signal = match self.run(program, &phases)? {
+                Some(signal) => signal,
+                None => continue,
+            };
+
+            if best.as_ref().is_none_or(|best| signal > best.signal) {
+                best = Some(Best { phases, signal });
+            }
+        }
+
+        Ok(best)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::assembler::assemble;
+
+    /// adds its phase to every input, halting once it outputs at least 100
+    const AMPLIFIER: &str = "
+                in [phase]
+        loop:   in [value]
+                add [value], [phase], [value]
+                out [value]
+                lt [value], #100, [more]
+                jt [more], #loop
+                hlt
+        phase:  data 0
+        value:  data 0
+        more:   data 0
+    ";
+
+    fn program() -> Vec<i64> {
+        assemble(AMPLIFIER).unwrap()
+    }
+
+    #[test]
+    fn runs_chains_rings_and_broadcasts() {
+        assert_eq!(Topology::chain(3).run(&program(), &[1, 2, 3]), Ok(Some(6)));
+        assert_eq!(Topology::ring(3).run(&program(), &[1, 2, 3]), Ok(Some(102)));
+        assert_eq!(
+            Topology::broadcast(3).run(&program(), &[5, 1, 2]),
+            Ok(Some(7))
+        );
+    }
+
+    #[test]
+    fn runs_empty_topology() {
+        assert_eq!(Topology::new(0).run(&program(), &[]), Ok(None));
+    }
+
+    #[test]
+    #[should_panic(expected = "Expected at least one VM")]
+    fn rejects_empty_chain() {
+        Topology::chain(0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Expected at least one VM")]
+    fn rejects_empty_rings() {
+        Topology::rings(&[]);
+    }
+
+    #[test]
+    fn links_multiple_rings() {
+        let topology = Topology::rings(&[2, 3]);
+
+        assert_eq!(topology.targets(0), &[1]);
+        assert_eq!(topology.targets(1), &[0, 2]);
+        assert_eq!(topology.targets(4), &[2]);
+        assert!(topology.run(&program(), &[1; 5]).unwrap().is_some());
+    }
+
+    #[test]
+    fn optimises_over_domains() {
+        let best = Topology::chain(2)
+            .optimise(&program(), &[vec![1, 2], vec![10, 20]])
+            .unwrap();
+
+        assert_eq!(
+            best,
+            Some(Best {
+                phases: vec![2, 20],
+                signal: 22
+            })
+        );
+    }
+}
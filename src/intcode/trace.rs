@@ -0,0 +1,308 @@
+use crate::intcode::dialect::Opcode;
+use crate::intcode::Instruction;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+
+/// Receives a notification for everything an emulator does whilst executing.
+///
+/// Every method defaults to doing nothing so implementations only need to handle the events they
+/// care about. Reads and writes are reported with their resolved addresses, so immediate mode
+/// parameters don't produce read events.
+pub trait Observer {
+    /// an instruction has been decoded at `pointer` and is about to execute
+    fn instruction(&mut self, _pointer: usize, _base: i64, _instruction: &Instruction) {}
+
+    /// a custom opcode from the emulator's dialect has been decoded at `pointer` and is about to
+    /// execute
+    fn custom(&mut self, _pointer: usize, _base: i64, _opcode: &Opcode) {}
+
+    /// a memory cell was read
+    fn read(&mut self, _address: usize, _value: i64) {}
+
+    /// a memory cell was written, replacing `old` with `new`
+    fn write(&mut self, _address: usize, _old: i64, _new: i64) {}
+
+    /// a value was taken from the input device
+    fn input(&mut self, _value: i64) {}
+
+    /// a value was sent to the output device
+    fn output(&mut self, _value: i64) {}
+}
+
+/// the unit observer ignores everything, so is used when no observer is required
+impl Observer for () {}
+
+/// A single event in an execution trace
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    Instruction {
+        pointer: usize,
+        base: i64,
+        instruction: Instruction,
+    },
+    Custom {
+        pointer: usize,
+        base: i64,
+        mnemonic: String,
+    },
+    Read {
+        address: usize,
+        value: i64,
+    },
+    Write {
+        address: usize,
+        old: i64,
+        new: i64,
+    },
+    Input(i64),
+    Output(i64),
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Event::Instruction {
+                pointer,
+                base,
+                instruction,
+            } => write!(f, "{:04} rb={} {}", pointer, base, instruction),
+            Event::Custom {
+                pointer,
+                base,
+                mnemonic,
+            } => write!(f, "{:04} rb={} {}", pointer, base, mnemonic),
+            Event::Read { address, value } => write!(f, "  read [{}] = {}", address, value),
+            Event::Write { address, old, new } => {
+                write!(f, "  write [{}] = {} (was {})", address, new, old)
+            }
+            Event::Input(value) => write!(f, "  input {}", value),
+            Event::Output(value) => write!(f, "  output {}", value),
+        }
+    }
+}
+
+/// Records every event in memory
+#[derive(Debug, Default)]
+pub struct Recorder {
+    pub events: Vec<Event>,
+}
+
+impl Observer for Recorder {
+    fn instruction(&mut self, pointer: usize, base: i64, instruction: &Instruction) {
+        self.events.push(Event::Instruction {
+            pointer,
+            base,
+            instruction: *instruction,
+        });
+    }
+
+    fn custom(&mut self, pointer: usize, base: i64, opcode: &Opcode) {
+        self.events.push(Event::Custom {
+            pointer,
+            base,
+            mnemonic: opcode.mnemonic().to_string(),
+        });
+    }
+
+    fn read(&mut self, address: usize, value: i64) {
+        self.events.push(Event::Read { address, value });
+    }
+
+    fn write(&mut self, address: usize, old: i64, new: i64) {
+        self.events.push(Event::Write { address, old, new });
+    }
+
+    fn input(&mut self, value: i64) {
+        self.events.push(Event::Input(value));
+    }
+
+    fn output(&mut self, value: i64) {
+        self.events.push(Event::Output(value));
+    }
+}
+
+/// Writes every event as a line of text, e.g. to a file for offline analysis
+#[derive(Debug)]
+pub struct TraceWriter<W: Write> {
+    writer: W,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> TraceWriter<W> {
+    pub fn new(writer: W) -> TraceWriter<W> {
+        TraceWriter {
+            writer,
+            error: None,
+        }
+    }
+
+    /// flushes the trace and returns the underlying writer, or the first error encountered
+    pub fn finish(mut self) -> io::Result<W> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn record(&mut self, event: Event) {
+        // observers can't fail, so hold on to the first error until the trace is finished
+        if self.error.is_none() {
+            if let Err(e) = writeln!(self.writer, "{}", event) {
+                self.error = Some(e);
+            }
+        }
+    }
+}
+
+impl<W: Write> Observer for TraceWriter<W> {
+    fn instruction(&mut self, pointer: usize, base: i64, instruction: &Instruction) {
+        self.record(Event::Instruction {
+            pointer,
+            base,
+            instruction: *instruction,
+        });
+    }
+
+    fn custom(&mut self, pointer: usize, base: i64, opcode: &Opcode) {
+        self.record(Event::Custom {
+            pointer,
+            base,
+            mnemonic: opcode.mnemonic().to_string(),
+        });
+    }
+
+    fn read(&mut self, address: usize, value: i64) {
+        self.record(Event::Read { address, value });
+    }
+
+    fn write(&mut self, address: usize, old: i64, new: i64) {
+        self.record(Event::Write { address, old, new });
+    }
+
+    fn input(&mut self, value: i64) {
+        self.record(Event::Input(value));
+    }
+
+    fn output(&mut self, value: i64) {
+        self.record(Event::Output(value));
+    }
+}
+
+/// The first point at which two traces differ
+#[derive(Debug, PartialEq)]
+pub struct Divergence {
+    /// the line number (from 1) where the traces first differ
+    pub line: usize,
+
+    /// the lines from each trace, or None if that trace had already ended
+    pub left: Option<String>,
+    pub right: Option<String>,
+}
+
+/// Compares two written traces line by line, returning where they first differ
+pub fn diff<A: BufRead, B: BufRead>(left: A, right: B) -> io::Result<Option<Divergence>> {
+    let mut left = left.lines();
+    let mut right = right.lines();
+    let mut line = 0;
+
+    loop {
+        line += 1;
+
+        let l = left.next().transpose()?;
+        let r = right.next().transpose()?;
+
+        if l.is_none() && r.is_none() {
+            return Ok(None);
+        }
+
+        if l != r {
+            return Ok(Some(Divergence {
+                line,
+                left: l,
+                right: r,
+            }));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::assembler::assemble;
+    use crate::intcode::{IntCodeEmulator, YieldReason};
+
+    const DOUBLER: &str = "
+                in [value]
+                mul [value], #2, [value]
+                out [value]
+                hlt
+        value:  data 0
+    ";
+
+    fn trace(input: i64) -> Vec<u8> {
+        let mut vm = IntCodeEmulator::new(assemble(DOUBLER).unwrap());
+        vm.stdin().push_back(input);
+
+        let mut writer = TraceWriter::new(Vec::new());
+        vm.execute_with(&mut writer).unwrap();
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn records_events() {
+        let mut vm = IntCodeEmulator::new(assemble(DOUBLER).unwrap());
+        vm.stdin().push_back(21);
+
+        let mut recorder = Recorder::default();
+        vm.execute_with(&mut recorder).unwrap();
+
+        let events: Vec<String> = recorder.events.iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            events,
+            vec![
+                "0000 rb=0 in [9]",
+                "  input 21",
+                "  write [9] = 21 (was 0)",
+                "0002 rb=0 mul [9], #2, [9]",
+                "  read [9] = 21",
+                "  write [9] = 42 (was 21)",
+                "0006 rb=0 out [9]",
+                "  read [9] = 42",
+                "  output 42",
+                "0008 rb=0 hlt",
+            ]
+        );
+    }
+
+    #[test]
+    fn records_input_once_it_arrives() {
+        let mut vm = IntCodeEmulator::new(assemble(DOUBLER).unwrap());
+        let mut recorder = Recorder::default();
+
+        let result = vm.execute_until_yield_with(&mut recorder).unwrap();
+        assert_eq!(result, YieldReason::InputRequired);
+        assert!(recorder.events.is_empty());
+
+        vm.stdin().push_back(21);
+        vm.execute_with(&mut recorder).unwrap();
+
+        let events: Vec<String> = recorder.events.iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            &events[..3],
+            &["0000 rb=0 in [9]", "  input 21", "  write [9] = 21 (was 0)"]
+        );
+        assert_eq!(events.len(), 10);
+    }
+
+    #[test]
+    fn diffs_traces() {
+        assert_eq!(diff(&trace(3)[..], &trace(3)[..]).unwrap(), None);
+
+        let divergence = diff(&trace(3)[..], &trace(4)[..]).unwrap().unwrap();
+        assert_eq!(divergence.line, 2);
+        assert_eq!(divergence.left, Some("  input 3".to_string()));
+        assert_eq!(divergence.right, Some("  input 4".to_string()));
+    }
+}
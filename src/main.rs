@@ -11,10 +11,223 @@ mod day10;
 mod day11;
 
 mod compass;
-mod intcode;
 mod points;
 
+use advent2019::intcode;
+
+use intcode::debugger::Debugger;
+use intcode::decompiler;
+use intcode::fuzz;
+use intcode::graph;
+use intcode::optimiser;
+use intcode::profiler::Profiler;
+use intcode::terminal::Terminal;
+use intcode::IntCodeEmulator;
+use std::env;
+use std::fs;
+use std::io;
+use std::process;
+
+const USAGE: &str = "\
+usage: advent2019                                solve every day
+       advent2019 debug <program> [inputs...]   debug an IntCode program interactively
+       advent2019 decompile <program>           print an IntCode program as structured pseudocode
+       advent2019 fuzz [cases] [seed]           compare the IntCode engines on random programs
+       advent2019 graph <program>               print the control flow of an IntCode program as DOT
+       advent2019 optimise <program> [--verify <recordings>]
+                                                fold constants and clear dead code in an IntCode program
+       advent2019 profile <program> [--json] [inputs...]
+                                                count where an IntCode program spends its time
+       advent2019 play <program> [--script <file>] [--transcript <file>]
+                                                play an ASCII IntCode program interactively";
+
 pub fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    match args.first().map(String::as_str) {
+        None => solve_all(),
+        Some("debug") => debug(&args[1..]),
+        Some("decompile") => print_decompiled(&args[1..]),
+        Some("fuzz") => run_fuzzer(&args[1..]),
+        Some("graph") => print_graph(&args[1..]),
+        Some("optimise") => optimise(&args[1..]),
+        Some("play") => play(&args[1..]),
+        Some("profile") => profile(&args[1..]),
+        Some(_) => {
+            eprintln!("{}", USAGE);
+            process::exit(1);
+        }
+    }
+}
+
+/// loads an IntCode program from the given file, exiting if it can't be read
+fn load_program(path: Option<&String>) -> IntCodeEmulator {
+    let path = path.unwrap_or_else(|| {
+        eprintln!("{}", USAGE);
+        process::exit(1);
+    });
+
+    let input = fs::read_to_string(path).unwrap_or_else(|e| {
+        eprintln!("Unable to read {}: {}", path, e);
+        process::exit(1);
+    });
+
+    IntCodeEmulator::from_input(&input)
+}
+
+fn debug(args: &[String]) {
+    let mut vm = load_program(args.first());
+
+    for arg in args.iter().skip(1) {
+        let value = arg.parse().expect("Inputs must be numbers");
+        vm.stdin().push_back(value);
+    }
+
+    let stdin = io::stdin();
+    let mut debugger = Debugger::new(vm);
+    debugger
+        .repl(stdin.lock(), io::stdout())
+        .expect("Unable to run debugger");
+}
+
+fn run_fuzzer(args: &[String]) {
+    let cases = args.first().map_or(10_000, |arg| {
+        arg.parse().expect("The number of cases must be a number")
+    });
+    let seed = args
+        .get(1)
+        .map_or(0, |arg| arg.parse().expect("The seed must be a number"));
+
+    match fuzz::fuzz(seed, cases, 10_000) {
+        Ok(()) => println!("{} cases matched", cases),
+        Err(mismatch) => {
+            eprintln!("{}", mismatch);
+            process::exit(1);
+        }
+    }
+}
+
+fn print_decompiled(args: &[String]) {
+    let vm = load_program(args.first());
+    let program = vm.ram().to_vec();
+    print!("{}", decompiler::decompile(&program));
+}
+
+fn print_graph(args: &[String]) {
+    let vm = load_program(args.first());
+    let program = vm.ram().to_vec();
+    print!("{}", graph::analyse(&program).to_dot());
+}
+
+fn optimise(args: &[String]) {
+    let program = load_program(args.first()).ram().to_vec();
+    let optimised = optimiser::optimise(&program);
+
+    match optimised.obstacle {
+        Some(obstacle) => eprintln!("left unchanged: {:?}", obstacle),
+        None => eprintln!(
+            "folded {} instructions and removed {} cells",
+            optimised.folded, optimised.removed
+        ),
+    }
+
+    // each line of the recordings is one run's inputs, separated by commas
+    if let Some(option) = args.get(1) {
+        let path = args
+            .get(2)
+            .filter(|_| option == "--verify")
+            .unwrap_or_else(|| {
+                eprintln!("{}", USAGE);
+                process::exit(1);
+            });
+
+        let recordings: Vec<Vec<i64>> = fs::read_to_string(path)
+            .unwrap_or_else(|e| {
+                eprintln!("Unable to read {}: {}", path, e);
+                process::exit(1);
+            })
+            .lines()
+            .map(|line| match line.trim() {
+                "" => Vec::new(),
+                line => IntCodeEmulator::parse_input(line),
+            })
+            .collect();
+
+        if let Err(divergence) =
+            optimiser::verify(&program, &optimised.program, &recordings, 10_000_000)
+        {
+            eprintln!("{}", divergence);
+            process::exit(1);
+        }
+        eprintln!("{} recordings matched", recordings.len());
+    }
+
+    let cells: Vec<String> = optimised.program.iter().map(|v| v.to_string()).collect();
+    println!("{}", cells.join(","));
+}
+
+fn profile(args: &[String]) {
+    let mut vm = load_program(args.first());
+    let mut json = false;
+
+    for arg in args.iter().skip(1) {
+        if arg == "--json" {
+            json = true;
+            continue;
+        }
+
+        let value = arg.parse().expect("Inputs must be numbers");
+        vm.stdin().push_back(value);
+    }
+
+    let mut profiler = Profiler::new();
+    if let Err(e) = vm.execute_with(&mut profiler) {
+        eprintln!("{}", e);
+    }
+
+    if json {
+        println!("{}", profiler.to_json());
+    } else {
+        let outputs: Vec<String> = vm.stdout().iter().map(|v| v.to_string()).collect();
+        println!("output           {}", outputs.join(","));
+        print!("{}", profiler.report(20));
+    }
+}
+
+fn play(args: &[String]) {
+    let mut terminal = Terminal::new(load_program(args.first()));
+    let mut options = args.iter().skip(1);
+
+    while let Some(option) = options.next() {
+        let path = options.next().unwrap_or_else(|| {
+            eprintln!("{}", USAGE);
+            process::exit(1);
+        });
+
+        let result = match option.as_str() {
+            "--script" => terminal.load_script(path),
+            "--transcript" => fs::File::create(path).map(|file| {
+                terminal.set_transcript(Box::new(io::BufWriter::new(file)));
+            }),
+            _ => {
+                eprintln!("{}", USAGE);
+                process::exit(1);
+            }
+        };
+
+        result.unwrap_or_else(|e| {
+            eprintln!("Unable to open {}: {}", path, e);
+            process::exit(1);
+        });
+    }
+
+    let stdin = io::stdin();
+    terminal
+        .play(stdin.lock(), io::stdout())
+        .expect("Unable to run terminal");
+}
+
+fn solve_all() {
     println!("Day 01 - Part 1 - {}", day01::part1());
     println!("Day 01 - Part 2 - {}", day01::part2());
 